@@ -0,0 +1,139 @@
+//! Pathfinding benchmarks on a large generated map.
+//!
+//! Run with `cargo bench --bench path`. Without `--bench`, as when the
+//! benchmarks are merely built and run as a test, a small map is used so
+//! that they finish at once.
+
+#![expect(clippy::print_stdout, reason = "benchmarks report their timings")]
+
+use gamik::game::path::{Bounds, DijkstraMap, PathCache, PathGrid, find_path};
+use gamik::game::{Blueprint, EntityMap, GameRng, GameState, Point, spawn, spawn_player, teleport};
+
+use std::hint::black_box;
+use std::time::Instant;
+
+/// Side of the map when benchmarking for real, in tiles.
+const LARGE: i32 = 512;
+
+/// Side of the map otherwise.
+const SMALL: i32 = 48;
+
+/// Creatures sharing one Dijkstra map in the many-agent benchmark.
+const AGENTS: usize = 1000;
+
+fn main() {
+    let side = if std::env::args().any(|arg| arg == "--bench") {
+        LARGE
+    } else {
+        SMALL
+    };
+    let corner = Point { x: 0, y: 0 };
+    let far_corner = Point {
+        x: side - 1,
+        y: side - 1,
+    };
+    let center = Point {
+        x: side / 2,
+        y: side / 2,
+    };
+    let bounds = Bounds {
+        min: corner,
+        max: far_corner,
+    };
+    let mut state = generate(side, &[corner, far_corner, center]);
+    let entities = &state.entities;
+    println!("{side}×{side} map, {} entities", entities.position().len());
+
+    let grid = time("build grid", || PathGrid::new(entities));
+    time("A* corner to corner", || {
+        find_path(&grid, corner, far_corner)
+    });
+    time("Dijkstra approach map", || {
+        DijkstraMap::approach(&grid, &[center], bounds)
+    });
+    let flee = time("Dijkstra flee map", || {
+        DijkstraMap::flee(&grid, &[center], bounds)
+    });
+
+    let mut rng = GameRng::new(1);
+    let agents: Vec<Point> = (0..AGENTS).map(|_| random_point(&mut rng, side)).collect();
+    time("next step for every agent", || {
+        agents
+            .iter()
+            .filter_map(|&at| flee.next_step(&grid, at))
+            .count()
+    });
+
+    // A player wandering about does not shape paths, so every lookup after
+    // the first is answered from the cache
+    let player = spawn_player(&mut state, "bench".into());
+    let mut cache = PathCache::default();
+    cache.find_path(&state.entities, corner, far_corner);
+    let mut step = 0;
+    time("cached A* corner to corner", || {
+        step += 1;
+        teleport(&mut state, player, Point { x: step % 2, y: 0 });
+        cache.find_path(&state.entities, corner, far_corner)
+    });
+}
+
+/// Run `f` a few times, print how long it took at best, and return what it
+/// returned the last time.
+fn time<T>(name: &str, mut f: impl FnMut() -> T) -> T {
+    let start = Instant::now();
+    let mut result = black_box(f());
+    let mut best = start.elapsed();
+    for _ in 1..5 {
+        let start = Instant::now();
+        result = black_box(f());
+        best = best.min(start.elapsed());
+    }
+    println!("{name:<28} {best:>12.3?}");
+    result
+}
+
+/// A `side` by `side` field strewn with walls and mud, the same every time,
+/// with the tiles around each of `clear` left open.
+fn generate(side: i32, clear: &[Point]) -> GameState {
+    let mut state = GameState::create_test_world("bench".into());
+    state.entities = EntityMap::default();
+    let mut rng = GameRng::from_text("bench");
+    let mud = state
+        .templates
+        .get("mud")
+        .cloned()
+        .expect("mud is built in");
+    for y in 0..side {
+        for x in 0..side {
+            let at = Point { x, y };
+            if clear.iter().any(|&open| at.chebyshev_distance(open) < 2) {
+                continue;
+            }
+            match rng.below(100) {
+                0..25 => {
+                    spawn(
+                        &mut state,
+                        Blueprint {
+                            position: Some(at),
+                            blocks_movement: true,
+                            ..Blueprint::default()
+                        },
+                    );
+                }
+                25..35 => {
+                    spawn(&mut state, mud.clone().at(at));
+                }
+                _ => {}
+            }
+        }
+    }
+    state
+}
+
+fn random_point(rng: &mut GameRng, side: i32) -> Point {
+    let side = side.unsigned_abs();
+    Point {
+        x: rng.below(side).try_into().unwrap_or_default(),
+        y: rng.below(side).try_into().unwrap_or_default(),
+    }
+}
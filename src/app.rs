@@ -1,20 +1,60 @@
 //! Application shell — wires game, UI, and networking together.
 
-use crate::game::{self, Direction, EntityID, GameAction, GameState, Point};
-use crate::net::{Message, ServerMessage, run_client_internal, run_server_internal};
+use crate::game::anatomy::{Anatomy, Modification, ModificationKind};
+use crate::game::{
+    self, ChatChannel, EntityID, EntityMap, Facing, GameAction, GameState, Point, Turn, carry,
+    item, vision,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::net::discovery::{DiscoveredServer, browse_lan};
+use crate::net::queue::{self, OverflowPolicy, QueueReceiver, QueueSender};
+use crate::net::stats::NetStats;
+use crate::net::ticket::{ServerTicket, decode_hex, encode_hex};
+use crate::net::{
+    ChatLine, ClientEvent, Hello, PROTOCOL_VERSION, ServerMessage, Welcome, client_queues,
+    run_client_internal, run_server_internal,
+};
 use crate::ui;
 
 use egui::{FontId, RichText};
 use iroh::EndpointAddr;
 use iroh::EndpointId;
+use iroh::SecretKey;
 use iroh::protocol::Router;
 use std::fs;
 use std::path::PathBuf;
-use tokio::sync::mpsc;
+use std::time::Duration;
+use tokio::sync::oneshot;
 
 // Toggle this constant to enable/disable test mode
 const TEST_MODE: bool = true;
 
+/// Storage key under which the client's identity is persisted.
+const SECRET_KEY_STORAGE_KEY: &str = "gamik_secret_key";
+/// Storage key for the newline-separated tickets of recently joined servers.
+const RECENT_SERVERS_STORAGE_KEY: &str = "gamik_recent_servers";
+const MAX_RECENT_SERVERS: usize = 8;
+/// Lines kept in the chat log before the oldest are dropped.
+const MAX_CHAT_LOG: usize = 200;
+/// How long a server that handed its world off keeps running, so that every
+/// client gets to read the redirect before the connection closes.
+const HANDOFF_GRACE: Duration = Duration::from_secs(5);
+/// The keys that move a character each way: WASD and QEZC, the arrow keys,
+/// the numpad and the vi-keys.
+const MOVEMENT_KEYS: [(Facing, &[egui::Key]); 8] = {
+    use egui::Key;
+    [
+        (Facing::North, &[Key::W, Key::ArrowUp, Key::Num8, Key::K]),
+        (Facing::South, &[Key::S, Key::ArrowDown, Key::Num2, Key::J]),
+        (Facing::West, &[Key::A, Key::ArrowLeft, Key::Num4, Key::H]),
+        (Facing::East, &[Key::D, Key::ArrowRight, Key::Num6, Key::L]),
+        (Facing::NorthWest, &[Key::Q, Key::Num7, Key::Y]),
+        (Facing::NorthEast, &[Key::E, Key::Num9, Key::U]),
+        (Facing::SouthWest, &[Key::Z, Key::Num1, Key::B]),
+        (Facing::SouthEast, &[Key::C, Key::Num3, Key::N]),
+    ]
+};
+
 /// Which screen the application is currently showing.
 #[derive(Debug, Clone, PartialEq)]
 enum AppScreen {
@@ -23,20 +63,85 @@ enum AppScreen {
     WorldCreation,
     CharacterSelection,
     WorldSelection,
+    JoinGame,
     Playing,
+    /// Waiting for a dropped connection to come back.
+    Reconnecting,
+    /// Our character died; says how.
+    Dead(String),
+}
+
+/// A line in the chat log: a chat message or a server notice.
+struct LogLine {
+    color: egui::Color32,
+    text: String,
 }
 
 pub struct GamikApp {
     player_id: EntityID,
     button_size: Option<f32>,
     menu_input_string: String,
+    /// Name announced to servers in our [`Hello`].
+    player_name: String,
+    /// Why the last connection attempt failed, shown on the main menu.
+    menu_error: Option<String>,
+    /// Long-lived identity; servers tie our characters to its public key.
+    secret_key: SecretKey,
+    /// Characters the server says our account may play.
+    owned_characters: Vec<EntityID>,
+    /// Why the ticket on the join screen could not be used.
+    join_error: Option<String>,
+    /// Tickets of servers we joined successfully, most recent first.
+    recent_servers: Vec<String>,
+    /// Ticket we are connecting with, remembered once the server welcomes us.
+    pending_ticket: Option<String>,
+    /// Ticket for the server we are hosting, shown in the lobby panel.
+    host_ticket: Option<String>,
+    /// Updates from the LAN browser task, started on the main menu.
+    #[cfg(not(target_arch = "wasm32"))]
+    lan_rx: Option<QueueReceiver<Vec<DiscoveredServer>>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    lan_servers: Vec<DiscoveredServer>,
 
     game: GameState,
     font_size: f32,
     router: Option<Router>,
     // Networking state
-    server_to_client_rx: Option<mpsc::UnboundedReceiver<Message>>,
-    client_to_server_tx: Option<mpsc::UnboundedSender<GameAction>>,
+    server_to_client_rx: Option<QueueReceiver<ClientEvent>>,
+    client_to_server_tx: Option<QueueSender<GameAction>>,
+    welcome: Option<Welcome>,
+    /// Screen to return to once a dropped connection is re-established.
+    resume_screen: AppScreen,
+    /// Progress message shown while reconnecting.
+    reconnect_status: String,
+    /// Chat messages and server notices (players joining, leaving, ...).
+    chat_log: Vec<LogLine>,
+    chat_input: String,
+    /// Join servers as a spectator instead of playing a character.
+    spectating: bool,
+    /// Where a spectator is looking; players always follow their character.
+    camera: ui::Camera,
+    /// Mouse drag not yet large enough to pan the camera by a whole tile.
+    drag_remainder: egui::Vec2,
+    /// Latest connection statistics, shown in the F3 overlay.
+    net_stats: Option<NetStats>,
+    show_net_overlay: bool,
+    /// Whether the inventory window, toggled with I, is open.
+    show_inventory: bool,
+    /// Creature whose body the inspection window shows; our own is toggled
+    /// with X, others are picked by clicking them.
+    inspecting: Option<EntityID>,
+    /// What the next tattoo or piercing looks like.
+    modification_input: String,
+    /// Whether the next click on the map shoots or throws at it; toggled
+    /// with F.
+    aiming: bool,
+    /// Whether the movement keys walk forwards and backwards and turn,
+    /// rather than step north, south, west and east; toggled with T.
+    tank_controls: bool,
+    /// Whether the map shows only what our character sees ahead of it;
+    /// toggled with V.
+    forward_vision: bool,
     screen: AppScreen,
     single_player: bool,
 
@@ -48,6 +153,18 @@ impl Default for GamikApp {
     fn default() -> Self {
         Self {
             menu_input_string: String::new(),
+            player_name: "Player".to_owned(),
+            menu_error: None,
+            secret_key: SecretKey::generate(&mut rand::rng()),
+            owned_characters: Vec::new(),
+            join_error: None,
+            recent_servers: Vec::new(),
+            pending_ticket: None,
+            host_ticket: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            lan_rx: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            lan_servers: Vec::new(),
             router: None,
             screen: if TEST_MODE {
                 AppScreen::Playing
@@ -60,6 +177,22 @@ impl Default for GamikApp {
             font_size: 14.0,
             server_to_client_rx: None,
             client_to_server_tx: None,
+            welcome: None,
+            resume_screen: AppScreen::MainMenu,
+            reconnect_status: String::new(),
+            chat_log: Vec::new(),
+            chat_input: String::new(),
+            spectating: false,
+            camera: ui::Camera::default(),
+            drag_remainder: egui::Vec2::ZERO,
+            net_stats: None,
+            show_net_overlay: false,
+            show_inventory: false,
+            inspecting: None,
+            modification_input: String::new(),
+            aiming: false,
+            tank_controls: false,
+            forward_vision: false,
             single_player: true,
             test_mode_initialized: false,
         }
@@ -115,45 +248,157 @@ impl GamikApp {
         // Apply the fonts to the context
         cc.egui_ctx.set_fonts(fonts);
 
-        Self::default()
+        let mut app = Self::default();
+
+        // Reuse the identity from previous sessions so servers recognise us
+        if let Some(secret_key) = cc
+            .storage
+            .and_then(|storage| storage.get_string(SECRET_KEY_STORAGE_KEY))
+            .and_then(|hex| secret_key_from_hex(&hex))
+        {
+            app.secret_key = secret_key;
+        }
+
+        if let Some(recent) = cc
+            .storage
+            .and_then(|storage| storage.get_string(RECENT_SERVERS_STORAGE_KEY))
+        {
+            app.recent_servers = recent.lines().map(str::to_owned).collect();
+        }
+
+        app
     }
 
     fn start_client<A>(&mut self, addr: A)
     where
         A: Into<EndpointAddr> + Clone,
     {
-        let (msg_tx, msg_rx) = mpsc::unbounded_channel();
-        let (event_tx, event_rx) = mpsc::unbounded_channel();
+        let ((msg_tx, msg_rx), (event_tx, event_rx)) = client_queues();
 
         let s_addr = addr.clone().into();
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: self.player_name.clone(),
+            spectator: self.spectating,
+        };
 
         self.server_to_client_rx = Some(msg_rx);
         self.client_to_server_tx = Some(event_tx);
+        self.menu_error = None;
+
+        let secret_key = self.secret_key.clone();
 
         tokio::spawn(async move {
-            let _ = run_client_internal(s_addr, msg_tx, event_rx).await;
+            // The player already heard why through the event queue
+            if let Err(e) = run_client_internal(s_addr, secret_key, hello, msg_tx, event_rx).await {
+                log::warn!("Client stopped: {e}");
+            }
         });
     }
 
+    /// Connect to the server described by `input`, which is either a
+    /// [`ServerTicket`] or a bare endpoint ID.
+    ///
+    /// On failure the reason is shown on the join screen.
+    fn join_server(&mut self, input: &str) {
+        match input.trim().parse::<ServerTicket>() {
+            Ok(ticket) => {
+                self.pending_ticket = Some(ticket.to_string());
+                self.start_client(ticket.addr);
+            }
+            Err(ticket_error) => {
+                let Ok(endpoint_id) = input.trim().parse::<EndpointId>() else {
+                    self.join_error = Some(ticket_error.to_string());
+                    return;
+                };
+                self.start_client(endpoint_id);
+            }
+        }
+
+        self.single_player = false;
+        self.join_error = None;
+        self.menu_input_string.clear();
+        if self.spectating {
+            self.camera = ui::Camera::default();
+            self.screen = AppScreen::Playing;
+        } else {
+            self.screen = AppScreen::CharacterSelection;
+        }
+    }
+
+    /// Start looking for servers on the local network, if not already.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_lan_browser(&mut self) {
+        if self.lan_rx.is_some() {
+            return;
+        }
+
+        // Only the latest list matters
+        let (lan_tx, lan_rx) = queue::channel(1, OverflowPolicy::DropOldest);
+        self.lan_rx = Some(lan_rx);
+
+        tokio::spawn(async move {
+            if let Err(e) = browse_lan(lan_tx).await {
+                log::warn!("LAN browsing unavailable: {e}");
+            }
+        });
+    }
+
+    /// Take the latest LAN server list from the browser task.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll_lan(&mut self) {
+        let Some(rx) = &mut self.lan_rx else {
+            return;
+        };
+        while let Some(servers) = rx.try_recv() {
+            self.lan_servers = servers;
+        }
+    }
+
+    /// Put `ticket` at the top of the recently joined servers.
+    fn remember_server(&mut self, ticket: String) {
+        self.recent_servers.retain(|t| *t != ticket);
+        self.recent_servers.insert(0, ticket);
+        self.recent_servers.truncate(MAX_RECENT_SERVERS);
+    }
+
     fn start_server(&mut self, game: GameState) {
-        let (router_tx, mut router_rx) = mpsc::unbounded_channel();
+        let (router_tx, mut router_rx) = oneshot::channel();
+        let world_name = game.world_name.clone();
+        // Our own client connects with this key, making us the host
+        let host = self.secret_key.public();
 
         // Spawn an async task to start the server
         tokio::spawn(async move {
-            match run_server_internal(game).await {
-                Ok(router) => {
-                    // Send the router back to the main thread
-                    let _ = router_tx.send(router);
+            // Send the router, or why there is none, back to the main thread
+            let started = run_server_internal(game, Some(host))
+                .await
+                .map_err(|e| e.to_string());
+            if let Err(Ok(router)) = router_tx.send(started) {
+                // Nobody is waiting for the server any more
+                if let Err(e) = router.shutdown().await {
+                    log::warn!("The unwanted server did not stop cleanly: {e}");
                 }
-                Err(e) => eprintln!("Server error: {e}"),
             }
         });
 
         while self.router.is_none() {
-            if let Ok(router) = router_rx.try_recv() {
-                self.router = Some(router);
+            match router_rx.try_recv() {
+                Ok(Ok(router)) => self.router = Some(router),
+                Ok(Err(e)) => {
+                    log::warn!("Server error: {e}");
+                    self.menu_error = Some(format!("Could not start the server: {e}"));
+                    return;
+                }
+                Err(oneshot::error::TryRecvError::Empty) => {}
+                Err(oneshot::error::TryRecvError::Closed) => return,
             }
         }
+
+        if let Some(router) = &self.router {
+            let ticket = ServerTicket::new(router.endpoint().addr(), world_name);
+            self.host_ticket = Some(ticket.to_string());
+        }
     }
 
     fn initialize_test_mode(&mut self) {
@@ -161,15 +406,25 @@ impl GamikApp {
             return;
         }
 
+        self.test_mode_initialized = true;
+
         // Create or load a test world
-        let test_world = get_world_files()
-            .first()
-            .and_then(|path| game::load_from_file(path).ok())
-            .unwrap_or_else(|| {
-                let world = GameState::create_test_world("test_world".into());
-                let _ = game::save_to_file(&world);
-                world
-            });
+        let test_world = if let Some(path) = get_world_files().first() {
+            match game::load_from_file(path) {
+                Ok(world) => world,
+                Err(e) => {
+                    self.menu_error = Some(format!("{}: {e}", path.display()));
+                    return;
+                }
+            }
+        } else {
+            let world = GameState::create_test_world("test_world".into());
+            if let Err(e) = game::save_to_file(&world) {
+                // Still playable; the next run just makes another one
+                log::warn!("Could not save the test world: {e}");
+            }
+            world
+        };
 
         // Start server (blocking)
         self.start_server(test_world);
@@ -181,15 +436,17 @@ impl GamikApp {
         }
 
         // Spawn test player
-        if let Some(tx) = &self.client_to_server_tx {
-            let _ = tx.send(GameAction::SpawnPlayer("TestPlayer".to_string()));
-        }
-
-        self.test_mode_initialized = true;
+        self.send_action(GameAction::SpawnPlayer("TestPlayer".to_owned()));
     }
 }
 
 impl eframe::App for GamikApp {
+    /// Called by the framework to persist state before shutdown.
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(SECRET_KEY_STORAGE_KEY, secret_key_to_hex(&self.secret_key));
+        storage.set_string(RECENT_SERVERS_STORAGE_KEY, self.recent_servers.join("\n"));
+    }
+
     /// Called each time the UI needs repainting, which may be many times per second.
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Initialize test mode once
@@ -199,10 +456,19 @@ impl eframe::App for GamikApp {
 
         // Poll network → update local game state copy
         self.poll_network();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.poll_lan();
 
         // Request continuous repainting to keep UI responsive
         ctx.request_repaint();
 
+        if ctx.input(|i| i.key_pressed(egui::Key::F3)) {
+            self.show_net_overlay = !self.show_net_overlay;
+        }
+        if self.show_net_overlay {
+            self.net_overlay(ctx);
+        }
+
         match self.screen {
             AppScreen::MainMenu => {
                 self.show_main_menu(ctx);
@@ -219,6 +485,15 @@ impl eframe::App for GamikApp {
             AppScreen::WorldSelection => {
                 self.show_world_selection_menu(ctx);
             }
+            AppScreen::JoinGame => {
+                self.show_join_menu(ctx);
+            }
+            AppScreen::Reconnecting => {
+                self.show_reconnecting_screen(ctx);
+            }
+            AppScreen::Dead(_) => {
+                self.show_death_screen(ctx);
+            }
             AppScreen::Playing => {
                 // Collect input → game actions
                 self.input(ctx);
@@ -240,16 +515,249 @@ impl GamikApp {
         let Some(rx) = &mut self.server_to_client_rx else {
             return;
         };
-        while let Ok(msg) = rx.try_recv() {
-            if let Message::Server(smsg) = msg {
-                match smsg {
-                    ServerMessage::EntityMap(emap) => {
-                        self.game.entities = emap;
+        let events: Vec<ClientEvent> = std::iter::from_fn(|| rx.try_recv()).collect();
+        let mut lost = None;
+        for event in events {
+            match event {
+                ClientEvent::Server(ServerMessage::EntityMap(emap)) => {
+                    self.game.entities = *emap;
+                }
+                ClientEvent::Server(ServerMessage::PlayerID(pid)) => self.player_id = pid,
+                ClientEvent::Server(ServerMessage::Welcome(welcome)) => {
+                    self.game.world_name.clone_from(&welcome.world_name);
+                    self.welcome = Some(welcome);
+                    if let Some(ticket) = self.pending_ticket.take() {
+                        self.remember_server(ticket);
+                    }
+                    if self.screen == AppScreen::Reconnecting {
+                        self.screen = self.resume_screen.clone();
                     }
-                    ServerMessage::PlayerID(pid) => self.player_id = pid,
                 }
+                ClientEvent::Server(ServerMessage::Rejected(reason)) => {
+                    lost = Some(reason.to_string());
+                }
+                ClientEvent::Server(ServerMessage::OwnedCharacters(owned)) => {
+                    self.owned_characters = owned;
+                }
+                ClientEvent::Server(ServerMessage::ActionRejected { action, reason }) => {
+                    if matches!(action, GameAction::SpawnAs(_)) {
+                        self.screen = AppScreen::CharacterSelection;
+                    }
+                    self.menu_error = Some(reason.to_string());
+                }
+                ClientEvent::Server(ServerMessage::PlayerJoined(name)) => {
+                    self.push_log(egui::Color32::GRAY, format!("{name} joined the game."));
+                }
+                ClientEvent::Server(ServerMessage::PlayerLeft(name)) => {
+                    self.push_log(egui::Color32::GRAY, format!("{name} left the game."));
+                }
+                ClientEvent::Server(ServerMessage::Notice(notice)) => {
+                    self.push_log(egui::Color32::GOLD, notice);
+                }
+                ClientEvent::Server(ServerMessage::CommandResult(Ok(output))) => {
+                    self.push_log(egui::Color32::LIGHT_GRAY, output);
+                }
+                ClientEvent::Server(ServerMessage::CommandResult(Err(e))) => {
+                    self.push_log(egui::Color32::LIGHT_RED, e.to_string());
+                }
+                ClientEvent::Server(ServerMessage::Chat(line)) => {
+                    let (color, text) = format_chat(&line);
+                    self.push_log(color, text);
+                }
+                ClientEvent::Server(ServerMessage::Stats(server)) => {
+                    if let Some(stats) = &mut self.net_stats {
+                        stats.server = Some(server);
+                    }
+                }
+                ClientEvent::Server(ServerMessage::Died(cause)) => {
+                    self.show_inventory = false;
+                    self.inspecting = None;
+                    self.screen = AppScreen::Dead(cause);
+                }
+                ClientEvent::Server(ServerMessage::Handoff(world)) => self.take_over(*world),
+                ClientEvent::Server(ServerMessage::Redirect(ticket)) => {
+                    self.follow_redirect(ticket);
+                }
+                ClientEvent::Stats(stats) => self.net_stats = Some(stats),
+                ClientEvent::Reconnecting { attempt, reason } => {
+                    if self.screen != AppScreen::Reconnecting {
+                        self.resume_screen = self.screen.clone();
+                        self.screen = AppScreen::Reconnecting;
+                    }
+                    self.reconnect_status = format!("{reason} (attempt {attempt})");
+                }
+                ClientEvent::Server(ServerMessage::Kicked(reason))
+                | ClientEvent::Disconnected(reason) => lost = Some(reason),
             }
         }
+
+        if let Some(reason) = lost {
+            self.disconnect(reason);
+        }
+    }
+
+    /// Host `world`, handed to us by the player who hosted it so far, and
+    /// tell their server where everybody should go.
+    fn take_over(&mut self, world: GameState) {
+        let world_name = world.world_name.clone();
+        if self.router.is_some() {
+            self.push_log(
+                egui::Color32::GOLD,
+                format!("You were offered {world_name}, but you are hosting already."),
+            );
+            self.decline_handoff();
+            return;
+        }
+        self.start_server(world);
+        if self.router.is_none() {
+            self.decline_handoff();
+            return;
+        }
+        if let Some(ticket) = self.host_ticket.clone() {
+            self.send_action(GameAction::HandoffReady(ticket));
+        }
+        self.push_log(
+            egui::Color32::GOLD,
+            format!("You are now hosting {world_name}."),
+        );
+    }
+
+    /// Tell the old host that we will not take its world over, so that it
+    /// carries on with it.
+    fn decline_handoff(&mut self) {
+        self.send_action(GameAction::HandoffDeclined);
+    }
+
+    /// The world moved to the server at `ticket`. The client task reconnects
+    /// by itself; if we were the old host, stop our server once everybody
+    /// has had time to leave.
+    fn follow_redirect(&mut self, ticket: String) {
+        if self.host_ticket.as_ref() == Some(&ticket) {
+            // We are the new host, reconnecting to ourselves
+            return;
+        }
+        self.host_ticket = None;
+        if let Some(router) = self.router.take() {
+            tokio::spawn(async move {
+                tokio::time::sleep(HANDOFF_GRACE).await;
+                if let Err(e) = router.shutdown().await {
+                    log::warn!("The old server did not stop cleanly: {e}");
+                }
+            });
+        }
+        self.push_log(egui::Color32::GOLD, "Moving to the new host…".to_owned());
+        self.remember_server(ticket);
+    }
+
+    /// Send `action` to the server. If it cannot be, says so in the chat log
+    /// while playing, or on the menu otherwise. Returns whether it was sent.
+    fn send_action(&mut self, action: GameAction) -> bool {
+        let Some(tx) = &self.client_to_server_tx else {
+            return false;
+        };
+        let Err(e) = tx.send(action) else {
+            return true;
+        };
+        let error = format!("Could not send that to the server: {e}.");
+        if self.screen == AppScreen::Playing {
+            self.push_log(egui::Color32::LIGHT_RED, error);
+        } else {
+            self.menu_error = Some(error);
+        }
+        false
+    }
+
+    /// Append a line to the chat log, keeping only the most recent ones.
+    fn push_log(&mut self, color: egui::Color32, text: String) {
+        self.chat_log.push(LogLine { color, text });
+        if self.chat_log.len() > MAX_CHAT_LOG {
+            self.chat_log.remove(0);
+        }
+    }
+
+    /// Send whatever is typed in the chat box.
+    fn send_chat(&mut self) {
+        let input = std::mem::take(&mut self.chat_input);
+        if input.trim().is_empty() {
+            return;
+        }
+        match parse_chat_input(&input, &self.game.entities) {
+            Ok(action) => {
+                self.send_action(action);
+            }
+            Err(e) => self.push_log(egui::Color32::LIGHT_RED, e),
+        }
+    }
+
+    /// Drop the client connection and return to the main menu showing `reason`.
+    fn disconnect(&mut self, reason: String) {
+        self.server_to_client_rx = None;
+        self.client_to_server_tx = None;
+        self.welcome = None;
+        self.owned_characters.clear();
+        self.chat_log.clear();
+        self.chat_input.clear();
+        self.pending_ticket = None;
+        self.net_stats = None;
+        self.menu_error = Some(reason);
+        self.screen = AppScreen::MainMenu;
+    }
+
+    fn show_reconnecting_screen(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(50.0);
+
+                ui.heading("Connection lost, reconnecting…");
+                ui.add_space(20.0);
+                ui.spinner();
+                ui.add_space(10.0);
+                ui.label(&self.reconnect_status);
+
+                ui.add_space(30.0);
+
+                if ui.button(RichText::new("Give Up").size(16.0)).clicked() {
+                    self.disconnect("Gave up reconnecting to the server.".to_owned());
+                }
+            });
+        });
+    }
+
+    fn show_death_screen(&mut self, ctx: &egui::Context) {
+        let AppScreen::Dead(cause) = &self.screen else {
+            return;
+        };
+        let cause = cause.clone();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(50.0);
+
+                ui.heading(RichText::new("You died").color(egui::Color32::LIGHT_RED));
+                ui.add_space(10.0);
+                ui.label(cause);
+                ui.add_space(30.0);
+
+                if ui
+                    .button(RichText::new("Create New Character").size(20.0))
+                    .clicked()
+                {
+                    self.screen = AppScreen::CharacterCreation;
+                }
+                if !self.owned_characters.is_empty()
+                    && ui
+                        .button(RichText::new("Play Another Character").size(16.0))
+                        .clicked()
+                {
+                    self.screen = AppScreen::CharacterSelection;
+                }
+
+                ui.add_space(20.0);
+
+                if ui.button(RichText::new("Leave").size(16.0)).clicked() {
+                    self.disconnect("You left the game after dying.".to_owned());
+                }
+            });
+        });
     }
 
     fn show_main_menu(&mut self, ctx: &egui::Context) {
@@ -259,7 +767,17 @@ impl GamikApp {
 
                 ui.heading(RichText::new("Roguelike Game").size(32.0));
 
-                ui.add_space(50.0);
+                if let Some(error) = &self.menu_error {
+                    ui.add_space(10.0);
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+
+                ui.add_space(40.0);
+
+                ui.label("Your name:");
+                ui.text_edit_singleline(&mut self.player_name);
+
+                ui.add_space(20.0);
 
                 if ui.button(RichText::new("Start Game").size(20.0)).clicked() {
                     self.single_player = true;
@@ -272,24 +790,123 @@ impl GamikApp {
                     .button(RichText::new("Join Online Game").size(20.0))
                     .clicked()
                 {
-                    self.single_player = false;
-                    // Parse the endpoint address
-                    match self.menu_input_string.parse::<EndpointId>() {
-                        Ok(addr) => {
-                            self.start_client(addr);
-                            self.menu_input_string.clear();
-                            self.screen = AppScreen::CharacterSelection;
+                    self.join_error = None;
+                    self.screen = AppScreen::JoinGame;
+                }
+
+                ui.add_space(10.0);
+                ui.checkbox(&mut self.spectating, "Watch as a spectator");
+
+                ui.add_space(20.0);
+
+                #[cfg(not(target_arch = "wasm32"))]
+                self.show_lan_games(ui);
+            });
+        });
+    }
+
+    /// The servers found on the local network, each a button to join it.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn show_lan_games(&mut self, ui: &mut egui::Ui) {
+        self.start_lan_browser();
+        ui.label(RichText::new("LAN Games:").size(16.0));
+        ui.add_space(10.0);
+
+        if self.lan_servers.is_empty() {
+            ui.label("Searching the local network…");
+        } else {
+            let mut chosen = None;
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for found in &self.lan_servers {
+                        let server = &found.server;
+                        let mut label = format!(
+                            "{} — hosted by {} — {} players — {} ms",
+                            server.world_name,
+                            server.host_name,
+                            server.player_count,
+                            found.ping.as_millis()
+                        );
+                        let compatible = server.protocol_version == PROTOCOL_VERSION;
+                        if !compatible {
+                            label.push_str(" (incompatible version)");
                         }
-                        Err(_) => {
-                            self.screen = AppScreen::MainMenu;
+                        if ui
+                            .add_enabled(
+                                compatible,
+                                egui::Button::new(RichText::new(label).size(16.0)),
+                            )
+                            .clicked()
+                        {
+                            chosen = Some(server.ticket.clone());
                         }
                     }
-                }
+                });
+            if let Some(ticket) = chosen {
+                self.join_server(&ticket);
+            }
+        }
+    }
+
+    fn show_join_menu(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.vertical_centered(|ui| {
+                ui.add_space(50.0);
 
+                ui.heading("Join Online Game");
                 ui.add_space(20.0);
 
-                ui.label("Enter Server ID:");
+                ui.label("Paste the ticket the host shared with you:");
+                ui.add_space(5.0);
                 ui.text_edit_singleline(&mut self.menu_input_string);
+
+                if let Some(error) = &self.join_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+
+                ui.add_space(10.0);
+
+                ui.checkbox(&mut self.spectating, "Watch as a spectator");
+                ui.add_space(10.0);
+
+                if ui.button(RichText::new("Join").size(20.0)).clicked() {
+                    let input = self.menu_input_string.clone();
+                    self.join_server(&input);
+                }
+
+                ui.add_space(30.0);
+
+                if !self.recent_servers.is_empty() {
+                    ui.label(RichText::new("Recent Servers:").size(16.0));
+                    ui.add_space(10.0);
+
+                    let mut chosen = None;
+                    egui::ScrollArea::vertical()
+                        .max_height(200.0)
+                        .show(ui, |ui| {
+                            for ticket in &self.recent_servers {
+                                let label = ticket.parse::<ServerTicket>().map_or_else(
+                                    |_| "Unknown server".to_owned(),
+                                    |t| format!("{} ({})", t.world_name, t.addr.id.fmt_short()),
+                                );
+                                if ui.button(RichText::new(label).size(18.0)).clicked() {
+                                    chosen = Some(ticket.clone());
+                                }
+                            }
+                        });
+                    if let Some(ticket) = chosen {
+                        self.join_server(&ticket);
+                    }
+                }
+
+                ui.add_space(20.0);
+
+                // Back button
+                if ui.button(RichText::new("Back").size(16.0)).clicked() {
+                    self.join_error = None;
+                    self.screen = AppScreen::MainMenu;
+                }
             });
         });
     }
@@ -300,6 +917,12 @@ impl GamikApp {
                 ui.add_space(50.0);
 
                 ui.heading("World Selection");
+
+                if let Some(error) = &self.menu_error {
+                    ui.add_space(10.0);
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
+
                 ui.add_space(20.0);
 
                 // Create New World button
@@ -329,13 +952,19 @@ impl GamikApp {
                                     if let Some(name) = filename.to_str() {
                                         if ui.button(RichText::new(name).size(18.0)).clicked() {
                                             // Load the world here
-                                            if let Ok(world) = game::load_from_file(&world_path) {
-                                                self.start_server(world);
-
-                                                let eid =
-                                                    self.router.as_ref().unwrap().endpoint().addr();
-                                                self.start_client(eid);
-                                                self.screen = AppScreen::CharacterSelection;
+                                            match game::load_from_file(&world_path) {
+                                                Ok(world) => {
+                                                    self.start_server(world);
+
+                                                    if let Some(router) = &self.router {
+                                                        let eid = router.endpoint().addr();
+                                                        self.start_client(eid);
+                                                        self.screen = AppScreen::CharacterSelection;
+                                                    }
+                                                }
+                                                Err(e) => {
+                                                    self.menu_error = Some(format!("{name}: {e}"));
+                                                }
                                             }
                                         }
                                     }
@@ -360,6 +989,15 @@ impl GamikApp {
                 ui.add_space(50.0);
 
                 ui.heading("Character Selection");
+                if let Some(welcome) = &self.welcome {
+                    ui.label(format!(
+                        "World: {} ({} players online)",
+                        welcome.world_name, welcome.player_count
+                    ));
+                }
+                if let Some(error) = &self.menu_error {
+                    ui.colored_label(egui::Color32::LIGHT_RED, error);
+                }
                 ui.add_space(20.0);
 
                 // Create New World button
@@ -372,7 +1010,7 @@ impl GamikApp {
 
                 ui.add_space(30.0);
 
-                let playables = self.game.get_playable_entities();
+                let playables = self.owned_characters.clone();
 
                 if playables.is_empty() {
                     ui.label("No existing characters found");
@@ -384,17 +1022,15 @@ impl GamikApp {
                         .max_height(300.0)
                         .show(ui, |ui| {
                             for playable in playables {
-                                if ui
-                                    .button(RichText::new(format!("{playable:#?}")).size(18.0))
-                                    .clicked()
+                                let label = self.game.entities.name_of(playable).map_or_else(
+                                    || format!("Character {}", playable.0),
+                                    str::to_owned,
+                                );
+                                if ui.button(RichText::new(label).size(18.0)).clicked()
+                                    && self.send_action(GameAction::SpawnAs(playable))
                                 {
-                                    if let Some(tx) = &self.client_to_server_tx {
-                                        if let Err(e) = tx.send(GameAction::SpawnAs(playable)) {
-                                            eprintln!("Failed to send game event: {e}");
-                                        } else {
-                                            self.screen = AppScreen::Playing;
-                                        }
-                                    }
+                                    self.menu_error = None;
+                                    self.screen = AppScreen::Playing;
                                 }
                             }
                         });
@@ -488,12 +1124,8 @@ impl GamikApp {
                         self.menu_input_string.trim().to_string()
                     };
                     self.menu_input_string.clear();
-                    if let Some(tx) = &self.client_to_server_tx {
-                        if let Err(e) = tx.send(GameAction::SpawnPlayer(char_name)) {
-                            eprintln!("Failed to send game event: {e}");
-                        } else {
-                            self.screen = AppScreen::CharacterSelection;
-                        }
+                    if self.send_action(GameAction::SpawnPlayer(char_name)) {
+                        self.screen = AppScreen::CharacterSelection;
                     }
                 }
 
@@ -512,37 +1144,330 @@ impl GamikApp {
     // -----------------------------------------------------------------------
 
     pub fn input(&mut self, ctx: &egui::Context) {
+        // Keys typed into the chat box are not movement.
+        if ctx.memory(|m| m.focused().is_some()) {
+            return;
+        }
+        if self.spectating {
+            self.spectator_input(ctx);
+            return;
+        }
+
         let mut messages_to_send = Vec::new();
+        let entities = &self.game.entities;
+        let me = self.player_id;
 
         ctx.input(|i| {
-            if i.key_pressed(egui::Key::W) || i.key_pressed(egui::Key::ArrowUp) {
-                messages_to_send.push(GameAction::Move(Direction::Up));
-            }
-
-            if i.key_pressed(egui::Key::S) || i.key_pressed(egui::Key::ArrowDown) {
-                messages_to_send.push(GameAction::Move(Direction::Down));
+            for (direction, keys) in MOVEMENT_KEYS {
+                if !keys.iter().any(|key| i.key_pressed(*key)) {
+                    continue;
+                }
+                if self.tank_controls {
+                    messages_to_send.extend(tank_action(entities, me, direction));
+                } else {
+                    messages_to_send.push(step_or_attack(entities, me, direction));
+                }
             }
-            if i.key_pressed(egui::Key::A) || i.key_pressed(egui::Key::ArrowLeft) {
-                messages_to_send.push(GameAction::Move(Direction::Left));
+            if i.key_pressed(egui::Key::T) {
+                self.tank_controls = !self.tank_controls;
             }
-            if i.key_pressed(egui::Key::D) || i.key_pressed(egui::Key::ArrowRight) {
-                messages_to_send.push(GameAction::Move(Direction::Right));
+            if i.key_pressed(egui::Key::V) {
+                self.forward_vision = !self.forward_vision;
             }
             if i.key_pressed(egui::Key::R) {
                 messages_to_send.push(GameAction::SaveWorld);
             }
+            if i.key_pressed(egui::Key::I) {
+                self.show_inventory = !self.show_inventory;
+            }
+            if i.key_pressed(egui::Key::X) {
+                self.inspecting = if self.inspecting == Some(me) {
+                    None
+                } else {
+                    Some(me)
+                };
+            }
+            if i.key_pressed(egui::Key::F) {
+                self.aiming = !self.aiming;
+            }
+            if i.key_pressed(egui::Key::G) {
+                // Pick up whatever lies on our tile
+                if let Some(item) = entities
+                    .position()
+                    .get(&me)
+                    .and_then(|at| entities.items_at(*at).first().copied())
+                {
+                    messages_to_send.push(GameAction::PickUp(item));
+                }
+            }
         });
         // Send all the collected messages
-        if let Some(tx) = &self.client_to_server_tx {
-            for event in messages_to_send {
-                if let Err(e) = tx.send(event) {
-                    eprintln!("Failed to send game event: {e}");
+        for event in messages_to_send {
+            if !self.send_action(event) {
+                break;
+            }
+        }
+    }
+
+    /// Spectators pan the camera with the movement keys and press F to
+    /// follow the next player.
+    fn spectator_input(&mut self, ctx: &egui::Context) {
+        let (dx, dy, follow_next) = ctx.input(|i| {
+            let pressed = |a, b| i32::from(i.key_pressed(a) || i.key_pressed(b));
+            let dx = pressed(egui::Key::D, egui::Key::ArrowRight)
+                - pressed(egui::Key::A, egui::Key::ArrowLeft);
+            let dy = pressed(egui::Key::S, egui::Key::ArrowDown)
+                - pressed(egui::Key::W, egui::Key::ArrowUp);
+            (dx, dy, i.key_pressed(egui::Key::F))
+        });
+
+        if dx != 0 || dy != 0 {
+            self.camera.pan(&self.game.entities, dx, dy);
+        }
+        if follow_next {
+            self.camera.follow_next(&self.game.entities);
+        }
+    }
+
+    /// Pan a spectator's camera while the map is dragged with the mouse.
+    fn drag_camera(&mut self, ui: &egui::Ui, tile_size: f32) {
+        let delta = ui.input(|i| {
+            if i.pointer.primary_down() {
+                i.pointer.delta()
+            } else {
+                egui::Vec2::ZERO
+            }
+        });
+        self.drag_remainder += delta;
+
+        // Dragging the map right moves the view left
+        let scaled = self.drag_remainder / tile_size;
+        let tiles = egui::vec2(scaled.x.trunc(), scaled.y.trunc());
+        if tiles != egui::Vec2::ZERO {
+            self.camera
+                .pan(&self.game.entities, -tiles.x as i32, -tiles.y as i32);
+            self.drag_remainder -= tiles * tile_size;
+        }
+    }
+
+    /// The chat log and input box, plus a status line for spectators.
+    fn chat_panel(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("chat").show(ctx, |ui| {
+            if self.spectating {
+                let following = match self.camera {
+                    ui::Camera::Follow(eid) => self
+                        .game
+                        .entities
+                        .name_of(eid)
+                        .map_or_else(|| "nobody".to_owned(), |name| format!("following {name}")),
+                    ui::Camera::Free(_) => "free camera".to_owned(),
+                };
+                ui.label(format!(
+                    "Spectating ({following}) — WASD or drag to pan, F to follow the next player"
+                ));
+            }
+            egui::ScrollArea::vertical()
+                .max_height(120.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for line in &self.chat_log {
+                        ui.colored_label(line.color, &line.text);
+                    }
+                });
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.chat_input)
+                    .hint_text("Say something... (/me, /w <name>, /g, /kick, ...)")
+                    .desired_width(f32::INFINITY),
+            );
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.send_chat();
+            }
+        });
+    }
+
+    /// What our character wears and carries, and what lies at its feet,
+    /// toggled with I. Carried items can be worn, dropped or moved between
+    /// containers.
+    fn inventory_window(&mut self, ctx: &egui::Context) {
+        let entities = &self.game.entities;
+        let me = self.player_id;
+        let mut actions = Vec::new();
+        let mut open = self.show_inventory;
+
+        egui::Window::new("Inventory")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if let Some(health) = entities.health.get(&me) {
+                    ui.label(format!("Health {} / {}", health.current, health.max));
                 }
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "Carrying {} of {} ({})",
+                        format_weight(carry::carried_weight(entities, me)),
+                        format_weight(carry::carry_limit(entities, me)),
+                        carry::encumbrance(entities, me).describe(),
+                    ));
+                    if ui.button("Organize").clicked() {
+                        actions.push(GameAction::Organize);
+                    }
+                });
+                ui.separator();
+                ui.strong("Worn");
+                if let Some(equipment) = entities.equipment.get(&me) {
+                    for (slot, &item) in &equipment.slots {
+                        ui.horizontal(|ui| {
+                            let slot = slot.name().replace('_', " ");
+                            ui.label(format!("{slot}: {}", item_name(entities, item)));
+                            if ui.small_button("Take off").clicked() {
+                                actions.push(GameAction::Unequip(item));
+                            }
+                            if ui.small_button("Drop").clicked() {
+                                actions.push(GameAction::Drop(item));
+                            }
+                        });
+                        item_rows(ui, entities, me, item, &mut actions);
+                    }
+                }
+                ui.separator();
+                ui.strong("Carried");
+                item_rows(ui, entities, me, me, &mut actions);
+                ui.separator();
+                ui.strong("Here (G to pick up)");
+                let here = entities
+                    .position()
+                    .get(&me)
+                    .map(|at| entities.items_at(*at))
+                    .unwrap_or_default();
+                for item in here {
+                    ui.horizontal(|ui| {
+                        ui.label(item_name(entities, item));
+                        if ui.small_button("Pick up").clicked() {
+                            actions.push(GameAction::PickUp(item));
+                        }
+                    });
+                }
+            });
+
+        self.show_inventory = open;
+        for action in actions {
+            if !self.send_action(action) {
+                break;
             }
         }
     }
 
+    /// The inspected creature's body: its parts, their wounds and how they
+    /// are decorated. Within reach, parts can be aimed at, tattooed and
+    /// pierced.
+    fn inspection_window(&mut self, ctx: &egui::Context) {
+        let Some(target) = self.inspecting else {
+            return;
+        };
+        let entities = &self.game.entities;
+        let Some(anatomy) = entities.anatomy.get(&target) else {
+            // Dead, or out of sight
+            self.inspecting = None;
+            return;
+        };
+        let me = self.player_id;
+        let within_reach = !self.spectating
+            && entities
+                .position()
+                .get(&me)
+                .zip(entities.position().get(&target))
+                .is_some_and(|(from, to)| from.chebyshev_distance(*to) <= 1);
+        let title = entities
+            .name_of(target)
+            .map_or_else(|| format!("Creature {}", target.0), str::to_owned);
+        let modification_input = &mut self.modification_input;
+        let mut actions = Vec::new();
+        let mut open = true;
+
+        egui::Window::new(format!("Inspect {title}"))
+            .id(egui::Id::new("inspection"))
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if let Some(health) = entities.health.get(&target) {
+                    ui.label(format!("Health {} / {}", health.current, health.max));
+                }
+                if within_reach {
+                    ui.horizontal(|ui| {
+                        ui.label("Design:");
+                        ui.text_edit_singleline(modification_input);
+                    });
+                }
+                ui.separator();
+                let controls = within_reach.then(|| PartControls {
+                    target,
+                    aim: target != me,
+                    design: modification_input.trim(),
+                });
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for index in 0..anatomy.parts.len() {
+                            part_rows(ui, anatomy, index, controls.as_ref(), &mut actions);
+                        }
+                    });
+            });
+
+        if !open {
+            self.inspecting = None;
+        }
+        for action in actions {
+            if !self.send_action(action) {
+                break;
+            }
+        }
+    }
+
+    /// Frame rate and connection statistics, toggled with F3.
+    fn net_overlay(&self, ctx: &egui::Context) {
+        let dt = ctx.input(|i| i.stable_dt);
+        egui::Area::new(egui::Id::new("net_overlay"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(format!("fps      {:.0}", 1.0 / dt.max(f32::EPSILON)));
+                    let Some(stats) = &self.net_stats else {
+                        ui.monospace("offline");
+                        return;
+                    };
+                    for line in net_stats_lines(stats) {
+                        ui.monospace(line);
+                    }
+                });
+            });
+    }
+
     fn rogue_screen(&mut self, ctx: &egui::Context) {
+        if let Some(ticket) = &self.host_ticket {
+            egui::Window::new("Lobby")
+                .default_open(false)
+                .show(ctx, |ui| {
+                    ui.label("Share this ticket so friends can join:");
+                    ui.add(egui::TextEdit::multiline(&mut ticket.as_str()).desired_rows(3));
+                    if ui.button("Copy").clicked() {
+                        ui.ctx().copy_text(ticket.clone());
+                    }
+                    ui.separator();
+                    ui.label(
+                        "Leaving ends the game for everyone. To keep it going, \
+                         hand the world to another player with /handoff <name>.",
+                    );
+                });
+        }
+
+        if self.client_to_server_tx.is_some() {
+            self.chat_panel(ctx);
+        }
+        if self.show_inventory && !self.spectating {
+            self.inventory_window(ctx);
+        }
+        self.inspection_window(ctx);
+        let mut clicked = None;
+
         egui::TopBottomPanel::top("lol").show(ctx, |ui| {
             // Customize button styling for tighter spacing
             let style = ui.style_mut();
@@ -575,11 +1500,13 @@ impl GamikApp {
             let rows = ((content.height() / button_size) as usize).max(1);
 
             // Camera centering
-            let center = self
-                .game
-                .entities
-                .get(&self.player_id)
-                .map_or(Point { x: 0, y: 0 }, |e| e.position);
+            let camera = if self.spectating {
+                self.drag_camera(ui, button_size);
+                self.camera
+            } else {
+                ui::Camera::Follow(self.player_id)
+            };
+            let center = camera.center(&self.game.entities);
 
             let cam_x = center.x - (cols as i32 / 2);
             let cam_y = center.y - (rows as i32 / 2);
@@ -588,6 +1515,8 @@ impl GamikApp {
 
             // Build spatial index once per frame for O(1) lookups
             let index = ui::build_spatial_index(&self.game.entities);
+            let seen = (self.forward_vision && !self.spectating)
+                .then(|| vision::visible_tiles(&self.game.entities, self.player_id, true));
 
             ui.centered_and_justified(|ui| {
                 ui.vertical_centered(|ui| {
@@ -599,10 +1528,14 @@ impl GamikApp {
                                     y: row as i32 + cam_y,
                                 };
 
-                                let glyph = ui::glyph_at(&index, &point);
+                                let glyph = if seen.as_ref().is_none_or(|s| s.contains(&point)) {
+                                    ui::glyph_at(&self.game.entities, &index, &point)
+                                } else {
+                                    ui::UNSEEN
+                                };
 
                                 let button = egui::Button::new(
-                                    RichText::new(glyph.character)
+                                    RichText::new(glyph.character.to_string())
                                         .color(glyph.fg_color)
                                         .font(FontId::proportional(
                                             self.font_size / glyph.size_mod,
@@ -611,16 +1544,342 @@ impl GamikApp {
                                 .min_size(egui::vec2(button_size, button_size))
                                 .corner_radius(0.0)
                                 .fill(glyph.bg_color);
-                                ui.add(button);
+                                if ui.add(button).clicked() {
+                                    clicked = Some(point);
+                                }
                             }
                         });
                     }
                 });
             });
         });
+
+        if let Some(point) = clicked {
+            self.map_clicked(point);
+        }
+    }
+
+    /// Fire at the tile clicked while aiming, or else inspect the creature
+    /// standing there.
+    fn map_clicked(&mut self, point: Point) {
+        if self.aiming && !self.spectating {
+            self.aiming = false;
+            self.send_action(GameAction::Fire { target: point });
+        } else if let Some(creature) = self.game.entities.creature_at(point) {
+            self.inspecting = Some(creature);
+        }
     }
 }
 
+/// What pressing a movement key does: attack whatever can be hurt in the
+/// way, or else step there.
+fn step_or_attack(entities: &EntityMap, me: EntityID, direction: Facing) -> GameAction {
+    creature_towards(entities, me, direction.delta())
+        .map_or(GameAction::step(direction), GameAction::Attack)
+}
+
+/// What pressing a movement key does with tank controls: up walks forwards,
+/// attacking whatever can be hurt ahead, down backs away, left and right
+/// turn, and the diagonals do nothing.
+fn tank_action(entities: &EntityMap, me: EntityID, direction: Facing) -> Option<GameAction> {
+    Some(match direction {
+        Facing::North => entities
+            .facing
+            .get(&me)
+            .and_then(|facing| creature_towards(entities, me, facing.delta()))
+            .map_or(GameAction::Walk { backwards: false }, GameAction::Attack),
+        Facing::South => GameAction::Walk { backwards: true },
+        Facing::West => GameAction::Turn(Turn::Left),
+        Facing::East => GameAction::Turn(Turn::Right),
+        Facing::NorthWest | Facing::NorthEast | Facing::SouthWest | Facing::SouthEast => {
+            return None;
+        }
+    })
+}
+
+/// Whatever can be hurt on the tile `(dx, dy)` away from `me`.
+fn creature_towards(entities: &EntityMap, me: EntityID, (dx, dy): (i32, i32)) -> Option<EntityID> {
+    let at = entities.position().get(&me)?;
+    entities.creature_at(Point {
+        x: at.x + dx,
+        y: at.y + dy,
+    })
+}
+
+/// What can be done to the parts of a body within reach.
+struct PartControls<'a> {
+    target: EntityID,
+    /// Whether blows can be aimed at them; not at our own.
+    aim: bool,
+    /// What tattoos and piercings look like; none are made while empty.
+    design: &'a str,
+}
+
+/// Rows for a part of `anatomy`, indented under the part it is attached to:
+/// how hurt it is, with `controls` if within reach, then its wounds and
+/// modifications.
+fn part_rows(
+    ui: &mut egui::Ui,
+    anatomy: &Anatomy,
+    index: usize,
+    controls: Option<&PartControls<'_>>,
+    actions: &mut Vec<GameAction>,
+) {
+    let Some(part) = anatomy.parts.get(index) else {
+        return;
+    };
+    let indent = 16.0 * anatomy.depth(index) as f32;
+    let lost = anatomy.is_destroyed(index);
+    ui.horizontal(|ui| {
+        ui.add_space(indent);
+        if lost {
+            ui.colored_label(egui::Color32::DARK_GRAY, format!("{} (lost)", part.name));
+            return;
+        }
+        let left = part.max_health.saturating_sub(part.damage());
+        let organ = if part.organ { " (organ)" } else { "" };
+        ui.label(format!("{}{organ} {left} / {}", part.name, part.max_health));
+        let Some(controls) = controls else {
+            return;
+        };
+        let target = controls.target;
+        if controls.aim && ui.small_button("Aim").clicked() {
+            actions.push(GameAction::AttackPart {
+                target,
+                part: index,
+            });
+        }
+        if controls.design.is_empty() {
+            return;
+        }
+        for (label, kind) in [
+            ("Tattoo", ModificationKind::Tattoo),
+            ("Pierce", ModificationKind::Piercing),
+        ] {
+            if ui.small_button(label).clicked() {
+                actions.push(GameAction::Modify {
+                    target,
+                    part: index,
+                    modification: Modification {
+                        kind,
+                        description: controls.design.to_owned(),
+                    },
+                });
+            }
+        }
+    });
+    for wound in &part.wounds {
+        ui.horizontal(|ui| {
+            ui.add_space(indent + 16.0);
+            ui.colored_label(
+                egui::Color32::LIGHT_RED,
+                format!("wound ({} to heal)", wound.damage),
+            );
+        });
+    }
+    for modification in &part.modifications {
+        ui.horizontal(|ui| {
+            ui.add_space(indent + 16.0);
+            ui.label(format!(
+                "{}: {}",
+                modification.kind.name(),
+                modification.description
+            ));
+        });
+    }
+}
+
+/// An item's name and weight, contents included.
+fn item_name(entities: &EntityMap, item: EntityID) -> String {
+    let name = entities
+        .name_of(item)
+        .map_or_else(|| format!("Item {}", item.0), str::to_owned);
+    format!(
+        "{name} ({})",
+        format_weight(carry::weight_of(entities, item))
+    )
+}
+
+/// One row per item in `holder`'s inventory, indented below it, with the
+/// contents of containers nested under them. `owner` is our character.
+fn item_rows(
+    ui: &mut egui::Ui,
+    entities: &EntityMap,
+    owner: EntityID,
+    holder: EntityID,
+    actions: &mut Vec<GameAction>,
+) {
+    let Some(inventory) = entities.inventory.get(&holder) else {
+        return;
+    };
+    ui.indent(("items", holder.0), |ui| {
+        for &item in &inventory.items {
+            ui.horizontal(|ui| {
+                ui.label(item_name(entities, item));
+                if entities.item.get(&item).is_some_and(|i| i.slot.is_some())
+                    && ui.small_button("Wear").clicked()
+                {
+                    actions.push(GameAction::Equip(item));
+                }
+                if ui.small_button("Drop").clicked() {
+                    actions.push(GameAction::Drop(item));
+                }
+                ui.menu_button("Put in", |ui| {
+                    let inside = item::possessions(entities, item);
+                    let containers = std::iter::once(owner)
+                        .chain(item::possessions(entities, owner))
+                        .filter(|c| entities.inventory.contains_key(c))
+                        .filter(|c| *c != item && *c != holder && !inside.contains(c));
+                    for container in containers {
+                        let label = if container == owner {
+                            "Pack".to_owned()
+                        } else {
+                            item_name(entities, container)
+                        };
+                        if ui.button(label).clicked() {
+                            actions.push(GameAction::Store { item, container });
+                        }
+                    }
+                });
+            });
+            item_rows(ui, entities, owner, item, actions);
+        }
+    });
+}
+
+/// Turn a line typed into the chat box into the action it asks for.
+///
+/// `/me <text>` emotes, `/w <name> <text>` whispers to the player called
+/// `name`, `/g <text>` speaks on the global channel, any other slash command
+/// is sent to the server as an admin command, and anything else is said out
+/// loud to nearby players.
+///
+/// # Errors
+///
+/// Returns a message for the chat log if a whisper is malformed or names an
+/// unknown player.
+fn parse_chat_input(input: &str, entities: &EntityMap) -> Result<GameAction, String> {
+    let input = input.trim();
+    if let Some(text) = input.strip_prefix("/me ") {
+        return Ok(GameAction::Emote(text.trim().to_owned()));
+    }
+    if let Some(text) = input.strip_prefix("/g ") {
+        return Ok(GameAction::SayGlobal(text.trim().to_owned()));
+    }
+    if let Some(rest) = input.strip_prefix("/w ") {
+        let Some((name, text)) = rest.trim().split_once(' ') else {
+            return Err("Usage: /w <name> <message>".to_owned());
+        };
+        let target = entities
+            .player_named(name)
+            .ok_or_else(|| format!("No player called {name}."))?;
+        return Ok(GameAction::Whisper {
+            target,
+            text: text.trim().to_owned(),
+        });
+    }
+    if input.starts_with('/') {
+        return Ok(GameAction::Command(input.to_owned()));
+    }
+    Ok(GameAction::Say(input.to_owned()))
+}
+
+/// How a chat line is shown in the log.
+fn format_chat(line: &ChatLine) -> (egui::Color32, String) {
+    let ChatLine {
+        from,
+        channel,
+        text,
+        ..
+    } = line;
+    match channel {
+        ChatChannel::Local => (egui::Color32::WHITE, format!("{from}: {text}")),
+        ChatChannel::Emote => (egui::Color32::LIGHT_YELLOW, format!("* {from} {text}")),
+        ChatChannel::Whisper(_) => (
+            egui::Color32::LIGHT_BLUE,
+            format!("{from} whispers: {text}"),
+        ),
+        ChatChannel::Global => (
+            egui::Color32::LIGHT_GREEN,
+            format!("[Global] {from}: {text}"),
+        ),
+    }
+}
+
+/// Encode a secret key as lowercase hex for [`eframe::Storage`].
+/// The connection half of the F3 overlay, one line per figure.
+fn net_stats_lines(stats: &NetStats) -> Vec<String> {
+    let traffic = &stats.traffic;
+    let mut lines = vec![
+        format!("rtt      {} ms", stats.rtt.as_millis()),
+        format!(
+            "in       {}/s, {} msg/s",
+            format_bytes(traffic.bytes_received_per_sec),
+            traffic.messages_received_per_sec
+        ),
+        format!(
+            "out      {}/s, {} msg/s",
+            format_bytes(traffic.bytes_sent_per_sec),
+            traffic.messages_sent_per_sec
+        ),
+        format!("queue    {}", stats.queue_depth),
+        format!("rejected {}", stats.rejected_actions),
+        format!(
+            "dropped  {} unreadable, {} actions",
+            stats.dropped_messages, stats.dropped_actions
+        ),
+        format!("skipped  {} updates", stats.skipped_updates),
+    ];
+    if let Some(server) = &stats.server {
+        lines.push(format!(
+            "tick     {} ({:.2} ms)",
+            server.tick,
+            server.tick_duration_micros as f64 / 1000.0
+        ));
+        lines.push(format!("server   queue {}", server.queue_depth));
+        lines.push(format!(
+            "server   {} rejected, {} unreadable",
+            server.rejected_actions, server.dropped_actions
+        ));
+        lines.push(format!(
+            "server   {} updates skipped",
+            server.skipped_updates
+        ));
+    }
+    lines
+}
+
+/// A byte count in B, KiB or MiB, whichever reads best.
+fn format_bytes(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    if bytes >= MIB {
+        format!("{:.1} MiB", bytes as f64 / MIB as f64)
+    } else if bytes >= KIB {
+        format!("{:.1} KiB", bytes as f64 / KIB as f64)
+    } else {
+        format!("{bytes} B")
+    }
+}
+
+fn format_weight(grams: u32) -> String {
+    if grams >= 1000 {
+        format!("{:.1} kg", f64::from(grams) / 1000.0)
+    } else {
+        format!("{grams} g")
+    }
+}
+
+fn secret_key_to_hex(key: &SecretKey) -> String {
+    encode_hex(&key.to_bytes())
+}
+
+/// Decode a secret key written by [`secret_key_to_hex`].
+fn secret_key_from_hex(hex: &str) -> Option<SecretKey> {
+    let bytes: [u8; 32] = decode_hex(hex)?.try_into().ok()?;
+    Some(SecretKey::from_bytes(&bytes))
+}
+
 /// Lists all available world files.
 pub fn get_world_files() -> Vec<PathBuf> {
     let worlds_dir = PathBuf::from("worlds");
@@ -639,3 +1898,141 @@ pub fn get_world_files() -> Vec<PathBuf> {
         .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("world"))
         .collect()
 }
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_with_bob() -> (GameState, EntityID) {
+        let mut game = GameState::create_test_world("test".into());
+        let bob = game::spawn_player(&mut game, "Bob".into());
+        (game, bob)
+    }
+
+    #[test]
+    fn plain_text_is_said_aloud() {
+        let (game, _) = world_with_bob();
+        assert_eq!(
+            parse_chat_input("  hello there ", &game.entities),
+            Ok(GameAction::Say("hello there".into()))
+        );
+    }
+
+    #[test]
+    fn chat_commands_pick_the_channel() {
+        let (game, bob) = world_with_bob();
+        assert_eq!(
+            parse_chat_input("/me waves", &game.entities),
+            Ok(GameAction::Emote("waves".into()))
+        );
+        assert_eq!(
+            parse_chat_input("/g anyone around?", &game.entities),
+            Ok(GameAction::SayGlobal("anyone around?".into()))
+        );
+        assert_eq!(
+            parse_chat_input("/w Bob psst", &game.entities),
+            Ok(GameAction::Whisper {
+                target: bob,
+                text: "psst".into()
+            })
+        );
+    }
+
+    #[test]
+    fn moving_into_a_creature_attacks_it() {
+        let (mut game, bob) = world_with_bob();
+        let sheep = game::spawn_template(&mut game, "sheep", Point { x: 11, y: 10 })
+            .expect("sheep is built in");
+        assert_eq!(
+            step_or_attack(&game.entities, bob, Facing::East),
+            GameAction::Attack(sheep)
+        );
+        assert_eq!(
+            step_or_attack(&game.entities, bob, Facing::West),
+            GameAction::Move(game::Direction::Left)
+        );
+        game::teleport(&mut game, bob, Point { x: 10, y: 11 });
+        assert_eq!(
+            step_or_attack(&game.entities, bob, Facing::NorthEast),
+            GameAction::Attack(sheep)
+        );
+        assert_eq!(
+            step_or_attack(&game.entities, bob, Facing::SouthWest),
+            GameAction::MoveDiagonal(game::Diagonal::DownLeft)
+        );
+    }
+
+    #[test]
+    fn tank_controls_walk_the_way_we_face() {
+        let (mut game, bob) = world_with_bob();
+        // Bob starts out facing south
+        let sheep = game::spawn_template(&mut game, "sheep", Point { x: 10, y: 11 })
+            .expect("sheep is built in");
+        assert_eq!(
+            tank_action(&game.entities, bob, Facing::North),
+            Some(GameAction::Attack(sheep))
+        );
+        assert_eq!(
+            tank_action(&game.entities, bob, Facing::South),
+            Some(GameAction::Walk { backwards: true })
+        );
+        assert_eq!(
+            tank_action(&game.entities, bob, Facing::West),
+            Some(GameAction::Turn(Turn::Left))
+        );
+        assert_eq!(tank_action(&game.entities, bob, Facing::NorthWest), None);
+        game::turn_entity(&mut game, bob, Turn::Left);
+        assert_eq!(
+            tank_action(&game.entities, bob, Facing::North),
+            Some(GameAction::Walk { backwards: false })
+        );
+    }
+
+    #[test]
+    fn whisper_to_unknown_player_is_an_error() {
+        let (game, _) = world_with_bob();
+        assert!(parse_chat_input("/w Carol hi", &game.entities).is_err());
+        assert!(parse_chat_input("/w Bob", &game.entities).is_err());
+    }
+
+    #[test]
+    fn other_slash_commands_go_to_the_server() {
+        let (game, _) = world_with_bob();
+        assert_eq!(
+            parse_chat_input("/kick Bob", &game.entities),
+            Ok(GameAction::Command("/kick Bob".into()))
+        );
+    }
+
+    #[test]
+    fn byte_counts_pick_a_readable_unit() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn weights_pick_a_readable_unit() {
+        assert_eq!(format_weight(150), "150 g");
+        assert_eq!(format_weight(1500), "1.5 kg");
+        assert_eq!(format_weight(30_000), "30.0 kg");
+    }
+
+    #[test]
+    fn server_figures_appear_once_reported() {
+        let mut stats = NetStats::default();
+        let offline = net_stats_lines(&stats).len();
+
+        stats.server = Some(crate::net::stats::ServerStats {
+            tick: 42,
+            ..Default::default()
+        });
+        let lines = net_stats_lines(&stats);
+        assert!(lines.len() > offline);
+        assert!(lines.iter().any(|l| l.contains("42")));
+    }
+}
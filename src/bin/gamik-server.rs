@@ -0,0 +1,19 @@
+//! Headless gamik server, administered by typing commands on stdin.
+//!
+//! Usage: `gamik-server [path/to/world.world]`. Without a path a fresh test
+//! world is created.
+
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::main]
+async fn main() -> n0_error::Result<()> {
+    use n0_error::StdResultExt as _;
+
+    let game = match std::env::args().nth(1) {
+        Some(path) => gamik::game::load_from_file(std::path::Path::new(&path)).anyerr()?,
+        None => gamik::game::GameState::create_test_world("Dedicated".to_owned()),
+    };
+    gamik::net::run_dedicated_server(game).await
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,518 @@
+//! Bodies: the parts creatures are made of, their wounds and how they are
+//! decorated.
+//!
+//! A creature with an [`Anatomy`] is a tree of [`BodyPart`]s, laid out by its
+//! species' template: limbs hang off the torso, organs lie inside the part
+//! that holds them. Parts carry Latin names, such as `"caput"` for the head
+//! or `"cor"` for the heart. Blows land on a part and leave a [`Wound`]
+//! there; a part whose wounds add up to its health is destroyed, along with
+//! everything attached to it, and losing a vital part kills. Wounds heal a
+//! little every [`HEAL_TICKS`] ticks of [`step`](super::step), and deep ones
+//! leave a scar.
+//!
+//! Scars, tattoos and piercings are [`Modification`]s of a part. They change
+//! nothing but how the creature looks when inspected.
+
+use super::{EntityID, GameEvent, GameState};
+
+use bitcode::{Decode, Encode};
+use std::fmt;
+
+/// Ticks a wound takes to heal by one point of damage.
+pub const HEAL_TICKS: u32 = 100;
+
+/// Wounds dealt at least this much damage leave a scar once healed.
+pub const SCAR_DEPTH: u32 = 3;
+
+/// Most modifications a single part can bear.
+pub const MAX_MODIFICATIONS: usize = 8;
+
+/// A creature's body, as its species' template lays it out.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Anatomy {
+    /// Every part comes after the part it is attached to.
+    pub parts: Vec<BodyPart>,
+}
+
+/// One part of a body, such as a limb or an organ.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct BodyPart {
+    /// Latin name, such as `"bracchium sinistrum"`.
+    pub name: String,
+    /// Index, in [`Anatomy::parts`], of the part this one is attached to;
+    /// `None` for the part everything else hangs off.
+    pub parent: Option<usize>,
+    /// Organs lie inside their parent and are only hit when aimed at.
+    pub organ: bool,
+    /// Losing a vital part kills the creature.
+    pub vital: bool,
+    /// Damage the part can take before it is destroyed.
+    pub max_health: u32,
+    /// How big a target the part makes; unaimed blows land on the biggest.
+    pub size: u32,
+    pub wounds: Vec<Wound>,
+    pub modifications: Vec<Modification>,
+}
+
+impl BodyPart {
+    /// An unharmed, undecorated part.
+    pub fn new(name: impl Into<String>, parent: Option<usize>, max_health: u32) -> Self {
+        Self {
+            name: name.into(),
+            parent,
+            organ: false,
+            vital: false,
+            max_health,
+            size: 1,
+            wounds: Vec::new(),
+            modifications: Vec::new(),
+        }
+    }
+
+    /// Damage dealt to the part and not yet healed.
+    pub fn damage(&self) -> u32 {
+        self.wounds
+            .iter()
+            .fold(0u32, |total, w| total.saturating_add(w.damage))
+    }
+}
+
+/// Damage done to a part by a single blow.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Wound {
+    /// Damage not yet healed.
+    pub damage: u32,
+    /// Damage the blow dealt; deep wounds leave a scar.
+    pub depth: u32,
+    /// Ticks spent healing since `damage` last went down.
+    pub healing: u32,
+}
+
+/// How a part has been marked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum ModificationKind {
+    /// Left behind by a deep wound.
+    Scar,
+    Tattoo,
+    Piercing,
+}
+
+impl ModificationKind {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Scar => "scar",
+            Self::Tattoo => "tattoo",
+            Self::Piercing => "piercing",
+        }
+    }
+}
+
+/// A mark on a part that changes only how it looks.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Modification {
+    pub kind: ModificationKind,
+    /// What it looks like, such as `"a coiled serpent"`.
+    pub description: String,
+}
+
+impl Anatomy {
+    /// Index of the part called `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.parts.iter().position(|p| p.name == name)
+    }
+
+    /// How many parts lie between `part` and the part everything hangs off.
+    pub fn depth(&self, part: usize) -> usize {
+        self.ancestors(part).count()
+    }
+
+    /// Whether `part`, or anything it is attached to, has been destroyed.
+    pub fn is_destroyed(&self, part: usize) -> bool {
+        std::iter::once(part)
+            .chain(self.ancestors(part))
+            .filter_map(|i| self.parts.get(i))
+            .any(|p| p.damage() >= p.max_health)
+    }
+
+    /// Whether a vital part has been destroyed.
+    pub fn is_fatally_hurt(&self) -> bool {
+        self.parts
+            .iter()
+            .enumerate()
+            .any(|(i, p)| p.vital && self.is_destroyed(i))
+    }
+
+    /// The part an unaimed blow lands on: the biggest one still there that
+    /// is not an organ.
+    pub fn target(&self) -> Option<usize> {
+        // Of parts the same size, the first one listed
+        self.parts
+            .iter()
+            .enumerate()
+            .filter(|(i, p)| !p.organ && !self.is_destroyed(*i))
+            .rev()
+            .max_by_key(|(_, p)| p.size)
+            .map(|(i, _)| i)
+    }
+
+    /// Wound `part` for `amount`. Returns whether that destroyed it.
+    pub fn wound(&mut self, part: usize, amount: u32) -> bool {
+        let was_destroyed = self.is_destroyed(part);
+        let Some(body_part) = self.parts.get_mut(part) else {
+            return false;
+        };
+        body_part.wounds.push(Wound {
+            damage: amount,
+            depth: amount,
+            healing: 0,
+        });
+        !was_destroyed && self.is_destroyed(part)
+    }
+
+    /// Parts `part` is attached to, nearest first.
+    fn ancestors(&self, part: usize) -> impl Iterator<Item = usize> + '_ {
+        // Parents come first, so this always ends, even in a corrupt save
+        std::iter::successors(self.parts.get(part).and_then(|p| p.parent), |&i| {
+            self.parts.get(i).and_then(|p| p.parent).filter(|&p| p < i)
+        })
+        .take(self.parts.len())
+    }
+}
+
+/// Why a part could not be modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnatomyError {
+    /// The target does not exist or has no body to speak of.
+    NoBody,
+    NoSuchPart,
+    OutOfReach,
+    /// Scars come from wounds, not from choice.
+    NotByHand,
+    NoRoom,
+}
+
+impl fmt::Display for AnatomyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoBody => write!(f, "There is nobody there."),
+            Self::NoSuchPart => write!(f, "That part is not there."),
+            Self::OutOfReach => write!(f, "That is out of reach."),
+            Self::NotByHand => write!(f, "Scars cannot be made by hand."),
+            Self::NoRoom => write!(f, "There is no room left there."),
+        }
+    }
+}
+
+impl std::error::Error for AnatomyError {}
+
+/// Tattoo or pierce `part` of `target`, which is `actor` or stands next to
+/// it.
+///
+/// # Errors
+///
+/// Returns why the part cannot be modified; nothing changes then.
+pub fn modify(
+    state: &mut GameState,
+    actor: EntityID,
+    target: EntityID,
+    part: usize,
+    modification: Modification,
+) -> Result<(), AnatomyError> {
+    if modification.kind == ModificationKind::Scar {
+        return Err(AnatomyError::NotByHand);
+    }
+    let entities = &state.entities;
+    if target != actor {
+        let (Some(from), Some(to)) = (
+            entities.position().get(&actor),
+            entities.position().get(&target),
+        ) else {
+            return Err(AnatomyError::OutOfReach);
+        };
+        if from.chebyshev_distance(*to) > 1 {
+            return Err(AnatomyError::OutOfReach);
+        }
+    }
+    let anatomy = state
+        .entities
+        .anatomy
+        .get_mut(&target)
+        .ok_or(AnatomyError::NoBody)?;
+    if anatomy.is_destroyed(part) {
+        return Err(AnatomyError::NoSuchPart);
+    }
+    let modifications = &mut anatomy
+        .parts
+        .get_mut(part)
+        .ok_or(AnatomyError::NoSuchPart)?
+        .modifications;
+    if modifications.len() >= MAX_MODIFICATIONS {
+        return Err(AnatomyError::NoRoom);
+    }
+    modifications.push(modification);
+    Ok(())
+}
+
+/// Let every wound heal for a tick, giving back the health it took.
+pub fn heal(state: &mut GameState) -> Vec<GameEvent> {
+    let entities = &mut state.entities;
+    let mut ids: Vec<EntityID> = entities.anatomy.keys().copied().collect();
+    ids.sort_unstable_by_key(|eid| eid.0);
+
+    let mut events = Vec::new();
+    for entity_id in ids {
+        let Some(anatomy) = entities.anatomy.get_mut(&entity_id) else {
+            continue;
+        };
+        let lost: Vec<bool> = (0..anatomy.parts.len())
+            .map(|i| anatomy.is_destroyed(i))
+            .collect();
+        let mut healed = 0u32;
+        for (part, lost) in anatomy.parts.iter_mut().zip(lost) {
+            // What is lost stays lost
+            if lost {
+                continue;
+            }
+            let mut scars = 0;
+            part.wounds.retain_mut(|wound| {
+                wound.healing += 1;
+                if wound.healing < HEAL_TICKS {
+                    return true;
+                }
+                wound.healing = 0;
+                wound.damage = wound.damage.saturating_sub(1);
+                healed += 1;
+                if wound.damage > 0 {
+                    return true;
+                }
+                if wound.depth >= SCAR_DEPTH {
+                    scars += 1;
+                }
+                events.push(GameEvent::WoundHealed {
+                    entity_id,
+                    part: part.name.clone(),
+                    scarred: wound.depth >= SCAR_DEPTH,
+                });
+                false
+            });
+            for _ in 0..scars {
+                if part.modifications.len() < MAX_MODIFICATIONS {
+                    part.modifications.push(Modification {
+                        kind: ModificationKind::Scar,
+                        description: "a scar".to_owned(),
+                    });
+                }
+            }
+        }
+        if let Some(health) = entities.health.get_mut(&entity_id) {
+            health.current = health.current.saturating_add(healed).min(health.max);
+        }
+    }
+    events
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Point, spawn_player, spawn_template, teleport};
+
+    /// A torso with a head, which holds a brain, and an arm, which holds a
+    /// hand.
+    fn body() -> Anatomy {
+        let mut thorax = BodyPart::new("thorax", None, 10);
+        thorax.vital = true;
+        thorax.size = 40;
+        let mut caput = BodyPart::new("caput", Some(0), 4);
+        caput.vital = true;
+        caput.size = 10;
+        let mut cerebrum = BodyPart::new("cerebrum", Some(1), 2);
+        cerebrum.organ = true;
+        cerebrum.vital = true;
+        let mut bracchium = BodyPart::new("bracchium", Some(0), 5);
+        bracchium.size = 15;
+        let manus = BodyPart::new("manus", Some(3), 3);
+        Anatomy {
+            parts: vec![thorax, caput, cerebrum, bracchium, manus],
+        }
+    }
+
+    fn part<'a>(anatomy: &'a Anatomy, name: &str) -> &'a BodyPart {
+        anatomy
+            .parts
+            .iter()
+            .find(|p| p.name == name)
+            .expect("the part exists")
+    }
+
+    fn tattoo(description: &str) -> Modification {
+        Modification {
+            kind: ModificationKind::Tattoo,
+            description: description.to_owned(),
+        }
+    }
+
+    #[test]
+    fn parts_are_found_by_name_and_know_their_depth() {
+        let body = body();
+        assert_eq!(body.find("manus"), Some(4));
+        assert_eq!(body.find("cauda"), None);
+        assert_eq!(body.depth(0), 0);
+        assert_eq!(body.depth(4), 2);
+    }
+
+    #[test]
+    fn destroying_a_part_takes_everything_attached_to_it() {
+        let mut body = body();
+        assert!(!body.wound(3, 4));
+        assert!(body.wound(3, 1));
+        assert!(body.is_destroyed(4));
+        assert!(!body.is_destroyed(0));
+        assert!(!body.is_fatally_hurt());
+        assert!(!body.wound(3, 1), "already destroyed");
+    }
+
+    #[test]
+    fn losing_a_vital_part_is_fatal() {
+        let mut body = body();
+        body.wound(2, 2);
+        assert!(body.is_fatally_hurt());
+    }
+
+    #[test]
+    fn unaimed_blows_land_on_the_biggest_part_left() {
+        let mut body = body();
+        assert_eq!(body.target(), Some(0));
+        if let Some(thorax) = body.parts.first_mut() {
+            thorax.size = 1;
+        }
+        assert_eq!(body.target(), Some(3));
+        body.wound(3, 5);
+        assert_eq!(body.target(), Some(1));
+    }
+
+    #[test]
+    fn wounds_heal_over_time_and_deep_ones_scar() {
+        let mut state = GameState::create_test_world("test".into());
+        let player = spawn_player(&mut state, "Alice".into());
+        state.entities.anatomy.insert(player, body());
+        let anatomy = state.entities.anatomy.get_mut(&player).expect("inserted");
+        anatomy.wound(0, SCAR_DEPTH);
+        anatomy.wound(3, 1);
+        state
+            .entities
+            .health
+            .get_mut(&player)
+            .expect("has health")
+            .current = 6;
+
+        let mut events = Vec::new();
+        for _ in 0..HEAL_TICKS {
+            events.extend(heal(&mut state));
+        }
+        assert_eq!(
+            events,
+            vec![GameEvent::WoundHealed {
+                entity_id: player,
+                part: "bracchium".into(),
+                scarred: false,
+            }]
+        );
+        assert_eq!(state.entities.health[&player].current, 8);
+
+        for _ in 0..HEAL_TICKS * (SCAR_DEPTH - 1) {
+            events.extend(heal(&mut state));
+        }
+        let anatomy = &state.entities.anatomy[&player];
+        assert_eq!(part(anatomy, "thorax").damage(), 0);
+        assert_eq!(
+            part(anatomy, "thorax").modifications,
+            vec![Modification {
+                kind: ModificationKind::Scar,
+                description: "a scar".into(),
+            }]
+        );
+        assert!(part(anatomy, "bracchium").modifications.is_empty());
+        assert_eq!(state.entities.health[&player].current, 10);
+    }
+
+    #[test]
+    fn lost_parts_do_not_heal() {
+        let mut state = GameState::create_test_world("test".into());
+        let player = spawn_player(&mut state, "Alice".into());
+        state.entities.anatomy.insert(player, body());
+        let anatomy = state.entities.anatomy.get_mut(&player).expect("inserted");
+        anatomy.wound(3, 5);
+        for _ in 0..HEAL_TICKS {
+            heal(&mut state);
+        }
+        assert!(state.entities.anatomy[&player].is_destroyed(3));
+    }
+
+    #[test]
+    fn parts_are_modified_within_reach() {
+        let mut state = GameState::create_test_world("test".into());
+        let alice = spawn_player(&mut state, "Alice".into());
+        let bob = spawn_player(&mut state, "Bob".into());
+        let tree = EntityID(1);
+        let name = "bracchium sinistrum";
+        let arm = state.entities.anatomy[&alice]
+            .find(name)
+            .expect("humans have arms");
+
+        assert_eq!(
+            modify(&mut state, alice, alice, arm, tattoo("a rose")),
+            Ok(())
+        );
+        assert_eq!(
+            modify(&mut state, alice, bob, arm, tattoo("a thorn")),
+            Ok(())
+        );
+        teleport(&mut state, bob, Point { x: 20, y: 20 });
+        assert_eq!(
+            modify(&mut state, alice, bob, arm, tattoo("a thorn")),
+            Err(AnatomyError::OutOfReach)
+        );
+        teleport(&mut state, alice, Point { x: 5, y: 6 });
+        assert_eq!(
+            modify(&mut state, alice, tree, 0, tattoo("a heart")),
+            Err(AnatomyError::NoBody)
+        );
+        assert_eq!(
+            modify(&mut state, alice, alice, 99, tattoo("a rose")),
+            Err(AnatomyError::NoSuchPart)
+        );
+        let scar = Modification {
+            kind: ModificationKind::Scar,
+            description: "a scar".into(),
+        };
+        assert_eq!(
+            modify(&mut state, alice, alice, arm, scar),
+            Err(AnatomyError::NotByHand)
+        );
+        assert_eq!(
+            part(&state.entities.anatomy[&alice], name).modifications,
+            vec![tattoo("a rose")]
+        );
+    }
+
+    #[test]
+    fn parts_hold_a_limited_number_of_modifications() {
+        let mut state = GameState::create_test_world("test".into());
+        let sheep =
+            spawn_template(&mut state, "sheep", Point { x: 0, y: 0 }).expect("sheep is built in");
+        for n in 0..MAX_MODIFICATIONS {
+            assert_eq!(
+                modify(&mut state, sheep, sheep, 0, tattoo(&n.to_string())),
+                Ok(())
+            );
+        }
+        assert_eq!(
+            modify(&mut state, sheep, sheep, 0, tattoo("one more")),
+            Err(AnatomyError::NoRoom)
+        );
+    }
+}
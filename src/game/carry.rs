@@ -0,0 +1,296 @@
+//! Weight, bulk and how much a character can carry.
+//!
+//! Every [`Item`](super::component::Item) has a weight and a volume.
+//! Containers hold as much volume as their capacity allows, while the weight
+//! of everything a character holds, containers and contents alike, counts
+//! against a limit that follows from the character's strength. Carrying more
+//! slows the character down, and far more stops it altogether.
+
+use super::{EntityID, EntityMap, GameState, item};
+
+/// Grams a character can carry, unhindered, per point of strength.
+pub const GRAMS_PER_STRENGTH: u32 = 3_000;
+
+/// How much a character's load hinders it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Encumbrance {
+    Unburdened,
+    /// Carrying more than the limit, but less than half as much again.
+    Burdened,
+    /// Carrying too much to move at all.
+    Overloaded,
+}
+
+impl Encumbrance {
+    /// Ticks a step takes, or `None` if the character cannot move.
+    pub const fn ticks_per_step(self) -> Option<u64> {
+        match self {
+            Self::Unburdened => Some(1),
+            Self::Burdened => Some(2),
+            Self::Overloaded => None,
+        }
+    }
+
+    pub const fn describe(self) -> &'static str {
+        match self {
+            Self::Unburdened => "unburdened",
+            Self::Burdened => "burdened",
+            Self::Overloaded => "overloaded",
+        }
+    }
+}
+
+/// Weight, in grams, of `entity_id` if it is an item, plus everything in it.
+pub fn weight_of(entities: &EntityMap, entity_id: EntityID) -> u32 {
+    let own = entities.item.get(&entity_id).map_or(0, |i| i.weight);
+    item::possessions(entities, entity_id)
+        .into_iter()
+        .filter_map(|eid| entities.item.get(&eid))
+        .fold(own, |total, i| total.saturating_add(i.weight))
+}
+
+/// Weight, in grams, of everything `actor` wears and carries.
+pub fn carried_weight(entities: &EntityMap, actor: EntityID) -> u32 {
+    weight_of(entities, actor) - entities.item.get(&actor).map_or(0, |i| i.weight)
+}
+
+/// Grams `actor` can carry unhindered; nothing without [`Stats`].
+///
+/// [`Stats`]: super::component::Stats
+pub fn carry_limit(entities: &EntityMap, actor: EntityID) -> u32 {
+    entities
+        .stats
+        .get(&actor)
+        .map_or(0, |s| s.strength.saturating_mul(GRAMS_PER_STRENGTH))
+}
+
+/// How much what `actor` carries hinders it.
+pub fn encumbrance(entities: &EntityMap, actor: EntityID) -> Encumbrance {
+    classify(
+        carried_weight(entities, actor),
+        carry_limit(entities, actor),
+    )
+}
+
+fn classify(carried: u32, limit: u32) -> Encumbrance {
+    let (carried, limit) = (u64::from(carried), u64::from(limit));
+    if carried <= limit {
+        Encumbrance::Unburdened
+    } else if carried * 2 <= limit * 3 {
+        Encumbrance::Burdened
+    } else {
+        Encumbrance::Overloaded
+    }
+}
+
+/// Millilitres still free in `container`, or `None` if it holds any amount.
+/// Containers inside it take up their own volume, whatever they hold.
+pub fn free_volume(entities: &EntityMap, container: EntityID) -> Option<u32> {
+    let inventory = entities.inventory.get(&container)?;
+    let capacity = inventory.capacity?;
+    let used = inventory
+        .items
+        .iter()
+        .filter_map(|eid| entities.item.get(eid))
+        .fold(0u32, |total, i| total.saturating_add(i.volume));
+    Some(capacity.saturating_sub(used))
+}
+
+/// Whether `item` fits in what is left of `container`.
+pub fn fits(entities: &EntityMap, item: EntityID, container: EntityID) -> bool {
+    let volume = entities.item.get(&item).map_or(0, |i| i.volume);
+    free_volume(entities, container).is_none_or(|free| volume <= free)
+}
+
+/// Where tidying `owner`'s inventory would put each item: which loose item
+/// goes into which carried container.
+///
+/// Loose items, containers excepted, are placed biggest first, each into the
+/// fullest container it still fits in, so that small things fill the gaps
+/// big ones leave. Items that fit nowhere stay loose.
+pub fn organize_plan(entities: &EntityMap, owner: EntityID) -> Vec<(EntityID, EntityID)> {
+    let Some(inventory) = entities.inventory.get(&owner) else {
+        return Vec::new();
+    };
+    let volume = |eid: &EntityID| entities.item.get(eid).map_or(0, |i| i.volume);
+
+    let mut loose: Vec<EntityID> = inventory
+        .items
+        .iter()
+        .copied()
+        .filter(|eid| !entities.inventory.contains_key(eid))
+        .collect();
+    loose.sort_by_key(|eid| (std::cmp::Reverse(volume(eid)), eid.0));
+
+    let mut space: Vec<(EntityID, u32)> = item::possessions(entities, owner)
+        .into_iter()
+        .filter_map(|eid| Some((eid, free_volume(entities, eid)?)))
+        .collect();
+
+    let mut plan = Vec::new();
+    for item in loose {
+        let needed = volume(&item);
+        let best = space
+            .iter_mut()
+            .filter(|(_, free)| *free >= needed)
+            .min_by_key(|(_, free)| *free);
+        if let Some((container, free)) = best {
+            *free -= needed;
+            plan.push((item, *container));
+        }
+    }
+    plan
+}
+
+/// Put `actor`'s loose items away as [`organize_plan`] suggests and return
+/// the items moved.
+pub fn organize(state: &mut GameState, actor: EntityID) -> Vec<EntityID> {
+    organize_plan(&state.entities, actor)
+        .into_iter()
+        .filter(|(item, container)| item::store(state, actor, *item, *container).is_ok())
+        .map(|(item, _)| item)
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::component::{Inventory, Item, Stats};
+    use crate::game::{Blueprint, Point, spawn};
+    use crate::testing::state_with_player;
+
+    const HERE: Point = Point { x: 10, y: 10 };
+
+    /// Spawn an item with the given weight and volume and pick it up.
+    fn carry(state: &mut GameState, actor: EntityID, weight: u32, volume: u32) -> EntityID {
+        let item = spawn(
+            state,
+            Blueprint {
+                item: Some(Item {
+                    slot: None,
+                    weight,
+                    volume,
+                }),
+                ..Blueprint::default()
+            }
+            .at(HERE),
+        );
+        item::pick_up(state, actor, item).expect("within reach");
+        item
+    }
+
+    fn carry_box(state: &mut GameState, actor: EntityID, capacity: u32) -> EntityID {
+        let container = carry(state, actor, 0, capacity);
+        state.entities.inventory.insert(
+            container,
+            Inventory {
+                items: Vec::new(),
+                capacity: Some(capacity),
+            },
+        );
+        container
+    }
+
+    #[test]
+    fn weight_includes_contents() {
+        let (mut state, player) = state_with_player();
+        let kit = carried_weight(&state.entities, player);
+        let sack = carry_box(&mut state, player, 1000);
+        let rock = carry(&mut state, player, 700, 100);
+        item::store(&mut state, player, rock, sack).expect("fits");
+
+        assert_eq!(weight_of(&state.entities, sack), 700);
+        assert_eq!(carried_weight(&state.entities, player), kit + 700);
+    }
+
+    #[test]
+    fn carry_limit_follows_strength() {
+        let (mut state, player) = state_with_player();
+        assert_eq!(
+            carry_limit(&state.entities, player),
+            10 * GRAMS_PER_STRENGTH
+        );
+        state.entities.stats.insert(player, Stats { strength: 2 });
+        assert_eq!(carry_limit(&state.entities, player), 2 * GRAMS_PER_STRENGTH);
+        state.entities.stats.remove(&player);
+        assert_eq!(carry_limit(&state.entities, player), 0);
+    }
+
+    #[test]
+    fn encumbrance_grows_with_the_load() {
+        assert_eq!(classify(0, 0), Encumbrance::Unburdened);
+        assert_eq!(classify(1000, 1000), Encumbrance::Unburdened);
+        assert_eq!(classify(1001, 1000), Encumbrance::Burdened);
+        assert_eq!(classify(1500, 1000), Encumbrance::Burdened);
+        assert_eq!(classify(1501, 1000), Encumbrance::Overloaded);
+        assert_eq!(classify(1, 0), Encumbrance::Overloaded);
+        assert_eq!(classify(u32::MAX, u32::MAX), Encumbrance::Unburdened);
+
+        let (mut state, player) = state_with_player();
+        assert_eq!(
+            encumbrance(&state.entities, player),
+            Encumbrance::Unburdened
+        );
+        carry(&mut state, player, 40_000, 1);
+        assert_eq!(encumbrance(&state.entities, player), Encumbrance::Burdened);
+        assert_eq!(Encumbrance::Burdened.ticks_per_step(), Some(2));
+        carry(&mut state, player, 10_000, 1);
+        assert_eq!(
+            encumbrance(&state.entities, player),
+            Encumbrance::Overloaded
+        );
+        assert_eq!(Encumbrance::Overloaded.ticks_per_step(), None);
+    }
+
+    #[test]
+    fn containers_hold_only_their_capacity() {
+        let (mut state, player) = state_with_player();
+        let sack = carry_box(&mut state, player, 500);
+        let big = carry(&mut state, player, 0, 400);
+        let small = carry(&mut state, player, 0, 200);
+
+        assert_eq!(free_volume(&state.entities, sack), Some(500));
+        assert_eq!(
+            free_volume(&state.entities, player),
+            None,
+            "hands hold anything"
+        );
+        item::store(&mut state, player, big, sack).expect("fits");
+        assert_eq!(free_volume(&state.entities, sack), Some(100));
+        assert!(!fits(&state.entities, small, sack));
+        assert_eq!(
+            item::store(&mut state, player, small, sack),
+            Err(item::ItemError::NoRoom)
+        );
+    }
+
+    #[test]
+    fn organizing_fills_the_fullest_container_that_fits() {
+        let (mut state, player) = state_with_player();
+        // The starting pouch holds 250 ml
+        let pouch = item::possessions(&state.entities, player)
+            .into_iter()
+            .find(|eid| state.entities.name_of(*eid) == Some("Pouch"))
+            .expect("characters start with a pouch");
+        let sack = carry_box(&mut state, player, 1000);
+        let boulder = carry(&mut state, player, 0, 5000);
+        let bottle = carry(&mut state, player, 0, 800);
+        let apple = carry(&mut state, player, 0, 220);
+        let coin = carry(&mut state, player, 0, 1);
+
+        let plan = organize_plan(&state.entities, player);
+        assert_eq!(
+            plan,
+            vec![(bottle, sack), (apple, pouch), (coin, pouch)],
+            "the boulder fits nowhere and the sack is not packed"
+        );
+
+        assert_eq!(organize(&mut state, player), vec![bottle, apple, coin]);
+        assert_eq!(state.entities.inventory[&player].items, vec![sack, boulder]);
+        assert!(organize_plan(&state.entities, player).is_empty());
+    }
+}
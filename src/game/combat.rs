@@ -0,0 +1,430 @@
+//! Fighting, getting hurt and dying.
+//!
+//! Anything with [`Health`](super::component::Health) can be hurt. A melee
+//! attack always hits an adjacent target, for damage that follows from the
+//! attacker's strength; there is no chance involved, so that replaying the
+//! same actions gives the same world. Creatures with an
+//! [`Anatomy`](super::anatomy::Anatomy) are wounded in the part the blow
+//! lands on, which the attacker may aim for. Whatever runs out of health, or
+//! loses a vital part, dies: it drops what it carried, its template's
+//! `drops` appear where it fell, and it remains as a corpse.
+
+use super::component::{Inventory, Renderable, Rgb};
+use super::{Blueprint, EntityID, GameEvent, GameState, item, spawn_template};
+
+use std::fmt;
+
+/// Glyph of corpses.
+pub const CORPSE_GLYPH: char = '%';
+
+/// Why an attack could not be made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombatError {
+    /// The target does not exist, or is the attacker.
+    NoTarget,
+    OutOfReach,
+    /// The target has no health to lose, like a tree or a corpse.
+    CannotBeHurt,
+    /// The part aimed for is not there, or already destroyed.
+    NoSuchPart,
+    /// Nothing that shoots or can be thrown is in the main hand.
+    NothingToFire,
+    /// The weapon in hand shoots ammunition the shooter does not carry.
+    OutOfAmmo,
+}
+
+impl fmt::Display for CombatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoTarget => write!(f, "There is nothing there to attack."),
+            Self::OutOfReach => write!(f, "That is out of reach."),
+            Self::CannotBeHurt => write!(f, "Attacking that would do no good."),
+            Self::NoSuchPart => write!(f, "There is nothing there to aim for."),
+            Self::NothingToFire => write!(f, "You have nothing in hand to shoot or throw."),
+            Self::OutOfAmmo => write!(f, "You have nothing to shoot with that."),
+        }
+    }
+}
+
+impl std::error::Error for CombatError {}
+
+/// Damage a melee blow from `attacker` deals.
+pub fn melee_damage(state: &GameState, attacker: EntityID) -> u32 {
+    let strength = state
+        .entities
+        .stats
+        .get(&attacker)
+        .map_or(0, |s| s.strength);
+    1 + strength / 4
+}
+
+/// Hit `target`, next to `attacker`, with a melee blow, aimed at `part` of
+/// its body if given.
+///
+/// # Errors
+///
+/// Returns why the attack cannot be made; nothing changes then.
+pub fn attack(
+    state: &mut GameState,
+    attacker: EntityID,
+    target: EntityID,
+    part: Option<usize>,
+) -> Result<Vec<GameEvent>, CombatError> {
+    let entities = &state.entities;
+    if attacker == target || !entities.contains(target) {
+        return Err(CombatError::NoTarget);
+    }
+    if !entities.health.contains_key(&target) {
+        return Err(CombatError::CannotBeHurt);
+    }
+    let (Some(from), Some(to)) = (
+        entities.position().get(&attacker),
+        entities.position().get(&target),
+    ) else {
+        return Err(CombatError::OutOfReach);
+    };
+    if from.chebyshev_distance(*to) > 1 {
+        return Err(CombatError::OutOfReach);
+    }
+    if let Some(part) = part {
+        let there = entities
+            .anatomy
+            .get(&target)
+            .is_some_and(|a| part < a.parts.len() && !a.is_destroyed(part));
+        if !there {
+            return Err(CombatError::NoSuchPart);
+        }
+    }
+    let amount = melee_damage(state, attacker);
+    Ok(damage(state, target, amount, Some(attacker), part))
+}
+
+/// Take `amount` health from `target`, wounding `part` of its body or, if
+/// not given, whatever part an unaimed blow lands on. Kills the target if
+/// no health is left or a vital part is destroyed.
+pub fn damage(
+    state: &mut GameState,
+    target: EntityID,
+    amount: u32,
+    attacker: Option<EntityID>,
+    part: Option<usize>,
+) -> Vec<GameEvent> {
+    let entities = &mut state.entities;
+    let Some(health) = entities.health.get_mut(&target) else {
+        return Vec::new();
+    };
+    health.current = health.current.saturating_sub(amount);
+    let out_of_health = health.current == 0;
+
+    let hit = entities.anatomy.get_mut(&target).and_then(|anatomy| {
+        let part = part.or_else(|| anatomy.target())?;
+        let name = anatomy.parts.get(part)?.name.clone();
+        let destroyed = anatomy.wound(part, amount);
+        Some((name, destroyed, anatomy.is_fatally_hurt()))
+    });
+    let dead = out_of_health || hit.as_ref().is_some_and(|(_, _, fatal)| *fatal);
+
+    let mut events = vec![GameEvent::Damaged {
+        entity_id: target,
+        attacker,
+        amount,
+        part: hit.as_ref().map(|(name, _, _)| name.clone()),
+    }];
+    if let Some((part, true, _)) = hit {
+        events.push(GameEvent::PartDestroyed {
+            entity_id: target,
+            part,
+        });
+    }
+    if dead {
+        let name = state
+            .entities
+            .name_of(target)
+            .unwrap_or("Something")
+            .to_owned();
+        kill(state, target);
+        events.push(GameEvent::Died {
+            entity_id: target,
+            killer: attacker,
+            name,
+        });
+    }
+    events
+}
+
+/// Turn `entity_id` into a corpse where it stands.
+///
+/// What it carried falls to the ground along with its template's drops, or
+/// stays in the corpse if it cannot, and, if it was a character, nobody can
+/// play it any more.
+pub fn kill(state: &mut GameState, entity_id: EntityID) {
+    let entities = &state.entities;
+    let position = entities.position().get(&entity_id).copied();
+    let mut corpse = Blueprint {
+        position,
+        name: Some(
+            entities
+                .name_of(entity_id)
+                .map_or_else(|| "Corpse".to_owned(), |name| format!("Corpse of {name}")),
+        ),
+        renderable: Some(Renderable::new(
+            CORPSE_GLYPH,
+            entities
+                .renderable
+                .get(&entity_id)
+                .map_or(Rgb::DARK_GRAY, |r| r.fg),
+            Renderable::ITEM,
+        )),
+        ..Blueprint::default()
+    };
+    let drops = entities.drops.get(&entity_id).cloned().unwrap_or_default();
+
+    let held = entities
+        .equipment
+        .get(&entity_id)
+        .into_iter()
+        .flat_map(|e| e.slots.values())
+        .chain(
+            entities
+                .inventory
+                .get(&entity_id)
+                .into_iter()
+                .flat_map(|i| i.items.iter()),
+        )
+        .copied()
+        .collect::<Vec<_>>();
+    let mut kept = Vec::new();
+    for item in held {
+        if item::drop_item(state, entity_id, item).is_err()
+            && state.entities.held_by.get(&item) == Some(&entity_id)
+        {
+            kept.push(item);
+        }
+    }
+    if !kept.is_empty() {
+        corpse.inventory = Some(Inventory {
+            items: kept,
+            capacity: None,
+        });
+    }
+
+    state.entities.insert(entity_id, corpse);
+    state
+        .accounts
+        .values_mut()
+        .for_each(|account| account.characters.retain(|eid| *eid != entity_id));
+    if let Some(at) = position {
+        for template in drops {
+            spawn_template(state, &template, at);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::component::Slot;
+    use crate::game::{AccountID, GameAction, Point, apply, claim_character, teleport};
+    use crate::testing::state_with_player;
+
+    const HERE: Point = Point { x: 10, y: 10 };
+
+    fn sheep(state: &mut GameState, at: Point) -> EntityID {
+        spawn_template(state, "sheep", at).expect("sheep is built in")
+    }
+
+    #[test]
+    fn blows_follow_strength() {
+        let (mut state, player) = state_with_player();
+        let target = sheep(&mut state, HERE);
+        assert_eq!(melee_damage(&state, player), 3);
+        assert_eq!(melee_damage(&state, target), 2);
+        state.entities.stats.remove(&player);
+        assert_eq!(melee_damage(&state, player), 1);
+    }
+
+    #[test]
+    fn attacks_need_a_living_target_within_reach() {
+        let (mut state, player) = state_with_player();
+        let far = sheep(&mut state, Point { x: 12, y: 10 });
+        let tree = EntityID(1);
+
+        assert_eq!(
+            attack(&mut state, player, far, None),
+            Err(CombatError::OutOfReach)
+        );
+        assert_eq!(
+            attack(&mut state, player, player, None),
+            Err(CombatError::NoTarget)
+        );
+        assert_eq!(
+            attack(&mut state, player, EntityID(999), None),
+            Err(CombatError::NoTarget)
+        );
+        teleport(&mut state, player, Point { x: 5, y: 6 });
+        assert_eq!(
+            attack(&mut state, player, tree, None),
+            Err(CombatError::CannotBeHurt)
+        );
+    }
+
+    #[test]
+    fn attacks_go_through_apply() {
+        let (mut state, player) = state_with_player();
+        let target = sheep(&mut state, Point { x: 11, y: 10 });
+
+        let events = apply(&mut state, player, &GameAction::Attack(target));
+        assert_eq!(
+            events,
+            vec![GameEvent::Damaged {
+                entity_id: target,
+                attacker: Some(player),
+                amount: 3,
+                part: Some("truncus".into()),
+            }]
+        );
+        assert_eq!(state.entities.health[&target].current, 3);
+    }
+
+    #[test]
+    fn aimed_blows_destroy_parts_and_vital_ones_kill() {
+        let (mut state, player) = state_with_player();
+        let target = sheep(&mut state, HERE);
+        let part = |state: &GameState, name| {
+            state.entities.anatomy[&target]
+                .find(name)
+                .expect("sheep have it")
+        };
+        let tail = part(&state, "cauda");
+        let heart = part(&state, "cor");
+        // Plenty of health, so that only losing a part can kill
+        state
+            .entities
+            .health
+            .get_mut(&target)
+            .expect("sheep have health")
+            .current = 100;
+
+        let events = attack(&mut state, player, target, Some(tail)).expect("in reach");
+        assert_eq!(
+            events.get(1),
+            Some(&GameEvent::PartDestroyed {
+                entity_id: target,
+                part: "cauda".into(),
+            })
+        );
+        assert_eq!(
+            attack(&mut state, player, target, Some(tail)),
+            Err(CombatError::NoSuchPart)
+        );
+        assert_eq!(
+            attack(&mut state, player, target, Some(99)),
+            Err(CombatError::NoSuchPart)
+        );
+
+        let events = attack(&mut state, player, target, Some(heart)).expect("in reach");
+        assert!(
+            matches!(events.last(), Some(GameEvent::Died { entity_id, .. }) if *entity_id == target),
+            "{events:?}"
+        );
+    }
+
+    #[test]
+    fn creatures_die_when_out_of_health_and_leave_a_corpse() {
+        let (mut state, player) = state_with_player();
+        let target = sheep(&mut state, HERE);
+        state.entities.drops.insert(target, vec!["stone".into()]);
+
+        attack(&mut state, player, target, None).expect("in reach");
+        let events = attack(&mut state, player, target, None).expect("in reach");
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::Died {
+                entity_id: target,
+                killer: Some(player),
+                name: "Sheep".into(),
+            })
+        );
+
+        let entities = &state.entities;
+        assert_eq!(entities.name_of(target), Some("Corpse of Sheep"));
+        assert_eq!(entities.position().get(&target), Some(&HERE));
+        assert!(!entities.health.contains_key(&target));
+        let dropped: Vec<Option<&str>> = entities
+            .items_at(HERE)
+            .into_iter()
+            .map(|eid| entities.name_of(eid))
+            .collect();
+        assert_eq!(dropped, vec![Some("Stone")]);
+        assert_eq!(
+            attack(&mut state, player, target, None),
+            Err(CombatError::CannotBeHurt)
+        );
+    }
+
+    #[test]
+    fn dead_characters_drop_everything_and_are_lost() {
+        let (mut state, player) = state_with_player();
+        let account = AccountID([1; 32]);
+        claim_character(&mut state, account, player);
+        let kit = item::possessions(&state.entities, player);
+
+        let events = damage(&mut state, player, 100, None, None);
+        assert!(events.contains(&GameEvent::Died {
+            entity_id: player,
+            killer: None,
+            name: "Alice".into(),
+        }));
+        assert!(!state.entities.is_player(player));
+        assert!(state.characters_of(&account).is_empty());
+        for item in kit {
+            assert_eq!(state.entities.position().get(&item), Some(&HERE));
+            assert_eq!(state.entities.held_by.get(&item), None);
+        }
+    }
+
+    #[test]
+    fn one_failed_drop_does_not_keep_the_rest_from_falling() {
+        let (mut state, player) = state_with_player();
+        let kit = item::possessions(&state.entities, player);
+        // Worn first, yet it lies elsewhere and cannot be dropped
+        let stray = spawn_template(&mut state, "stone", Point { x: 12, y: 10 }).expect("built in");
+        state
+            .entities
+            .equipment
+            .get_mut(&player)
+            .expect("characters have equipment")
+            .slots
+            .insert(Slot::Head, stray);
+
+        kill(&mut state, player);
+
+        for item in kit {
+            assert_eq!(state.entities.position().get(&item), Some(&HERE));
+        }
+        assert_eq!(
+            state.entities.position().get(&stray),
+            Some(&Point { x: 12, y: 10 })
+        );
+        assert!(!state.entities.inventory.contains_key(&player));
+    }
+
+    #[test]
+    fn corpses_keep_what_cannot_fall_to_the_ground() {
+        let (mut state, player) = state_with_player();
+        let kit = item::possessions(&state.entities, player);
+        state.entities.set_position(player, None);
+
+        kill(&mut state, player);
+
+        let corpse = &state.entities.inventory[&player];
+        assert_eq!(corpse.items, kit);
+        for item in kit {
+            assert_eq!(state.entities.held_by.get(&item), Some(&player));
+        }
+    }
+}
@@ -0,0 +1,767 @@
+//! Entity components.
+//!
+//! An entity is just an [`EntityID`]; what it is follows from the components
+//! stored for it. Each kind of component lives in its own map in
+//! [`EntityMap`], so a tree is an entity with a position, a glyph and the
+//! tags that make it block movement and sight, and a new kind of thing needs
+//! no code as long as it can be put together from existing components.
+//!
+//! Bodies are components too, but complicated enough to live in
+//! [`anatomy`](super::anatomy).
+//!
+//! Entities are assembled from a [`Blueprint`], usually one of the
+//! [`Templates`](super::template::Templates) loaded from data files, and,
+//! like everything else in
+//! [`GameState`](super::GameState), only changed through the functions in
+//! [`game`](super).
+
+use super::anatomy::Anatomy;
+use super::{Diagonal, Direction, EntityID, Point, Turn};
+
+use bitcode::{Decode, Encode};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Storage for one kind of component.
+pub type ComponentMap<T> = FxHashMap<EntityID, T>;
+
+/// Entities that carry a marker component.
+pub type TagSet = FxHashSet<EntityID>;
+
+/// A colour, without tying the game to a UI toolkit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    pub const BLACK: Self = Self(0, 0, 0);
+    pub const WHITE: Self = Self(255, 255, 255);
+    pub const DARK_GRAY: Self = Self(96, 96, 96);
+}
+
+/// How an entity is drawn on the map.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Renderable {
+    pub glyph: char,
+    pub fg: Rgb,
+    pub bg: Rgb,
+    /// Size of the glyph as a percentage of a normal tile's.
+    pub size_percent: u16,
+    /// Of several entities on one tile, the one on the highest layer is drawn.
+    pub layer: u8,
+}
+
+impl Renderable {
+    /// Layer of things lying on the ground.
+    pub const GROUND: u8 = 0;
+    /// Layer of items, which lie on the ground but over it.
+    pub const ITEM: u8 = 1;
+    /// Layer of creatures, drawn over whatever they stand on.
+    pub const CREATURE: u8 = 2;
+    /// Layer of things in flight, drawn over everything.
+    pub const PROJECTILE: u8 = 3;
+
+    /// A full-size glyph in `fg` on black.
+    pub const fn new(glyph: char, fg: Rgb, layer: u8) -> Self {
+        Self {
+            glyph,
+            fg,
+            bg: Rgb::BLACK,
+            size_percent: 100,
+            layer,
+        }
+    }
+}
+
+/// Marks a character that players can control.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Player {
+    /// Set while the character's owner is not connected.
+    pub dormant: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub const fn full(max: u32) -> Self {
+        Self { current: max, max }
+    }
+}
+
+/// A creature's innate abilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Stats {
+    /// Decides, among other things, how much the creature can carry.
+    pub strength: u32,
+}
+
+/// Which of the eight compass points a creature looks towards.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub enum Facing {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    #[default]
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Facing {
+    /// Every facing, clockwise from north.
+    pub const ALL: [Self; 8] = [
+        Self::North,
+        Self::NorthEast,
+        Self::East,
+        Self::SouthEast,
+        Self::South,
+        Self::SouthWest,
+        Self::West,
+        Self::NorthWest,
+    ];
+
+    /// Returns the `(dx, dy)` offset of the tile straight ahead.
+    pub const fn delta(self) -> (i32, i32) {
+        match self {
+            Self::North => (0, -1),
+            Self::NorthEast => (1, -1),
+            Self::East => (1, 0),
+            Self::SouthEast => (1, 1),
+            Self::South => (0, 1),
+            Self::SouthWest => (-1, 1),
+            Self::West => (-1, 0),
+            Self::NorthWest => (-1, -1),
+        }
+    }
+
+    /// The facing after turning an eighth of a circle.
+    pub fn turned(self, turn: Turn) -> Self {
+        let count = Self::ALL.len();
+        let at = Self::ALL
+            .iter()
+            .position(|&f| f == self)
+            .unwrap_or_default();
+        let next = match turn {
+            Turn::Left => at + count - 1,
+            Turn::Right => at + 1,
+        };
+        Self::ALL.get(next % count).copied().unwrap_or(self)
+    }
+}
+
+impl From<Direction> for Facing {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Direction::Up => Self::North,
+            Direction::Down => Self::South,
+            Direction::Left => Self::West,
+            Direction::Right => Self::East,
+        }
+    }
+}
+
+impl From<Diagonal> for Facing {
+    fn from(diagonal: Diagonal) -> Self {
+        match diagonal {
+            Diagonal::UpLeft => Self::NorthWest,
+            Diagonal::UpRight => Self::NorthEast,
+            Diagonal::DownLeft => Self::SouthWest,
+            Diagonal::DownRight => Self::SouthEast,
+        }
+    }
+}
+
+/// Items carried by a character, or kept in a container.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Inventory {
+    pub items: Vec<EntityID>,
+    /// Total volume, in millilitres, of the items that fit; `None` for no
+    /// limit, as for a character's own hands and back.
+    pub capacity: Option<u32>,
+}
+
+/// Where on a character an item is worn or held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
+pub enum Slot {
+    Head,
+    Body,
+    Waist,
+    Legs,
+    Feet,
+    MainHand,
+    OffHand,
+}
+
+impl Slot {
+    pub const ALL: [Self; 7] = [
+        Self::Head,
+        Self::Body,
+        Self::Waist,
+        Self::Legs,
+        Self::Feet,
+        Self::MainHand,
+        Self::OffHand,
+    ];
+
+    /// The slot's name, as written in templates.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Head => "head",
+            Self::Body => "body",
+            Self::Waist => "waist",
+            Self::Legs => "legs",
+            Self::Feet => "feet",
+            Self::MainHand => "main_hand",
+            Self::OffHand => "off_hand",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|slot| slot.name() == name)
+    }
+}
+
+/// Marks an entity that can be picked up and carried.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Item {
+    /// Where the item is worn, if it can be.
+    pub slot: Option<Slot>,
+    /// In grams, not counting anything inside it.
+    pub weight: u32,
+    /// In millilitres.
+    pub volume: u32,
+}
+
+/// Items a character is wearing or holding, by slot.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Equipment {
+    pub slots: BTreeMap<Slot, EntityID>,
+}
+
+/// Makes an item something to shoot or throw with.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Ranged {
+    /// Tiles a shot flies before it falls.
+    pub range: u32,
+    pub damage: u32,
+    /// Chance, in percent, that a shot hits a creature in its way.
+    pub accuracy: u32,
+    /// Tiles a shot flies per tick.
+    pub speed: u32,
+    /// Template of the ammunition it shoots; without one, the item is
+    /// itself thrown.
+    pub ammo: Option<String>,
+}
+
+/// Something in flight, shot or thrown by `shooter`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Projectile {
+    pub shooter: EntityID,
+    /// Tiles still to fly over, the next one last.
+    pub path: Vec<Point>,
+    pub speed: u32,
+    pub damage: u32,
+    pub accuracy: u32,
+}
+
+/// The components of an entity that has yet to be spawned.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Blueprint {
+    pub position: Option<Point>,
+    pub name: Option<String>,
+    /// ID of the template the entity was made from.
+    pub template: Option<String>,
+    pub renderable: Option<Renderable>,
+    pub player: Option<Player>,
+    pub health: Option<Health>,
+    pub anatomy: Option<Anatomy>,
+    pub facing: Option<Facing>,
+    pub stats: Option<Stats>,
+    pub inventory: Option<Inventory>,
+    pub item: Option<Item>,
+    pub equipment: Option<Equipment>,
+    pub ranged: Option<Ranged>,
+    pub projectile: Option<Projectile>,
+    /// Templates spawned where the entity is destroyed.
+    pub drops: Vec<String>,
+    /// How hard the tile the entity lies on is to cross, in percent of a
+    /// normal step; see [`path`](super::path).
+    pub move_cost: Option<u32>,
+    pub blocks_movement: bool,
+    pub blocks_sight: bool,
+}
+
+impl Blueprint {
+    /// A character players can control.
+    pub fn player(name: Option<String>, position: Point) -> Self {
+        Self {
+            position: Some(position),
+            name,
+            renderable: Some(Renderable::new('@', Rgb::WHITE, Renderable::CREATURE)),
+            player: Some(Player::default()),
+            health: Some(Health::full(10)),
+            facing: Some(Facing::default()),
+            stats: Some(Stats { strength: 10 }),
+            inventory: Some(Inventory::default()),
+            equipment: Some(Equipment::default()),
+            ..Self::default()
+        }
+    }
+
+    /// The same blueprint, placed at `position`.
+    pub fn at(self, position: Point) -> Self {
+        Self {
+            position: Some(position),
+            ..self
+        }
+    }
+}
+
+/// Where [`Generation`]s are drawn from, for every [`EntityMap`] alike.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Which state of the entities that shape paths an [`EntityMap`] is in.
+///
+/// Each change draws a new number from one counter for the whole program, so
+/// two maps only share a generation if one is a copy of the other. It is no
+/// part of the world: it is neither saved nor sent, and [`EntityMap`]'s
+/// equality ignores it.
+#[derive(Debug, Clone, Copy)]
+struct Generation(u64);
+
+impl Generation {
+    fn next() -> Self {
+        Self(NEXT_GENERATION.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for Generation {
+    fn default() -> Self {
+        Self::next()
+    }
+}
+
+/// Every entity in the world, stored one kind of component at a time.
+///
+/// The components that decide which paths are found (positions, movement
+/// costs and what blocks movement) can only be changed through methods
+/// that keep [`Self::generation`] up to date.
+#[derive(Debug, Default, Clone, Encode, Decode)]
+pub struct EntityMap {
+    /// Every entity that exists, whatever components it has.
+    ids: FxHashSet<EntityID>,
+    position: ComponentMap<Point>,
+    pub name: ComponentMap<String>,
+    pub template: ComponentMap<String>,
+    pub renderable: ComponentMap<Renderable>,
+    pub player: ComponentMap<Player>,
+    pub health: ComponentMap<Health>,
+    pub anatomy: ComponentMap<Anatomy>,
+    pub facing: ComponentMap<Facing>,
+    pub stats: ComponentMap<Stats>,
+    pub inventory: ComponentMap<Inventory>,
+    pub item: ComponentMap<Item>,
+    pub equipment: ComponentMap<Equipment>,
+    /// The entity whose inventory or equipment an item is in. Items have
+    /// either this or a position.
+    pub held_by: ComponentMap<EntityID>,
+    pub ranged: ComponentMap<Ranged>,
+    pub projectile: ComponentMap<Projectile>,
+    pub drops: ComponentMap<Vec<String>>,
+    move_cost: ComponentMap<u32>,
+    blocks_movement: TagSet,
+    pub blocks_sight: TagSet,
+    #[bitcode(skip)]
+    generation: Generation,
+}
+
+impl EntityMap {
+    pub fn contains(&self, entity_id: EntityID) -> bool {
+        self.ids.contains(&entity_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// The IDs of every entity, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = EntityID> + '_ {
+        self.ids.iter().copied()
+    }
+
+    /// Changes whenever an entity that blocks movement, or changes what its
+    /// tile costs to cross, appears, moves or disappears; what paths are
+    /// found on the map stays the same as long as this does.
+    pub fn generation(&self) -> u64 {
+        self.generation.0
+    }
+
+    /// Where every entity on the map is; see [`Self::set_position`].
+    pub fn position(&self) -> &ComponentMap<Point> {
+        &self.position
+    }
+
+    /// What entering the tile of each entity that changes it costs.
+    pub fn move_costs(&self) -> &ComponentMap<u32> {
+        &self.move_cost
+    }
+
+    /// Entities that keep others from entering their tile.
+    pub fn movement_blockers(&self) -> &TagSet {
+        &self.blocks_movement
+    }
+
+    /// Whether `entity_id` has a say in the paths found on the map.
+    fn shapes_paths(&self, entity_id: EntityID) -> bool {
+        self.blocks_movement.contains(&entity_id) || self.move_cost.contains_key(&entity_id)
+    }
+
+    /// Put `entity_id` at `position`, or take it off the map if `None`.
+    pub fn set_position(&mut self, entity_id: EntityID, position: Option<Point>) {
+        let before = match position {
+            Some(position) => self.position.insert(entity_id, position),
+            None => self.position.remove(&entity_id),
+        };
+        if before != position && self.shapes_paths(entity_id) {
+            self.generation = Generation::next();
+        }
+    }
+
+    /// Create `entity_id` with the components in `blueprint`, replacing
+    /// whatever it had before.
+    pub fn insert(&mut self, entity_id: EntityID, blueprint: Blueprint) {
+        self.remove(entity_id);
+        self.ids.insert(entity_id);
+
+        let Blueprint {
+            position,
+            name,
+            template,
+            renderable,
+            player,
+            health,
+            anatomy,
+            facing,
+            stats,
+            inventory,
+            item,
+            equipment,
+            ranged,
+            projectile,
+            drops,
+            move_cost,
+            blocks_movement,
+            blocks_sight,
+        } = blueprint;
+        insert_some(&mut self.position, entity_id, position);
+        insert_some(&mut self.name, entity_id, name);
+        insert_some(&mut self.template, entity_id, template);
+        insert_some(&mut self.renderable, entity_id, renderable);
+        insert_some(&mut self.player, entity_id, player);
+        insert_some(&mut self.health, entity_id, health);
+        insert_some(&mut self.anatomy, entity_id, anatomy);
+        insert_some(&mut self.facing, entity_id, facing);
+        insert_some(&mut self.stats, entity_id, stats);
+        insert_some(&mut self.inventory, entity_id, inventory);
+        insert_some(&mut self.item, entity_id, item);
+        insert_some(&mut self.equipment, entity_id, equipment);
+        insert_some(&mut self.ranged, entity_id, ranged);
+        insert_some(&mut self.projectile, entity_id, projectile);
+        insert_some(&mut self.move_cost, entity_id, move_cost);
+        if !drops.is_empty() {
+            self.drops.insert(entity_id, drops);
+        }
+        if blocks_movement {
+            self.blocks_movement.insert(entity_id);
+        }
+        if blocks_sight {
+            self.blocks_sight.insert(entity_id);
+        }
+        if self.shapes_paths(entity_id) {
+            self.generation = Generation::next();
+        }
+    }
+
+    /// Remove `entity_id` and all of its components.
+    pub fn remove(&mut self, entity_id: EntityID) {
+        if self.shapes_paths(entity_id) {
+            self.generation = Generation::next();
+        }
+        self.ids.remove(&entity_id);
+        self.position.remove(&entity_id);
+        self.name.remove(&entity_id);
+        self.template.remove(&entity_id);
+        self.renderable.remove(&entity_id);
+        self.player.remove(&entity_id);
+        self.health.remove(&entity_id);
+        self.anatomy.remove(&entity_id);
+        self.facing.remove(&entity_id);
+        self.stats.remove(&entity_id);
+        self.inventory.remove(&entity_id);
+        self.item.remove(&entity_id);
+        self.equipment.remove(&entity_id);
+        self.held_by.remove(&entity_id);
+        self.ranged.remove(&entity_id);
+        self.projectile.remove(&entity_id);
+        self.drops.remove(&entity_id);
+        self.move_cost.remove(&entity_id);
+        self.blocks_movement.remove(&entity_id);
+        self.blocks_sight.remove(&entity_id);
+    }
+
+    pub fn name_of(&self, entity_id: EntityID) -> Option<&str> {
+        self.name.get(&entity_id).map(String::as_str)
+    }
+
+    /// Whether `entity_id` is a character players can control.
+    pub fn is_player(&self, entity_id: EntityID) -> bool {
+        self.player.contains_key(&entity_id)
+    }
+
+    pub fn is_dormant(&self, entity_id: EntityID) -> bool {
+        self.player.get(&entity_id).is_some_and(|p| p.dormant)
+    }
+
+    /// The player character called `name`, if there is one.
+    pub fn player_named(&self, name: &str) -> Option<EntityID> {
+        self.player
+            .keys()
+            .copied()
+            .find(|eid| self.name_of(*eid) == Some(name))
+    }
+
+    pub fn is_item(&self, entity_id: EntityID) -> bool {
+        self.item.contains_key(&entity_id)
+    }
+
+    /// Items lying on the ground at `point`; not those flying over it.
+    pub fn items_at(&self, point: Point) -> Vec<EntityID> {
+        let mut items: Vec<EntityID> = self
+            .item
+            .keys()
+            .copied()
+            .filter(|eid| {
+                self.position.get(eid) == Some(&point) && !self.projectile.contains_key(eid)
+            })
+            .collect();
+        items.sort_unstable_by_key(|eid| eid.0);
+        items
+    }
+
+    /// Something at `point` that can be hurt, if there is anything.
+    pub fn creature_at(&self, point: Point) -> Option<EntityID> {
+        self.health
+            .keys()
+            .copied()
+            .filter(|eid| self.position.get(eid) == Some(&point))
+            .min_by_key(|eid| eid.0)
+    }
+
+    pub fn blocks_sight(&self, entity_id: EntityID) -> bool {
+        self.blocks_sight.contains(&entity_id)
+    }
+
+    /// Whether something standing at `point` keeps others from entering it.
+    pub fn blocks_movement_at(&self, point: Point) -> bool {
+        self.blocks_movement
+            .iter()
+            .any(|eid| self.position.get(eid) == Some(&point))
+    }
+
+    /// Whether something standing at `point` keeps anyone from seeing past it.
+    pub fn blocks_sight_at(&self, point: Point) -> bool {
+        self.blocks_sight
+            .iter()
+            .any(|eid| self.position.get(eid) == Some(&point))
+    }
+}
+
+impl PartialEq for EntityMap {
+    fn eq(&self, other: &Self) -> bool {
+        // Spelled out so that a new component cannot be left out unnoticed
+        let Self {
+            ids,
+            position,
+            name,
+            template,
+            renderable,
+            player,
+            health,
+            anatomy,
+            facing,
+            stats,
+            inventory,
+            item,
+            equipment,
+            held_by,
+            ranged,
+            projectile,
+            drops,
+            move_cost,
+            blocks_movement,
+            blocks_sight,
+            generation: _,
+        } = self;
+        *ids == other.ids
+            && *position == other.position
+            && *name == other.name
+            && *template == other.template
+            && *renderable == other.renderable
+            && *player == other.player
+            && *health == other.health
+            && *anatomy == other.anatomy
+            && *facing == other.facing
+            && *stats == other.stats
+            && *inventory == other.inventory
+            && *item == other.item
+            && *equipment == other.equipment
+            && *held_by == other.held_by
+            && *ranged == other.ranged
+            && *projectile == other.projectile
+            && *drops == other.drops
+            && *move_cost == other.move_cost
+            && *blocks_movement == other.blocks_movement
+            && *blocks_sight == other.blocks_sight
+    }
+}
+
+impl Eq for EntityMap {}
+
+fn insert_some<T>(map: &mut ComponentMap<T>, entity_id: EntityID, component: Option<T>) {
+    if let Some(component) = component {
+        map.insert(entity_id, component);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Point = Point { x: 0, y: 0 };
+
+    fn wall(position: Point) -> Blueprint {
+        Blueprint {
+            position: Some(position),
+            renderable: Some(Renderable::new('#', Rgb::WHITE, Renderable::CREATURE)),
+            blocks_movement: true,
+            blocks_sight: true,
+            ..Blueprint::default()
+        }
+    }
+
+    #[test]
+    fn insert_stores_only_the_blueprints_components() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), wall(ORIGIN));
+
+        assert!(entities.contains(EntityID(1)));
+        assert_eq!(entities.position.get(&EntityID(1)), Some(&ORIGIN));
+        assert!(entities.renderable.contains_key(&EntityID(1)));
+        assert!(entities.blocks_sight(EntityID(1)));
+        assert!(!entities.is_player(EntityID(1)));
+        assert!(!entities.health.contains_key(&EntityID(1)));
+    }
+
+    #[test]
+    fn entities_need_no_components_to_exist() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), Blueprint::default());
+        assert!(entities.contains(EntityID(1)));
+        assert_eq!(entities.len(), 1);
+    }
+
+    #[test]
+    fn remove_drops_every_component() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), Blueprint::player(Some("Alice".into()), ORIGIN));
+        entities.remove(EntityID(1));
+        assert_eq!(entities, EntityMap::default());
+    }
+
+    #[test]
+    fn reinserting_replaces_old_components() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), Blueprint::player(Some("Alice".into()), ORIGIN));
+        entities.insert(EntityID(1), wall(ORIGIN));
+        assert!(!entities.is_player(EntityID(1)));
+        assert_eq!(entities.name_of(EntityID(1)), None);
+    }
+
+    #[test]
+    fn blocking_entities_block_their_tile() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), wall(ORIGIN));
+        assert!(entities.blocks_movement_at(ORIGIN));
+        assert!(!entities.blocks_movement_at(Point { x: 1, y: 0 }));
+    }
+
+    #[test]
+    fn players_are_found_by_name() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), Blueprint::player(Some("Alice".into()), ORIGIN));
+        entities.insert(EntityID(2), wall(ORIGIN));
+        assert_eq!(entities.player_named("Alice"), Some(EntityID(1)));
+        assert_eq!(entities.player_named("Bob"), None);
+    }
+
+    #[test]
+    fn turning_goes_round_the_compass() {
+        assert_eq!(Facing::North.turned(Turn::Left), Facing::NorthWest);
+        assert_eq!(Facing::NorthWest.turned(Turn::Right), Facing::North);
+        let mut facing = Facing::East;
+        for _ in 0..8 {
+            facing = facing.turned(Turn::Right);
+        }
+        assert_eq!(facing, Facing::East);
+        assert_eq!(
+            Facing::from(Direction::Left).delta(),
+            Direction::Left.delta()
+        );
+    }
+
+    #[test]
+    fn only_things_with_health_are_creatures() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), wall(ORIGIN));
+        assert_eq!(entities.creature_at(ORIGIN), None);
+        entities.insert(EntityID(2), Blueprint::player(Some("Alice".into()), ORIGIN));
+        assert_eq!(entities.creature_at(ORIGIN), Some(EntityID(2)));
+    }
+
+    #[test]
+    fn generation_follows_what_shapes_paths() {
+        let mut entities = EntityMap::default();
+        entities.insert(EntityID(1), Blueprint::player(Some("Alice".into()), ORIGIN));
+        let start = entities.generation();
+        entities.set_position(EntityID(1), Some(Point { x: 1, y: 0 }));
+        assert_eq!(entities.generation(), start, "players do not block");
+
+        entities.insert(EntityID(2), wall(ORIGIN));
+        let walled = entities.generation();
+        assert_ne!(walled, start);
+        entities.set_position(EntityID(2), Some(ORIGIN));
+        assert_eq!(entities.generation(), walled, "the wall stayed put");
+        entities.set_position(EntityID(2), None);
+        assert_ne!(entities.generation(), walled);
+
+        let copy = entities.clone();
+        assert_eq!(copy.generation(), entities.generation());
+        let decoded: EntityMap = bitcode::decode(&bitcode::encode(&entities)).expect("valid");
+        assert_ne!(decoded.generation(), entities.generation());
+        assert_eq!(decoded, entities);
+    }
+}
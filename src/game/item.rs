@@ -0,0 +1,479 @@
+//! Items: picking them up, carrying them in containers and wearing them.
+//!
+//! An item is an entity with an [`Item`](super::component::Item)
+//! component. It either lies on the ground, with a position, or is held by
+//! another entity, recorded in [`EntityMap::held_by`]: kept in that entity's
+//! inventory or, for characters, worn in one of its equipment slots.
+//! Containers are items with an inventory of their own, so they nest, and
+//! everything a character holds, however deep, is its possessions.
+//!
+//! The functions here check that an action makes sense, say that the item is
+//! within reach, and leave the state untouched if it does not.
+
+use super::{EntityID, EntityMap, GameState, carry, spawn};
+
+use std::fmt;
+
+/// Templates of the items every new character starts out wearing.
+pub const STARTING_KIT: [&str; 2] = ["loincloth", "pouch"];
+
+/// Why an item could not be moved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemError {
+    NotAnItem,
+    /// Only items on the actor's tile or next to it can be picked up.
+    OutOfReach,
+    NotCarried,
+    NotAContainer,
+    /// The container is too full to take the item.
+    NoRoom,
+    /// A container cannot go inside itself or anything it contains.
+    InsideItself,
+    CannotBeWorn,
+    NotWorn,
+}
+
+impl fmt::Display for ItemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAnItem => write!(f, "That cannot be carried."),
+            Self::OutOfReach => write!(f, "That is out of reach."),
+            Self::NotCarried => write!(f, "You are not carrying that."),
+            Self::NotAContainer => write!(f, "That cannot hold anything."),
+            Self::NoRoom => write!(f, "There is no room for that in there."),
+            Self::InsideItself => write!(f, "A container cannot go inside itself."),
+            Self::CannotBeWorn => write!(f, "That cannot be worn."),
+            Self::NotWorn => write!(f, "You are not wearing that."),
+        }
+    }
+}
+
+impl std::error::Error for ItemError {}
+
+/// The entity at the top of whatever holds `entity_id`: the character
+/// carrying it, the container lying on the ground it is in, or the entity
+/// itself if nothing holds it.
+pub fn owner_of(entities: &EntityMap, entity_id: EntityID) -> EntityID {
+    let mut current = entity_id;
+    // Bounded so that a corrupt save cannot hang the server
+    for _ in 0..=entities.held_by.len() {
+        match entities.held_by.get(&current) {
+            Some(&holder) => current = holder,
+            None => break,
+        }
+    }
+    current
+}
+
+/// Whether `actor` holds `item`, directly or inside a container.
+pub fn carries(entities: &EntityMap, actor: EntityID, item: EntityID) -> bool {
+    item != actor && entities.is_item(item) && owner_of(entities, item) == actor
+}
+
+/// Everything `owner` holds, worn items first, each container followed by
+/// its contents.
+pub fn possessions(entities: &EntityMap, owner: EntityID) -> Vec<EntityID> {
+    let mut found = Vec::new();
+    collect_contents(entities, owner, &mut found);
+    found
+}
+
+fn collect_contents(entities: &EntityMap, holder: EntityID, found: &mut Vec<EntityID>) {
+    let worn = entities
+        .equipment
+        .get(&holder)
+        .into_iter()
+        .flat_map(|e| e.slots.values());
+    let kept = entities
+        .inventory
+        .get(&holder)
+        .into_iter()
+        .flat_map(|i| i.items.iter());
+    for &item in worn.chain(kept) {
+        // Guards against cycles in a corrupt save
+        if !found.contains(&item) {
+            found.push(item);
+            collect_contents(entities, item, found);
+        }
+    }
+}
+
+/// Move `item` from the ground into `actor`'s inventory.
+///
+/// # Errors
+///
+/// Returns why the item cannot be picked up; nothing changes then.
+pub fn pick_up(state: &mut GameState, actor: EntityID, item: EntityID) -> Result<(), ItemError> {
+    let entities = &state.entities;
+    if !entities.is_item(item) || item == actor {
+        return Err(ItemError::NotAnItem);
+    }
+    let (Some(from), Some(at)) = (
+        entities.position().get(&actor),
+        entities.position().get(&item),
+    ) else {
+        return Err(ItemError::OutOfReach);
+    };
+    if from.chebyshev_distance(*at) > 1 || entities.projectile.contains_key(&item) {
+        return Err(ItemError::OutOfReach);
+    }
+    if !entities.inventory.contains_key(&actor) {
+        return Err(ItemError::NotAContainer);
+    }
+    state.entities.set_position(item, None);
+    put_in(&mut state.entities, actor, item);
+    Ok(())
+}
+
+/// Put `item`, which `actor` carries, on the ground at `actor`'s feet.
+///
+/// # Errors
+///
+/// Returns why the item cannot be dropped; nothing changes then.
+pub fn drop_item(state: &mut GameState, actor: EntityID, item: EntityID) -> Result<(), ItemError> {
+    if !carries(&state.entities, actor, item) {
+        return Err(ItemError::NotCarried);
+    }
+    let Some(&at) = state.entities.position().get(&actor) else {
+        return Err(ItemError::OutOfReach);
+    };
+    take_out(&mut state.entities, item);
+    state.entities.set_position(item, Some(at));
+    Ok(())
+}
+
+/// Move `item`, which `actor` carries, into `container`: a container
+/// `actor` carries, or `actor` itself.
+///
+/// # Errors
+///
+/// Returns why the item cannot go there; nothing changes then.
+pub fn store(
+    state: &mut GameState,
+    actor: EntityID,
+    item: EntityID,
+    container: EntityID,
+) -> Result<(), ItemError> {
+    let entities = &state.entities;
+    if !carries(entities, actor, item) {
+        return Err(ItemError::NotCarried);
+    }
+    if container != actor && !carries(entities, actor, container) {
+        return Err(ItemError::NotCarried);
+    }
+    if !entities.inventory.contains_key(&container) {
+        return Err(ItemError::NotAContainer);
+    }
+    if container == item || possessions(entities, item).contains(&container) {
+        return Err(ItemError::InsideItself);
+    }
+    if entities.held_by.get(&item) != Some(&container) && !carry::fits(entities, item, container) {
+        return Err(ItemError::NoRoom);
+    }
+    take_out(&mut state.entities, item);
+    put_in(&mut state.entities, container, item);
+    Ok(())
+}
+
+/// Wear `item`, which `actor` carries, in the item's slot. Whatever was worn
+/// there goes into `actor`'s inventory.
+///
+/// # Errors
+///
+/// Returns why the item cannot be worn; nothing changes then.
+pub fn equip(state: &mut GameState, actor: EntityID, item: EntityID) -> Result<(), ItemError> {
+    let entities = &state.entities;
+    if !carries(entities, actor, item) {
+        return Err(ItemError::NotCarried);
+    }
+    let Some(slot) = entities.item.get(&item).and_then(|i| i.slot) else {
+        return Err(ItemError::CannotBeWorn);
+    };
+    if !entities.equipment.contains_key(&actor) || !entities.inventory.contains_key(&actor) {
+        return Err(ItemError::CannotBeWorn);
+    }
+
+    take_out(&mut state.entities, item);
+    let entities = &mut state.entities;
+    let replaced = entities
+        .equipment
+        .get_mut(&actor)
+        .and_then(|e| e.slots.insert(slot, item));
+    entities.held_by.insert(item, actor);
+    if let Some(old) = replaced {
+        put_in(entities, actor, old);
+    }
+    Ok(())
+}
+
+/// Take off `item`, which `actor` wears, and put it in `actor`'s inventory.
+///
+/// # Errors
+///
+/// Returns why the item cannot be taken off; nothing changes then.
+pub fn unequip(state: &mut GameState, actor: EntityID, item: EntityID) -> Result<(), ItemError> {
+    let entities = &state.entities;
+    let worn = entities
+        .equipment
+        .get(&actor)
+        .is_some_and(|e| e.slots.values().any(|&eid| eid == item));
+    if !worn {
+        return Err(ItemError::NotWorn);
+    }
+    if !entities.inventory.contains_key(&actor) {
+        return Err(ItemError::NotAContainer);
+    }
+    take_out(&mut state.entities, item);
+    put_in(&mut state.entities, actor, item);
+    Ok(())
+}
+
+/// Spawn the [`STARTING_KIT`] and dress `character` in it. Items whose
+/// template the world lacks are left out; ones that cannot be worn stay in
+/// the inventory.
+pub fn give_starting_kit(state: &mut GameState, character: EntityID) {
+    for template in STARTING_KIT {
+        let Some(blueprint) = state.templates.get(template).cloned() else {
+            continue;
+        };
+        let item = spawn(state, blueprint);
+        put_in(&mut state.entities, character, item);
+        if let Err(e) = equip(state, character, item) {
+            log::warn!("Starting kit item {template} stays in the inventory: {e}");
+        }
+    }
+}
+
+/// The world as `viewer` may see it: without anything other characters
+/// carry, or the contents of containers `viewer` does not hold.
+pub fn view_for(entities: &EntityMap, viewer: Option<EntityID>) -> EntityMap {
+    let is_private = |eid: &EntityID| Some(owner_of(entities, *eid)) != viewer;
+    let mut view = entities.clone();
+
+    let hidden: Vec<EntityID> = entities
+        .held_by
+        .keys()
+        .copied()
+        .filter(is_private)
+        .collect();
+    for eid in hidden {
+        view.remove(eid);
+    }
+    view.inventory.retain(|eid, _| !is_private(eid));
+    view.equipment.retain(|eid, _| !is_private(eid));
+    view
+}
+
+/// Detach `item` from whatever holds it.
+pub(super) fn take_out(entities: &mut EntityMap, item: EntityID) {
+    let Some(holder) = entities.held_by.remove(&item) else {
+        return;
+    };
+    if let Some(inventory) = entities.inventory.get_mut(&holder) {
+        inventory.items.retain(|&eid| eid != item);
+    }
+    if let Some(equipment) = entities.equipment.get_mut(&holder) {
+        equipment.slots.retain(|_, eid| *eid != item);
+    }
+}
+
+/// Add `item`, which nothing holds, to `container`'s inventory.
+fn put_in(entities: &mut EntityMap, container: EntityID, item: EntityID) {
+    if let Some(inventory) = entities.inventory.get_mut(&container) {
+        inventory.items.push(item);
+        entities.held_by.insert(item, container);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::component::{Inventory, Item, Slot};
+    use crate::game::{Blueprint, Point, Templates, spawn_player, spawn_template};
+    use crate::testing::state_with_player;
+
+    const HERE: Point = Point { x: 10, y: 10 };
+
+    fn worn(state: &GameState, actor: EntityID, slot: Slot) -> Option<EntityID> {
+        state.entities.equipment[&actor].slots.get(&slot).copied()
+    }
+
+    fn stone(state: &mut GameState, at: Point) -> EntityID {
+        spawn_template(state, "stone", at).expect("stone is built in")
+    }
+
+    #[test]
+    fn characters_start_dressed() {
+        let (state, player) = state_with_player();
+        let loincloth = worn(&state, player, Slot::Legs).expect("wears a loincloth");
+        let pouch = worn(&state, player, Slot::Waist).expect("wears a pouch");
+
+        assert_eq!(state.entities.name_of(loincloth), Some("Loincloth"));
+        assert!(state.entities.inventory.contains_key(&pouch));
+        assert_eq!(possessions(&state.entities, player), vec![pouch, loincloth]);
+        assert!(!state.entities.position().contains_key(&pouch));
+    }
+
+    #[test]
+    fn starting_kit_needs_its_templates() {
+        let (mut state, _) = state_with_player();
+        state.templates = Templates::default();
+        let bare = spawn_player(&mut state, "Bob".into());
+        assert!(possessions(&state.entities, bare).is_empty());
+    }
+
+    #[test]
+    fn items_within_reach_can_be_picked_up() {
+        let (mut state, player) = state_with_player();
+        let near = stone(&mut state, Point { x: 11, y: 11 });
+        let far = stone(&mut state, Point { x: 12, y: 10 });
+
+        assert_eq!(pick_up(&mut state, player, far), Err(ItemError::OutOfReach));
+        assert_eq!(pick_up(&mut state, player, near), Ok(()));
+        assert!(carries(&state.entities, player, near));
+        assert!(!state.entities.position().contains_key(&near));
+        assert_eq!(
+            pick_up(&mut state, player, near),
+            Err(ItemError::OutOfReach),
+            "already picked up"
+        );
+    }
+
+    #[test]
+    fn only_items_can_be_picked_up() {
+        let (mut state, player) = state_with_player();
+        let other = spawn_player(&mut state, "Bob".into());
+        assert_eq!(
+            pick_up(&mut state, player, other),
+            Err(ItemError::NotAnItem)
+        );
+        assert_eq!(
+            pick_up(&mut state, player, player),
+            Err(ItemError::NotAnItem)
+        );
+    }
+
+    #[test]
+    fn dropped_items_land_at_the_actors_feet() {
+        let (mut state, player) = state_with_player();
+        let pouch = worn(&state, player, Slot::Waist).expect("wears a pouch");
+
+        assert_eq!(drop_item(&mut state, player, pouch), Ok(()));
+        assert_eq!(state.entities.position().get(&pouch), Some(&HERE));
+        assert_eq!(worn(&state, player, Slot::Waist), None);
+        assert_eq!(state.entities.held_by.get(&pouch), None);
+        assert_eq!(
+            drop_item(&mut state, player, pouch),
+            Err(ItemError::NotCarried)
+        );
+    }
+
+    #[test]
+    fn containers_nest() {
+        let (mut state, player) = state_with_player();
+        let pouch = worn(&state, player, Slot::Waist).expect("wears a pouch");
+        // A bag that folds up small enough for the pouch but holds anything
+        let inner = spawn(
+            &mut state,
+            Blueprint {
+                item: Some(Item {
+                    volume: 100,
+                    ..Item::default()
+                }),
+                inventory: Some(Inventory::default()),
+                ..Blueprint::default()
+            }
+            .at(HERE),
+        );
+        pick_up(&mut state, player, inner).expect("within reach");
+        let pebble = stone(&mut state, HERE);
+        pick_up(&mut state, player, pebble).expect("within reach");
+
+        assert_eq!(store(&mut state, player, pebble, inner), Ok(()));
+        assert_eq!(store(&mut state, player, inner, pouch), Ok(()));
+        assert_eq!(owner_of(&state.entities, pebble), player);
+        assert_eq!(state.entities.held_by.get(&pebble), Some(&inner));
+
+        assert_eq!(
+            store(&mut state, player, pouch, inner),
+            Err(ItemError::InsideItself)
+        );
+        assert_eq!(
+            store(&mut state, player, pouch, pouch),
+            Err(ItemError::InsideItself)
+        );
+        assert_eq!(
+            store(&mut state, player, inner, pebble),
+            Err(ItemError::NotAContainer)
+        );
+
+        // Dropping the outer pouch takes everything in it along
+        drop_item(&mut state, player, pouch).expect("carried");
+        assert!(!carries(&state.entities, player, pebble));
+        assert_eq!(owner_of(&state.entities, pebble), pouch);
+    }
+
+    #[test]
+    fn equipping_swaps_out_what_was_worn() {
+        let (mut state, player) = state_with_player();
+        let old = worn(&state, player, Slot::Waist).expect("wears a pouch");
+        let new = spawn_template(&mut state, "pouch", HERE).expect("built in");
+        pick_up(&mut state, player, new).expect("within reach");
+
+        assert_eq!(equip(&mut state, player, new), Ok(()));
+        assert_eq!(worn(&state, player, Slot::Waist), Some(new));
+        assert_eq!(state.entities.inventory[&player].items, vec![old]);
+    }
+
+    #[test]
+    fn only_wearable_items_can_be_equipped() {
+        let (mut state, player) = state_with_player();
+        let arrow = spawn_template(&mut state, "arrow", HERE).expect("arrow is built in");
+        assert_eq!(equip(&mut state, player, arrow), Err(ItemError::NotCarried));
+        pick_up(&mut state, player, arrow).expect("within reach");
+        assert_eq!(
+            equip(&mut state, player, arrow),
+            Err(ItemError::CannotBeWorn)
+        );
+    }
+
+    #[test]
+    fn unequipped_items_go_into_the_inventory() {
+        let (mut state, player) = state_with_player();
+        let loincloth = worn(&state, player, Slot::Legs).expect("wears a loincloth");
+
+        assert_eq!(unequip(&mut state, player, loincloth), Ok(()));
+        assert_eq!(worn(&state, player, Slot::Legs), None);
+        assert_eq!(state.entities.inventory[&player].items, vec![loincloth]);
+        assert_eq!(
+            unequip(&mut state, player, loincloth),
+            Err(ItemError::NotWorn)
+        );
+    }
+
+    #[test]
+    fn others_cannot_see_what_a_character_carries() {
+        let (mut state, alice) = state_with_player();
+        let bob = spawn_player(&mut state, "Bob".into());
+        let alices_pouch = worn(&state, alice, Slot::Waist).expect("wears a pouch");
+        let on_ground = stone(&mut state, HERE);
+
+        let view = view_for(&state.entities, Some(bob));
+        assert!(!view.contains(alices_pouch));
+        assert!(!view.inventory.contains_key(&alice));
+        assert!(!view.equipment.contains_key(&alice));
+        assert!(view.contains(on_ground));
+        assert_eq!(
+            possessions(&view, bob),
+            possessions(&state.entities, bob),
+            "Bob still sees his own things"
+        );
+
+        let spectator = view_for(&state.entities, None);
+        assert!(spectator.held_by.is_empty());
+    }
+}
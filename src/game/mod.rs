@@ -2,6 +2,32 @@
 //!
 //! This module contains all game state types, the [`GameAction`] enum for
 //! state mutations, and the pure [`apply`] function that advances the game.
+//! What entities are made of lives in [`component`], the kinds of entity
+//! there are in [`template`], how items are carried in [`item`], how much
+//! can be carried in [`carry`], how creatures are hurt in [`combat`], how
+//! things are shot and thrown in [`ranged`] and what bodies are made of in
+//! [`anatomy`]. What creatures can see is worked out in [`vision`], and how
+//! they find their way in [`path`]. What happens by itself as time passes is
+//! advanced by [`step`], and whatever is left to chance is drawn from
+//! [`rng`].
+
+pub mod anatomy;
+pub mod carry;
+pub mod combat;
+pub mod component;
+pub mod item;
+pub mod path;
+pub mod ranged;
+pub mod rng;
+pub mod template;
+pub mod vision;
+
+pub use anatomy::AnatomyError;
+pub use combat::CombatError;
+pub use component::{Blueprint, EntityMap, Facing};
+pub use item::ItemError;
+pub use rng::GameRng;
+pub use template::Templates;
 
 use bitcode::{Decode, Encode};
 use rustc_hash::FxHashMap;
@@ -13,8 +39,18 @@ use std::path::{Path, PathBuf};
 // Type aliases
 // ---------------------------------------------------------------------------
 
-/// Map from entity IDs to their data.
-pub type EntityMap = FxHashMap<EntityID, Entity>;
+/// Map from player accounts to their persistent data.
+pub type AccountMap = FxHashMap<AccountID, Account>;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// How far, in tiles, local speech and emotes carry.
+pub const HEARING_RADIUS: i32 = 12;
+
+/// Template whose body player characters are given.
+pub const PLAYER_SPECIES: &str = "human";
 
 // ---------------------------------------------------------------------------
 // Core value types
@@ -35,6 +71,32 @@ impl EntityGenerator {
     }
 }
 
+/// Opaque identifier of a player account.
+///
+/// The game layer does not care where it comes from; the networking layer
+/// uses the bytes of the client's public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
+pub struct AccountID(pub [u8; 32]);
+
+/// What a player account is allowed to do on the server.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode)]
+pub enum PermissionLevel {
+    #[default]
+    Player,
+    /// May run admin commands such as kicking or banning players.
+    Operator,
+}
+
+/// Persistent data about a player account, saved with the world.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Account {
+    /// Characters this account is allowed to control.
+    pub characters: Vec<EntityID>,
+    pub permission: PermissionLevel,
+    /// Banned accounts are refused when they try to connect.
+    pub banned: bool,
+}
+
 /// A 2-D point on the game grid.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode)]
 pub struct Point {
@@ -42,6 +104,26 @@ pub struct Point {
     pub y: i32,
 }
 
+impl Point {
+    /// Number of king moves between two points.
+    pub fn chebyshev_distance(self, other: Self) -> i32 {
+        (self.x - other.x).abs().max((self.y - other.y).abs())
+    }
+}
+
+/// Who gets to hear a chat message.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum ChatChannel {
+    /// Spoken aloud; heard within [`HEARING_RADIUS`].
+    Local,
+    /// An action description; seen within [`HEARING_RADIUS`].
+    Emote,
+    /// Heard only by the addressed entity.
+    Whisper(EntityID),
+    /// Heard by everyone in the world.
+    Global,
+}
+
 /// Cardinal direction for movement.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
 pub enum Direction {
@@ -63,25 +145,36 @@ impl Direction {
     }
 }
 
-/// The kind of entity.
-#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
-pub enum EntityType {
-    Player,
-    Tree,
+/// Diagonal direction for movement.
+///
+/// Kept apart from [`Direction`] because bitcode packs the tag of an enum
+/// by how many variants it has: four more would have changed how every
+/// [`GameAction::Move`] is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Diagonal {
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
 }
 
-impl EntityType {
-    pub fn blocks_sight(&self) -> bool {
-        matches!(self, Self::Tree)
+impl Diagonal {
+    /// Returns the `(dx, dy)` offset for one step in this direction.
+    pub const fn delta(self) -> (i32, i32) {
+        match self {
+            Self::UpLeft => (-1, -1),
+            Self::UpRight => (1, -1),
+            Self::DownLeft => (-1, 1),
+            Self::DownRight => (1, 1),
+        }
     }
 }
 
-/// An entity in the game world.
-#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
-pub struct Entity {
-    pub position: Point,
-    pub name: Option<String>,
-    pub entity_type: EntityType,
+/// Which way to turn, an eighth of a circle at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Turn {
+    Left,
+    Right,
 }
 
 // ---------------------------------------------------------------------------
@@ -91,11 +184,91 @@ pub struct Entity {
 /// Every possible state-mutating action that can be applied to the game.
 #[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub enum GameAction {
+    /// Step one tile, turning to face that way.
     Move(Direction),
+    /// Turn an eighth of a circle; takes as long as a step.
+    Turn(Turn),
+    /// Step one tile forwards, or backwards, without turning.
+    Walk {
+        backwards: bool,
+    },
+    /// Hit an adjacent creature.
+    Attack(EntityID),
+    /// Hit a particular part, by index in its
+    /// [`Anatomy`](anatomy::Anatomy), of an adjacent creature.
+    AttackPart {
+        target: EntityID,
+        part: usize,
+    },
+    /// Shoot or throw what is held in the main hand towards a tile.
+    Fire {
+        target: Point,
+    },
+    /// Tattoo or pierce a part of oneself or of an adjacent creature.
+    Modify {
+        target: EntityID,
+        part: usize,
+        modification: anatomy::Modification,
+    },
     SpawnPlayer(String),
     /// Networking-level: request to control an existing entity.
     SpawnAs(EntityID),
     SaveWorld,
+    Say(String),
+    Whisper {
+        target: EntityID,
+        text: String,
+    },
+    Emote(String),
+    SayGlobal(String),
+    /// Pick up an item lying within reach.
+    PickUp(EntityID),
+    /// Put a carried item on the ground.
+    Drop(EntityID),
+    /// Wear a carried item, taking off whatever was worn in its slot.
+    Equip(EntityID),
+    /// Take off a worn item and keep it in the inventory.
+    Unequip(EntityID),
+    /// Move a carried item into a carried container, or with `container`
+    /// being the actor, into the actor's own inventory.
+    Store {
+        item: EntityID,
+        container: EntityID,
+    },
+    /// Put loose items away into carried containers with room for them.
+    Organize,
+    /// Create an entity from a template; operators only.
+    SpawnTemplate {
+        template: String,
+        at: Point,
+    },
+    /// Networking-level: an admin command line such as `/kick Bob`.
+    Command(String),
+    /// Networking-level: the ticket of the server a handoff target started
+    /// with the world it was sent.
+    HandoffReady(String),
+    /// Networking-level: the handoff target cannot take the world, usually
+    /// because it is hosting one already.
+    HandoffDeclined,
+    /// Step one tile diagonally, turning to face that way. Comes last so that
+    /// the actions before it keep their encoding.
+    MoveDiagonal(Diagonal),
+}
+
+impl GameAction {
+    /// The action that steps one tile towards `facing`.
+    pub const fn step(facing: Facing) -> Self {
+        match facing {
+            Facing::North => Self::Move(Direction::Up),
+            Facing::South => Self::Move(Direction::Down),
+            Facing::West => Self::Move(Direction::Left),
+            Facing::East => Self::Move(Direction::Right),
+            Facing::NorthWest => Self::MoveDiagonal(Diagonal::UpLeft),
+            Facing::NorthEast => Self::MoveDiagonal(Diagonal::UpRight),
+            Facing::SouthWest => Self::MoveDiagonal(Diagonal::DownLeft),
+            Facing::SouthEast => Self::MoveDiagonal(Diagonal::DownRight),
+        }
+    }
 }
 
 /// Events emitted by [`apply`] so upper layers know what happened.
@@ -107,12 +280,75 @@ pub enum GameEvent {
     PlayerSpawned {
         entity_id: EntityID,
     },
+    EntitySpawned {
+        entity_id: EntityID,
+    },
+    /// An item was picked up, dropped, stored, worn or taken off.
+    ItemMoved {
+        item: EntityID,
+    },
+    Damaged {
+        entity_id: EntityID,
+        attacker: Option<EntityID>,
+        amount: u32,
+        /// The body part the blow landed on, if the entity has a body.
+        part: Option<String>,
+    },
+    /// The entity shot or threw `projectile`, which is now in flight.
+    Fired {
+        entity_id: EntityID,
+        projectile: EntityID,
+    },
+    /// A body part, and everything attached to it, is lost.
+    PartDestroyed {
+        entity_id: EntityID,
+        part: String,
+    },
+    /// A wound closed, leaving a scar if it was deep.
+    WoundHealed {
+        entity_id: EntityID,
+        part: String,
+        scarred: bool,
+    },
+    /// A body part was tattooed or pierced.
+    Modified {
+        entity_id: EntityID,
+        part: usize,
+    },
+    /// The actor tried to modify a body part but could not.
+    ModifyRefused {
+        entity_id: EntityID,
+        reason: AnatomyError,
+    },
+    /// The entity ran out of health and is now a corpse.
+    Died {
+        entity_id: EntityID,
+        killer: Option<EntityID>,
+        /// What it was called while alive.
+        name: String,
+    },
+    /// The actor tried to attack but could not.
+    AttackRefused {
+        entity_id: EntityID,
+        reason: CombatError,
+    },
+    /// The actor tried to move an item but could not.
+    ItemRefused {
+        entity_id: EntityID,
+        reason: ItemError,
+    },
     /// Upper layer should map this entity to the requesting endpoint.
     SpawnAsRequested {
         entity_id: EntityID,
     },
     /// Upper layer should trigger a world save.
     SaveRequested,
+    /// Upper layer should deliver the message to everyone who [`can_hear`] it.
+    Chat {
+        speaker: EntityID,
+        channel: ChatChannel,
+        text: String,
+    },
 }
 
 // ---------------------------------------------------------------------------
@@ -120,18 +356,29 @@ pub enum GameEvent {
 // ---------------------------------------------------------------------------
 
 /// Pure, deterministic game state — no networking handles, no UI state.
-#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
 pub struct GameState {
     pub entity_gen: EntityGenerator,
     pub entities: EntityMap,
+    pub accounts: AccountMap,
+    /// The kinds of entity that can be spawned in this world.
+    pub templates: Templates,
     pub world_name: String,
+    /// Where chance is drawn from, so that it is saved with the world.
+    pub rng: GameRng,
 }
 
 impl GameState {
-    /// Create a test world populated with a few trees.
+    /// Create a test world populated with a few trees and stones.
     pub fn create_test_world(name: String) -> Self {
-        let mut entity_gen = EntityGenerator::default();
-        let mut entities = EntityMap::default();
+        let mut state = Self {
+            entity_gen: EntityGenerator::default(),
+            entities: EntityMap::default(),
+            accounts: AccountMap::default(),
+            templates: Templates::builtin(),
+            rng: GameRng::from_text(&name),
+            world_name: name,
+        };
 
         let tree_positions = [
             Point { x: 5, y: 5 },
@@ -143,31 +390,51 @@ impl GameState {
         ];
 
         for pos in tree_positions {
-            let id = entity_gen.next();
-            entities.insert(
-                id,
-                Entity {
-                    name: None,
-                    position: pos,
-                    entity_type: EntityType::Tree,
-                },
-            );
+            spawn_template(&mut state, "tree", pos);
         }
-
-        Self {
-            entity_gen,
-            entities,
-            world_name: name,
+        for pos in [Point { x: 12, y: 9 }, Point { x: 8, y: 12 }] {
+            spawn_template(&mut state, "stone", pos);
         }
+        state
     }
 
-    /// Return IDs of all player-type entities.
+    /// Return IDs of all player characters.
     pub fn get_playable_entities(&self) -> Vec<EntityID> {
-        self.entities
-            .iter()
-            .filter(|(_, e)| e.entity_type == EntityType::Player)
-            .map(|(eid, _)| *eid)
-            .collect()
+        self.entities.player.keys().copied().collect()
+    }
+
+    /// Whether `account` is allowed to control `entity_id`.
+    pub fn owns(&self, account: &AccountID, entity_id: EntityID) -> bool {
+        self.accounts
+            .get(account)
+            .is_some_and(|a| a.characters.contains(&entity_id))
+    }
+
+    /// The permission level stored for `account`.
+    pub fn permission_of(&self, account: &AccountID) -> PermissionLevel {
+        self.accounts
+            .get(account)
+            .map(|a| a.permission)
+            .unwrap_or_default()
+    }
+
+    /// Whether `account` has been banned from this world.
+    pub fn is_banned(&self, account: &AccountID) -> bool {
+        self.accounts.get(account).is_some_and(|a| a.banned)
+    }
+
+    /// Return the IDs of all existing characters owned by `account`.
+    pub fn characters_of(&self, account: &AccountID) -> Vec<EntityID> {
+        self.accounts
+            .get(account)
+            .map(|a| {
+                a.characters
+                    .iter()
+                    .copied()
+                    .filter(|eid| self.entities.contains(*eid))
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 }
 
@@ -186,6 +453,39 @@ pub fn apply(state: &mut GameState, entity_id: EntityID, action: &GameAction) ->
             move_entity(state, entity_id, *direction);
             vec![GameEvent::EntityMoved { entity_id }]
         }
+        GameAction::MoveDiagonal(diagonal) => {
+            move_entity(state, entity_id, *diagonal);
+            vec![GameEvent::EntityMoved { entity_id }]
+        }
+        GameAction::Turn(turn) => {
+            turn_entity(state, entity_id, *turn);
+            vec![GameEvent::EntityMoved { entity_id }]
+        }
+        GameAction::Walk { backwards } => {
+            walk(state, entity_id, *backwards);
+            vec![GameEvent::EntityMoved { entity_id }]
+        }
+        GameAction::Attack(target) => combat::attack(state, entity_id, *target, None)
+            .unwrap_or_else(|reason| vec![GameEvent::AttackRefused { entity_id, reason }]),
+        GameAction::AttackPart { target, part } => {
+            combat::attack(state, entity_id, *target, Some(*part))
+                .unwrap_or_else(|reason| vec![GameEvent::AttackRefused { entity_id, reason }])
+        }
+        GameAction::Fire { target } => ranged::fire(state, entity_id, *target)
+            .unwrap_or_else(|reason| vec![GameEvent::AttackRefused { entity_id, reason }]),
+        GameAction::Modify {
+            target,
+            part,
+            modification,
+        } => vec![
+            match anatomy::modify(state, entity_id, *target, *part, modification.clone()) {
+                Ok(()) => GameEvent::Modified {
+                    entity_id: *target,
+                    part: *part,
+                },
+                Err(reason) => GameEvent::ModifyRefused { entity_id, reason },
+            },
+        ],
         GameAction::SpawnPlayer(name) => {
             let new_id = spawn_player(state, name.clone());
             vec![GameEvent::PlayerSpawned { entity_id: new_id }]
@@ -193,32 +493,217 @@ pub fn apply(state: &mut GameState, entity_id: EntityID, action: &GameAction) ->
         GameAction::SpawnAs(eid) => {
             vec![GameEvent::SpawnAsRequested { entity_id: *eid }]
         }
+        GameAction::SpawnTemplate { template, at } => spawn_template(state, template, *at)
+            .map(|entity_id| GameEvent::EntitySpawned { entity_id })
+            .into_iter()
+            .collect(),
+        GameAction::PickUp(item) => {
+            item_moved(entity_id, *item, item::pick_up(state, entity_id, *item))
+        }
+        GameAction::Drop(item) => {
+            item_moved(entity_id, *item, item::drop_item(state, entity_id, *item))
+        }
+        GameAction::Equip(item) => {
+            item_moved(entity_id, *item, item::equip(state, entity_id, *item))
+        }
+        GameAction::Unequip(item) => {
+            item_moved(entity_id, *item, item::unequip(state, entity_id, *item))
+        }
+        GameAction::Store { item, container } => item_moved(
+            entity_id,
+            *item,
+            item::store(state, entity_id, *item, *container),
+        ),
+        GameAction::Organize => carry::organize(state, entity_id)
+            .into_iter()
+            .map(|item| GameEvent::ItemMoved { item })
+            .collect(),
         GameAction::SaveWorld => {
             vec![GameEvent::SaveRequested]
         }
+        GameAction::Say(text) => chat(entity_id, ChatChannel::Local, text),
+        GameAction::Whisper { target, text } => {
+            chat(entity_id, ChatChannel::Whisper(*target), text)
+        }
+        GameAction::Emote(text) => chat(entity_id, ChatChannel::Emote, text),
+        GameAction::SayGlobal(text) => chat(entity_id, ChatChannel::Global, text),
+        // Commands and handoffs are interpreted by the server, not the game.
+        GameAction::Command(_) | GameAction::HandoffReady(_) | GameAction::HandoffDeclined => {
+            Vec::new()
+        }
+    }
+}
+
+fn item_moved(
+    entity_id: EntityID,
+    item: EntityID,
+    result: Result<(), ItemError>,
+) -> Vec<GameEvent> {
+    vec![match result {
+        Ok(()) => GameEvent::ItemMoved { item },
+        Err(reason) => GameEvent::ItemRefused { entity_id, reason },
+    }]
+}
+
+fn chat(speaker: EntityID, channel: ChatChannel, text: &str) -> Vec<GameEvent> {
+    vec![GameEvent::Chat {
+        speaker,
+        channel,
+        text: text.to_owned(),
+    }]
+}
+
+/// Whether `listener` hears what `speaker` says on `channel`.
+///
+/// Speakers always hear themselves.
+pub fn can_hear(
+    state: &GameState,
+    speaker: EntityID,
+    listener: EntityID,
+    channel: &ChatChannel,
+) -> bool {
+    if speaker == listener {
+        return true;
+    }
+    match channel {
+        ChatChannel::Global => true,
+        ChatChannel::Whisper(target) => *target == listener,
+        ChatChannel::Local | ChatChannel::Emote => {
+            let positions = &state.entities.position();
+            let (Some(from), Some(to)) = (positions.get(&speaker), positions.get(&listener)) else {
+                return false;
+            };
+            from.chebyshev_distance(*to) <= HEARING_RADIUS
+        }
     }
 }
 
-/// Spawn a new player entity and return its ID.
+/// Advance everything that changes by itself, such as healing wounds and
+/// things in flight, by one tick.
+pub fn step(state: &mut GameState) -> Vec<GameEvent> {
+    let mut events = ranged::fly(state);
+    events.extend(anatomy::heal(state));
+    events
+}
+
+/// Spawn a new player entity, with the body of a [`PLAYER_SPECIES`] and
+/// dressed in the starting kit, and return its ID.
 pub fn spawn_player(state: &mut GameState, name: String) -> EntityID {
+    let mut blueprint = Blueprint::player(Some(name), Point { x: 10, y: 10 });
+    blueprint.anatomy = state
+        .templates
+        .get(PLAYER_SPECIES)
+        .and_then(|species| species.anatomy.clone());
+    let id = spawn(state, blueprint);
+    item::give_starting_kit(state, id);
+    id
+}
+
+/// Record that `account` owns `entity_id`.
+pub fn claim_character(state: &mut GameState, account: AccountID, entity_id: EntityID) {
+    let characters = &mut state.accounts.entry(account).or_default().characters;
+    if !characters.contains(&entity_id) {
+        characters.push(entity_id);
+    }
+}
+
+/// Change the permission level of `account`.
+pub fn set_permission(state: &mut GameState, account: AccountID, permission: PermissionLevel) {
+    state.accounts.entry(account).or_default().permission = permission;
+}
+
+/// Ban or unban `account`.
+pub fn set_banned(state: &mut GameState, account: AccountID, banned: bool) {
+    state.accounts.entry(account).or_default().banned = banned;
+}
+
+/// Create a new entity from `blueprint` and return its ID.
+pub fn spawn(state: &mut GameState, blueprint: Blueprint) -> EntityID {
     let id = state.entity_gen.next();
-    state.entities.insert(
-        id,
-        Entity {
-            name: Some(name),
-            position: Point { x: 10, y: 10 },
-            entity_type: EntityType::Player,
-        },
-    );
+    state.entities.insert(id, blueprint);
     id
 }
 
-/// Move an entity one tile in the given direction.
-pub fn move_entity(state: &mut GameState, entity_id: EntityID, direction: Direction) {
-    if let Some(entity) = state.entities.get_mut(&entity_id) {
-        let (dx, dy) = direction.delta();
-        entity.position.x = entity.position.x.saturating_add(dx);
-        entity.position.y = entity.position.y.saturating_add(dy);
+/// Create an entity from the template called `template`, placed at
+/// `position`. Returns `None` if the world has no such template.
+pub fn spawn_template(state: &mut GameState, template: &str, position: Point) -> Option<EntityID> {
+    let blueprint = state.templates.get(template)?.clone().at(position);
+    Some(spawn(state, blueprint))
+}
+
+/// Move an entity straight to `position`, ignoring the tiles in between.
+pub fn teleport(state: &mut GameState, entity_id: EntityID, position: Point) {
+    if state.entities.position().contains_key(&entity_id) {
+        state.entities.set_position(entity_id, Some(position));
+    }
+}
+
+/// Mark a character as dormant (owner offline) or awake.
+pub fn set_dormant(state: &mut GameState, entity_id: EntityID, dormant: bool) {
+    if let Some(player) = state.entities.player.get_mut(&entity_id) {
+        player.dormant = dormant;
+    }
+}
+
+/// Move an entity one tile in the given direction, unless something that
+/// blocks movement is in the way. Creatures turn to face that way first.
+pub fn move_entity(state: &mut GameState, entity_id: EntityID, direction: impl Into<Facing>) {
+    let direction = direction.into();
+    if let Some(facing) = state.entities.facing.get_mut(&entity_id) {
+        *facing = direction;
+    }
+    step_by(state, entity_id, direction.delta());
+}
+
+/// Turn a creature an eighth of a circle; anything else has no front.
+pub fn turn_entity(state: &mut GameState, entity_id: EntityID, turn: Turn) {
+    if let Some(facing) = state.entities.facing.get_mut(&entity_id) {
+        *facing = facing.turned(turn);
+    }
+}
+
+/// Move a creature one tile the way it faces, or the opposite way, without
+/// turning.
+pub fn walk(state: &mut GameState, entity_id: EntityID, backwards: bool) {
+    let Some((dx, dy)) = state.entities.facing.get(&entity_id).map(|f| f.delta()) else {
+        return;
+    };
+    let delta = if backwards { (-dx, -dy) } else { (dx, dy) };
+    step_by(state, entity_id, delta);
+}
+
+/// Move an entity by `(dx, dy)`, if [`can_step`] allows it.
+fn step_by(state: &mut GameState, entity_id: EntityID, delta: (i32, i32)) {
+    let Some(&from) = state.entities.position().get(&entity_id) else {
+        return;
+    };
+    if can_step(&state.entities, from, delta) {
+        teleport(state, entity_id, offset(from, delta));
+    }
+}
+
+/// Whether something at `from` can step by `(dx, dy)`: nothing may block
+/// the tile, and a diagonal step may not squeeze between two blockers on
+/// either side of it.
+pub fn can_step(entities: &EntityMap, from: Point, delta: (i32, i32)) -> bool {
+    can_step_where(|at| entities.blocks_movement_at(at), from, delta)
+}
+
+/// Like [`can_step`], with the tiles `blocked` says are blocked.
+pub fn can_step_where(blocked: impl Fn(Point) -> bool, from: Point, (dx, dy): (i32, i32)) -> bool {
+    if blocked(offset(from, (dx, dy))) {
+        return false;
+    }
+    let squeezed =
+        dx != 0 && dy != 0 && blocked(offset(from, (dx, 0))) && blocked(offset(from, (0, dy)));
+    !squeezed
+}
+
+/// The point `(dx, dy)` away from `from`.
+pub const fn offset(from: Point, (dx, dy): (i32, i32)) -> Point {
+    Point {
+        x: from.x.saturating_add(dx),
+        y: from.y.saturating_add(dy),
     }
 }
 
@@ -226,24 +711,99 @@ pub fn move_entity(state: &mut GameState, entity_id: EntityID, direction: Direct
 // Persistence (serialization + file I/O)
 // ---------------------------------------------------------------------------
 
+/// Marks the start of every `.world` file.
+const SAVE_MAGIC: [u8; 8] = *b"GAMIKWLD";
+
+/// Version of the `.world` format, stored after [`SAVE_MAGIC`].
+///
+/// Bump it whenever the encoding of [`GameState`] changes; bitcode has no
+/// way to notice on its own, so older files would decode into garbage or
+/// not at all.
+pub const SAVE_FORMAT_VERSION: u32 = 2;
+
+/// Why a world could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file was saved in another format version, or before saves had
+    /// one if `None`.
+    Incompatible(Option<u32>),
+    /// The header is right but the world itself does not decode.
+    Corrupt(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Could not read the world: {e}"),
+            Self::Incompatible(None) => write!(
+                f,
+                "Incompatible save: the world was saved by an older version of the game."
+            ),
+            Self::Incompatible(Some(version)) => write!(
+                f,
+                "Incompatible save: the world is in format {version}, \
+                 but this version of the game reads format {SAVE_FORMAT_VERSION}."
+            ),
+            Self::Corrupt(e) => write!(f, "The world is damaged: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 /// Saves the [`GameState`] to a `.world` file in the `worlds` directory.
 pub fn save_to_file(state: &GameState) -> io::Result<()> {
     let worlds_dir = PathBuf::from("worlds");
     fs::create_dir_all(&worlds_dir)?;
 
     let file_path = worlds_dir.join(format!("{}.world", state.world_name));
-    let encoded = bitcode::encode(state);
-    fs::write(&file_path, encoded)?;
+    fs::write(&file_path, encode_save(state))?;
 
     Ok(())
 }
 
 /// Loads a [`GameState`] from a `.world` file.
-pub fn load_from_file(file_path: &Path) -> io::Result<GameState> {
-    let bytes = fs::read(file_path)?;
-    let state: GameState =
-        bitcode::decode(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-    Ok(state)
+///
+/// # Errors
+///
+/// Returns [`LoadError::Incompatible`] if the file was written in another
+/// format version, and the other variants if it cannot be read or decoded.
+pub fn load_from_file(file_path: &Path) -> Result<GameState, LoadError> {
+    decode_save(&fs::read(file_path)?)
+}
+
+/// The contents of a `.world` file holding `state`.
+pub fn encode_save(state: &GameState) -> Vec<u8> {
+    let mut bytes = Vec::from(SAVE_MAGIC);
+    bytes.extend_from_slice(&SAVE_FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&bitcode::encode(state));
+    bytes
+}
+
+/// Decode the contents of a `.world` file.
+///
+/// # Errors
+///
+/// See [`load_from_file`].
+pub fn decode_save(bytes: &[u8]) -> Result<GameState, LoadError> {
+    let Some(rest) = bytes.strip_prefix(SAVE_MAGIC.as_slice()) else {
+        return Err(LoadError::Incompatible(None));
+    };
+    let Some((version, body)) = rest.split_first_chunk::<4>() else {
+        return Err(LoadError::Corrupt("the header is cut short".to_owned()));
+    };
+    let version = u32::from_le_bytes(*version);
+    if version != SAVE_FORMAT_VERSION {
+        return Err(LoadError::Incompatible(Some(version)));
+    }
+    bitcode::decode(body).map_err(|e| LoadError::Corrupt(e.to_string()))
 }
 
 // ---------------------------------------------------------------------------
@@ -253,14 +813,7 @@ pub fn load_from_file(file_path: &Path) -> io::Result<GameState> {
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    fn empty_state() -> GameState {
-        GameState {
-            entity_gen: EntityGenerator::default(),
-            entities: EntityMap::default(),
-            world_name: "test".into(),
-        }
-    }
+    use crate::testing::empty_state;
 
     // -- spawn_player --------------------------------------------------------
 
@@ -269,11 +822,10 @@ mod tests {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "Alice".into());
 
-        assert!(state.entities.contains_key(&id));
-        let entity = &state.entities[&id];
-        assert_eq!(entity.name, Some("Alice".into()));
-        assert_eq!(entity.entity_type, EntityType::Player);
-        assert_eq!(entity.position, Point { x: 10, y: 10 });
+        assert!(state.entities.contains(id));
+        assert_eq!(state.entities.name_of(id), Some("Alice"));
+        assert!(state.entities.is_player(id));
+        assert_eq!(state.entities.position()[&id], Point { x: 10, y: 10 });
     }
 
     #[test]
@@ -290,11 +842,11 @@ mod tests {
     fn move_entity_up() {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "P".into());
-        let start = state.entities[&id].position;
+        let start = state.entities.position()[&id];
 
         move_entity(&mut state, id, Direction::Up);
         assert_eq!(
-            state.entities[&id].position,
+            state.entities.position()[&id],
             Point {
                 x: start.x,
                 y: start.y - 1
@@ -306,11 +858,11 @@ mod tests {
     fn move_entity_down() {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "P".into());
-        let start = state.entities[&id].position;
+        let start = state.entities.position()[&id];
 
         move_entity(&mut state, id, Direction::Down);
         assert_eq!(
-            state.entities[&id].position,
+            state.entities.position()[&id],
             Point {
                 x: start.x,
                 y: start.y + 1
@@ -322,11 +874,11 @@ mod tests {
     fn move_entity_left() {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "P".into());
-        let start = state.entities[&id].position;
+        let start = state.entities.position()[&id];
 
         move_entity(&mut state, id, Direction::Left);
         assert_eq!(
-            state.entities[&id].position,
+            state.entities.position()[&id],
             Point {
                 x: start.x - 1,
                 y: start.y
@@ -338,11 +890,11 @@ mod tests {
     fn move_entity_right() {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "P".into());
-        let start = state.entities[&id].position;
+        let start = state.entities.position()[&id];
 
         move_entity(&mut state, id, Direction::Right);
         assert_eq!(
-            state.entities[&id].position,
+            state.entities.position()[&id],
             Point {
                 x: start.x + 1,
                 y: start.y
@@ -350,6 +902,46 @@ mod tests {
         );
     }
 
+    #[test]
+    fn move_entity_diagonally() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+
+        move_entity(&mut state, id, Diagonal::UpRight);
+        assert_eq!(state.entities.position()[&id], Point { x: 11, y: 9 });
+        assert_eq!(state.entities.facing[&id], Facing::NorthEast);
+        apply(
+            &mut state,
+            id,
+            &GameAction::MoveDiagonal(Diagonal::DownLeft),
+        );
+        assert_eq!(state.entities.position()[&id], Point { x: 10, y: 10 });
+        assert_eq!(
+            Facing::ALL
+                .map(GameAction::step)
+                .iter()
+                .filter(|a| matches!(a, GameAction::MoveDiagonal(_)))
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn diagonal_steps_cannot_squeeze_between_blockers() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        spawn_template(&mut state, "tree", Point { x: 11, y: 10 });
+
+        // One blocker beside the way can be slipped past...
+        move_entity(&mut state, id, Diagonal::DownRight);
+        assert_eq!(state.entities.position()[&id], Point { x: 11, y: 11 });
+
+        // ...but not two on either side of it
+        spawn_template(&mut state, "tree", Point { x: 12, y: 11 });
+        move_entity(&mut state, id, Diagonal::UpRight);
+        assert_eq!(state.entities.position()[&id], Point { x: 11, y: 11 });
+    }
+
     #[test]
     fn move_nonexistent_entity_is_noop() {
         let mut state = empty_state();
@@ -358,20 +950,55 @@ mod tests {
         assert_eq!(state, before);
     }
 
+    // -- facing --------------------------------------------------------------
+
+    #[test]
+    fn moving_turns_creatures_to_face_the_way_they_go() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        assert_eq!(state.entities.facing[&id], Facing::South);
+        move_entity(&mut state, id, Direction::Left);
+        assert_eq!(state.entities.facing[&id], Facing::West);
+    }
+
+    #[test]
+    fn walking_follows_the_facing() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        apply(&mut state, id, &GameAction::Turn(Turn::Left));
+        assert_eq!(state.entities.facing[&id], Facing::SouthEast);
+        apply(&mut state, id, &GameAction::Walk { backwards: false });
+        assert_eq!(state.entities.position()[&id], Point { x: 11, y: 11 });
+        apply(&mut state, id, &GameAction::Walk { backwards: true });
+        apply(&mut state, id, &GameAction::Walk { backwards: true });
+        assert_eq!(state.entities.position()[&id], Point { x: 9, y: 9 });
+        assert_eq!(state.entities.facing[&id], Facing::SouthEast);
+    }
+
+    #[test]
+    fn things_without_a_front_do_not_walk() {
+        let mut state = empty_state();
+        let tree = spawn_template(&mut state, "tree", Point { x: 0, y: 0 }).expect("built in");
+        let before = state.clone();
+        turn_entity(&mut state, tree, Turn::Right);
+        walk(&mut state, tree, false);
+        assert_eq!(state, before);
+    }
+
     #[test]
     fn move_allows_negative_coordinates() {
         let mut state = empty_state();
         let id = spawn_player(&mut state, "P".into());
         // Place entity at origin
-        state.entities.get_mut(&id).expect("just spawned").position = Point { x: 0, y: 0 };
+        teleport(&mut state, id, Point { x: 0, y: 0 });
 
         // i32::saturating_sub(1) allows going below zero (saturates at i32::MIN)
         move_entity(&mut state, id, Direction::Up);
-        assert_eq!(state.entities[&id].position, Point { x: 0, y: -1 });
+        assert_eq!(state.entities.position()[&id], Point { x: 0, y: -1 });
 
-        state.entities.get_mut(&id).expect("exists").position = Point { x: 0, y: 0 };
+        teleport(&mut state, id, Point { x: 0, y: 0 });
         move_entity(&mut state, id, Direction::Left);
-        assert_eq!(state.entities[&id].position, Point { x: -1, y: 0 });
+        assert_eq!(state.entities.position()[&id], Point { x: -1, y: 0 });
     }
 
     // -- apply ---------------------------------------------------------------
@@ -395,7 +1022,7 @@ mod tests {
         assert_eq!(events.len(), 1);
         match &events[0] {
             GameEvent::PlayerSpawned { entity_id } => {
-                assert!(state.entities.contains_key(entity_id));
+                assert!(state.entities.contains(*entity_id));
             }
             other => panic!("expected PlayerSpawned, got {other:?}"),
         }
@@ -420,6 +1047,104 @@ mod tests {
         );
     }
 
+    #[test]
+    fn apply_say_returns_local_chat_event() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        let before = state.clone();
+
+        let events = apply(&mut state, id, &GameAction::Say("hello".into()));
+
+        assert_eq!(
+            events,
+            vec![GameEvent::Chat {
+                speaker: id,
+                channel: ChatChannel::Local,
+                text: "hello".into(),
+            }]
+        );
+        assert_eq!(state, before);
+    }
+
+    #[test]
+    fn apply_whisper_targets_recipient() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        let events = apply(
+            &mut state,
+            id,
+            &GameAction::Whisper {
+                target: EntityID(7),
+                text: "psst".into(),
+            },
+        );
+        assert_eq!(
+            events,
+            vec![GameEvent::Chat {
+                speaker: id,
+                channel: ChatChannel::Whisper(EntityID(7)),
+                text: "psst".into(),
+            }]
+        );
+    }
+
+    // -- can_hear ------------------------------------------------------------
+
+    fn two_players_apart(distance: i32) -> (GameState, EntityID, EntityID) {
+        let mut state = empty_state();
+        let a = spawn_player(&mut state, "A".into());
+        let b = spawn_player(&mut state, "B".into());
+        teleport(
+            &mut state,
+            b,
+            Point {
+                x: 10 + distance,
+                y: 10,
+            },
+        );
+        (state, a, b)
+    }
+
+    #[test]
+    fn local_speech_carries_within_hearing_radius() {
+        let (state, a, b) = two_players_apart(HEARING_RADIUS);
+        assert!(can_hear(&state, a, b, &ChatChannel::Local));
+        assert!(can_hear(&state, a, b, &ChatChannel::Emote));
+    }
+
+    #[test]
+    fn local_speech_does_not_carry_beyond_hearing_radius() {
+        let (state, a, b) = two_players_apart(HEARING_RADIUS + 1);
+        assert!(!can_hear(&state, a, b, &ChatChannel::Local));
+        assert!(!can_hear(&state, a, b, &ChatChannel::Emote));
+    }
+
+    #[test]
+    fn global_chat_carries_everywhere() {
+        let (state, a, b) = two_players_apart(1000);
+        assert!(can_hear(&state, a, b, &ChatChannel::Global));
+    }
+
+    #[test]
+    fn whispers_reach_only_their_target() {
+        let mut state = empty_state();
+        let a = spawn_player(&mut state, "A".into());
+        let b = spawn_player(&mut state, "B".into());
+        let c = spawn_player(&mut state, "C".into());
+
+        let channel = ChatChannel::Whisper(b);
+        assert!(can_hear(&state, a, b, &channel));
+        assert!(!can_hear(&state, a, c, &channel));
+        assert!(can_hear(&state, a, a, &channel));
+    }
+
+    #[test]
+    fn chebyshev_distance_counts_diagonals_as_one() {
+        let origin = Point { x: 0, y: 0 };
+        assert_eq!(origin.chebyshev_distance(Point { x: 3, y: -3 }), 3);
+        assert_eq!(origin.chebyshev_distance(Point { x: -1, y: 5 }), 5);
+    }
+
     // -- determinism ---------------------------------------------------------
 
     #[test]
@@ -448,12 +1173,8 @@ mod tests {
     #[test]
     fn create_test_world_has_trees() {
         let state = GameState::create_test_world("w".into());
-        let tree_count = state
-            .entities
-            .values()
-            .filter(|e| e.entity_type == EntityType::Tree)
-            .count();
-        assert_eq!(tree_count, 6);
+        assert_eq!(state.entities.blocks_sight.len(), 6);
+        assert!(state.get_playable_entities().is_empty());
     }
 
     // -- get_playable_entities -----------------------------------------------
@@ -469,15 +1190,214 @@ mod tests {
         assert!(playable.contains(&pid));
     }
 
-    // -- entity_type ---------------------------------------------------------
+    // -- accounts ------------------------------------------------------------
+
+    #[test]
+    fn claimed_characters_are_owned() {
+        let mut state = empty_state();
+        let alice = AccountID([1; 32]);
+        let bob = AccountID([2; 32]);
+        let pid = spawn_player(&mut state, "Alice".into());
+
+        claim_character(&mut state, alice, pid);
+
+        assert!(state.owns(&alice, pid));
+        assert!(!state.owns(&bob, pid));
+        assert_eq!(state.characters_of(&alice), vec![pid]);
+        assert!(state.characters_of(&bob).is_empty());
+    }
+
+    #[test]
+    fn claim_character_is_idempotent() {
+        let mut state = empty_state();
+        let alice = AccountID([1; 32]);
+        let pid = spawn_player(&mut state, "Alice".into());
+
+        claim_character(&mut state, alice, pid);
+        claim_character(&mut state, alice, pid);
+
+        assert_eq!(state.accounts[&alice].characters, vec![pid]);
+    }
+
+    #[test]
+    fn characters_of_skips_removed_entities() {
+        let mut state = empty_state();
+        let alice = AccountID([1; 32]);
+        let pid = spawn_player(&mut state, "Alice".into());
+        claim_character(&mut state, alice, pid);
+
+        state.entities.remove(pid);
+
+        assert!(state.characters_of(&alice).is_empty());
+    }
+
+    // -- dormancy ------------------------------------------------------------
+
+    #[test]
+    fn spawned_players_are_awake() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        assert!(!state.entities.is_dormant(id));
+    }
+
+    #[test]
+    fn set_dormant_toggles_flag() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+
+        set_dormant(&mut state, id, true);
+        assert!(state.entities.is_dormant(id));
+
+        set_dormant(&mut state, id, false);
+        assert!(!state.entities.is_dormant(id));
+    }
+
+    // -- administration ------------------------------------------------------
+
+    #[test]
+    fn accounts_default_to_player_permission() {
+        let state = empty_state();
+        assert_eq!(
+            state.permission_of(&AccountID([1; 32])),
+            PermissionLevel::Player
+        );
+    }
+
+    #[test]
+    fn set_permission_creates_account_if_needed() {
+        let mut state = empty_state();
+        let account = AccountID([1; 32]);
+
+        set_permission(&mut state, account, PermissionLevel::Operator);
+
+        assert_eq!(state.permission_of(&account), PermissionLevel::Operator);
+    }
+
+    #[test]
+    fn set_banned_toggles_ban() {
+        let mut state = empty_state();
+        let account = AccountID([1; 32]);
+
+        set_banned(&mut state, account, true);
+        assert!(state.is_banned(&account));
+
+        set_banned(&mut state, account, false);
+        assert!(!state.is_banned(&account));
+    }
+
+    #[test]
+    fn teleport_moves_entity_anywhere() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        let target = Point { x: -40, y: 300 };
+
+        teleport(&mut state, id, target);
+
+        assert_eq!(state.entities.position()[&id], target);
+    }
+
+    #[test]
+    fn spawn_places_unnamed_entity() {
+        let mut state = empty_state();
+        let at = Point { x: 3, y: 4 };
+
+        let id = spawn(&mut state, Blueprint::default().at(at));
+
+        assert_eq!(state.entities.position()[&id], at);
+        assert_eq!(state.entities.name_of(id), None);
+        assert!(!state.entities.is_player(id));
+    }
+
+    #[test]
+    fn templates_spawn_where_asked() {
+        let mut state = empty_state();
+        let at = Point { x: -2, y: 7 };
+
+        let events = apply(
+            &mut state,
+            EntityID(0),
+            &GameAction::SpawnTemplate {
+                template: "sheep".into(),
+                at,
+            },
+        );
+
+        let [GameEvent::EntitySpawned { entity_id }] = events.as_slice() else {
+            panic!("expected EntitySpawned, got {events:?}");
+        };
+        assert_eq!(state.entities.position()[entity_id], at);
+        assert_eq!(state.entities.name_of(*entity_id), Some("Sheep"));
+    }
+
+    #[test]
+    fn unknown_templates_spawn_nothing() {
+        let mut state = empty_state();
+        assert_eq!(
+            spawn_template(&mut state, "dragon", Point { x: 0, y: 0 }),
+            None
+        );
+        assert!(state.entities.is_empty());
+    }
+
+    // -- components ----------------------------------------------------------
 
     #[test]
     fn tree_blocks_sight() {
-        assert!(EntityType::Tree.blocks_sight());
+        let mut state = empty_state();
+        let id =
+            spawn_template(&mut state, "tree", Point { x: 0, y: 0 }).expect("tree is built in");
+        assert!(state.entities.blocks_sight(id));
     }
 
     #[test]
     fn player_does_not_block_sight() {
-        assert!(!EntityType::Player.blocks_sight());
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        assert!(!state.entities.blocks_sight(id));
+    }
+
+    #[test]
+    fn moves_into_blocking_entities_are_refused() {
+        let mut state = empty_state();
+        let id = spawn_player(&mut state, "P".into());
+        spawn_template(&mut state, "tree", Point { x: 11, y: 10 }).expect("tree is built in");
+
+        move_entity(&mut state, id, Direction::Right);
+        assert_eq!(state.entities.position()[&id], Point { x: 10, y: 10 });
+    }
+
+    #[test]
+    fn players_do_not_block_each_other() {
+        let mut state = empty_state();
+        let a = spawn_player(&mut state, "A".into());
+        let b = spawn_player(&mut state, "B".into());
+        teleport(&mut state, b, Point { x: 11, y: 10 });
+
+        move_entity(&mut state, a, Direction::Right);
+        assert_eq!(state.entities.position()[&a], Point { x: 11, y: 10 });
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let mut state = empty_state();
+        spawn_player(&mut state, "P".into());
+        let decoded = decode_save(&encode_save(&state)).expect("just saved");
+        assert_eq!(decoded, state);
+    }
+
+    #[test]
+    fn saves_from_other_versions_are_refused() {
+        let state = empty_state();
+        assert!(matches!(
+            decode_save(&bitcode::encode(&state)),
+            Err(LoadError::Incompatible(None))
+        ));
+
+        let newer_version = SAVE_FORMAT_VERSION + 1;
+        let mut newer = encode_save(&state);
+        newer[SAVE_MAGIC.len()..][..4].copy_from_slice(&newer_version.to_le_bytes());
+        let error = decode_save(&newer).expect_err("that format is not known yet");
+        assert!(matches!(error, LoadError::Incompatible(Some(v)) if v == newer_version));
+        assert!(error.to_string().starts_with("Incompatible save"));
     }
 }
@@ -0,0 +1,523 @@
+//! Finding the way.
+//!
+//! Pathfinding works on a [`PathGrid`], a snapshot of which tiles can be
+//! entered, and at what cost, taken from the entities in the world. Entering
+//! a tile costs [`NORMAL_COST`] unless something lying on it has a
+//! [`move_cost`](super::component::Blueprint::move_cost); a diagonal step
+//! costs the same as a straight one, as it takes as long in the game, but may
+//! not squeeze between two blockers.
+//!
+//! [`find_path`] runs A* from one place to another, as for click-to-move. A
+//! [`DijkstraMap`] instead holds, for every tile around some goals, the cost
+//! of reaching the nearest one, so that any number of creatures can
+//! [`approach`](DijkstraMap::approach) or [`flee`](DijkstraMap::flee) them by
+//! rolling downhill from wherever they are. The world has no edge, so every
+//! search is bounded: [`find_path`] gives up after expanding [`MAX_SEARCH`]
+//! tiles, and a map covers only the [`Bounds`] it is made for.
+//!
+//! Grids and maps are expensive to build, so a [`PathCache`] keeps them, and
+//! the paths found on them, until an entity that blocks movement or changes
+//! the cost of its tile moves, appears or disappears.
+
+use super::{EntityID, EntityMap, Facing, Point, can_step_where, offset};
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Cost of entering a tile nothing makes harder, or easier, to cross.
+pub const NORMAL_COST: u32 = 100;
+
+/// Tiles [`find_path`] expands before deciding there is no way.
+pub const MAX_SEARCH: usize = 100_000;
+
+/// How much further than the threats fleeing creatures look for safety, in
+/// percent; more makes them run past a threat to reach open ground.
+const FLEE_FACTOR: i64 = 120;
+
+/// The ways out of a tile, straight ones first so that ties between equally
+/// good steps go their way.
+const STEPS: [Facing; 8] = [
+    Facing::North,
+    Facing::South,
+    Facing::West,
+    Facing::East,
+    Facing::NorthWest,
+    Facing::NorthEast,
+    Facing::SouthWest,
+    Facing::SouthEast,
+];
+
+/// Paths, or maps, a [`PathCache`] keeps before starting afresh.
+const MAX_CACHED: usize = 1024;
+
+/// Which tiles can be entered, and at what cost.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PathGrid {
+    blocked: FxHashSet<Point>,
+    /// Tiles that cost other than [`NORMAL_COST`] to enter.
+    costs: FxHashMap<Point, u32>,
+    /// The lowest cost of entering any tile, which keeps A*'s estimates from
+    /// overshooting.
+    cheapest: u32,
+}
+
+impl PathGrid {
+    /// The grid as the entities make it now; the costliest thing lying on a
+    /// tile sets its cost.
+    pub fn new(entities: &EntityMap) -> Self {
+        let position = |eid: &EntityID| entities.position().get(eid).copied();
+        let blocked = entities
+            .movement_blockers()
+            .iter()
+            .filter_map(position)
+            .collect();
+        let mut costs: FxHashMap<Point, u32> = FxHashMap::default();
+        #[expect(
+            clippy::iter_over_hash_type,
+            reason = "the costliest thing on a tile counts, whatever the order"
+        )]
+        for (eid, &cost) in entities.move_costs() {
+            if let Some(at) = position(eid) {
+                let tile = costs.entry(at).or_insert(cost);
+                *tile = (*tile).max(cost);
+            }
+        }
+        let cheapest = costs.values().copied().fold(NORMAL_COST, u32::min).max(1);
+        Self {
+            blocked,
+            costs,
+            cheapest,
+        }
+    }
+
+    pub fn is_blocked(&self, at: Point) -> bool {
+        self.blocked.contains(&at)
+    }
+
+    /// What entering `at` costs.
+    pub fn cost(&self, at: Point) -> u32 {
+        self.costs.get(&at).copied().unwrap_or(NORMAL_COST)
+    }
+
+    /// The tiles that can be stepped to from `from`, and the way to each.
+    pub fn neighbours(&self, from: Point) -> impl Iterator<Item = (Facing, Point)> + '_ {
+        STEPS
+            .into_iter()
+            .filter(move |direction| {
+                can_step_where(|at| self.is_blocked(at), from, direction.delta())
+            })
+            .map(move |direction| (direction, offset(from, direction.delta())))
+    }
+}
+
+/// The cheapest way from `from` to `to`, as the tiles to step on in order,
+/// ending with `to`; empty if already there. `None` if `to` is blocked or
+/// cannot be reached within [`MAX_SEARCH`] tiles.
+pub fn find_path(grid: &PathGrid, from: Point, to: Point) -> Option<Vec<Point>> {
+    if from == to {
+        return Some(Vec::new());
+    }
+    if grid.is_blocked(to) {
+        return None;
+    }
+    let estimate =
+        |at: Point| u64::from(grid.cheapest) * u64::from(at.chebyshev_distance(to).unsigned_abs());
+    let mut spent: FxHashMap<Point, u64> = FxHashMap::default();
+    let mut came_from: FxHashMap<Point, Point> = FxHashMap::default();
+    // Ties go to whatever is estimated closest to the goal, then by position
+    let mut open = BinaryHeap::new();
+    spent.insert(from, 0);
+    open.push(Reverse((estimate(from), estimate(from), from.x, from.y)));
+
+    let mut expanded = 0;
+    while let Some(Reverse((total, left, x, y))) = open.pop() {
+        let at = Point { x, y };
+        if at == to {
+            return Some(walk_back(&came_from, from, to));
+        }
+        let cost = total - left;
+        if spent.get(&at).is_some_and(|&best| cost > best) {
+            // Found a cheaper way here since this was queued
+            continue;
+        }
+        expanded += 1;
+        if expanded > MAX_SEARCH {
+            return None;
+        }
+        for (_, next) in grid.neighbours(at) {
+            let cost = cost + u64::from(grid.cost(next));
+            if spent.get(&next).is_none_or(|&best| cost < best) {
+                spent.insert(next, cost);
+                came_from.insert(next, at);
+                let left = estimate(next);
+                open.push(Reverse((cost + left, left, next.x, next.y)));
+            }
+        }
+    }
+    None
+}
+
+fn walk_back(came_from: &FxHashMap<Point, Point>, from: Point, to: Point) -> Vec<Point> {
+    let mut path = vec![to];
+    let mut at = to;
+    while let Some(&previous) = came_from.get(&at) {
+        if previous == from {
+            break;
+        }
+        path.push(previous);
+        at = previous;
+    }
+    path.reverse();
+    path
+}
+
+/// The rectangle of tiles from `min` to `max`, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Bounds {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Bounds {
+    /// The tiles no more than `radius` steps from `center`.
+    pub const fn around(center: Point, radius: i32) -> Self {
+        Self {
+            min: offset(center, (-radius, -radius)),
+            max: offset(center, (radius, radius)),
+        }
+    }
+
+    pub const fn contains(self, at: Point) -> bool {
+        self.min.x <= at.x && at.x <= self.max.x && self.min.y <= at.y && at.y <= self.max.y
+    }
+}
+
+/// For every tile in some [`Bounds`], how costly it is to get from there to
+/// the nearest of some goals. Creatures move towards the goals by stepping
+/// to ever lower values; one map serves all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DijkstraMap {
+    values: FxHashMap<Point, i64>,
+}
+
+impl DijkstraMap {
+    /// A map leading to the nearest of `goals`. Goals may be blocked, like a
+    /// chest to be opened; the way then leads next to them.
+    pub fn approach(grid: &PathGrid, goals: &[Point], bounds: Bounds) -> Self {
+        let seeds = goals
+            .iter()
+            .filter(|goal| bounds.contains(**goal))
+            .map(|&goal| (goal, 0))
+            .collect();
+        Self::scan(grid, seeds, bounds)
+    }
+
+    /// A map leading away from `threats`. Rather than simply uphill, which
+    /// ends in corners, it leads to wherever is furthest from them while
+    /// counting the way there, so fleeing creatures prefer open ground.
+    pub fn flee(grid: &PathGrid, threats: &[Point], bounds: Bounds) -> Self {
+        let towards = Self::approach(grid, threats, bounds);
+        let seeds = towards
+            .values
+            .iter()
+            .map(|(&at, &value)| (at, -value * FLEE_FACTOR / 100))
+            .collect();
+        Self::scan(grid, seeds, bounds)
+    }
+
+    /// Spread the `seeds` out over `bounds`, each tile taking the lowest
+    /// value any neighbour offers plus the cost of stepping from it to that
+    /// neighbour.
+    fn scan(grid: &PathGrid, seeds: Vec<(Point, i64)>, bounds: Bounds) -> Self {
+        let mut values: FxHashMap<Point, i64> = FxHashMap::default();
+        let mut open = BinaryHeap::new();
+        for (at, value) in seeds {
+            if values.get(&at).is_none_or(|&old| value < old) {
+                values.insert(at, value);
+                open.push(Reverse((value, at.x, at.y)));
+            }
+        }
+        while let Some(Reverse((value, x, y))) = open.pop() {
+            let at = Point { x, y };
+            if values.get(&at).is_some_and(|&best| value > best) {
+                continue;
+            }
+            // Whoever comes here from a neighbour pays for entering this tile
+            let through = value + i64::from(grid.cost(at));
+            for (_, next) in grid.neighbours(at) {
+                if bounds.contains(next) && values.get(&next).is_none_or(|&old| through < old) {
+                    values.insert(next, through);
+                    open.push(Reverse((through, next.x, next.y)));
+                }
+            }
+        }
+        Self { values }
+    }
+
+    /// The value of `at`; `None` outside the map or where it cannot lead.
+    pub fn value(&self, at: Point) -> Option<i64> {
+        self.values.get(&at).copied()
+    }
+
+    /// Which way to step from `from` to the lowest neighbouring value, if
+    /// any is lower than that of `from` itself.
+    pub fn next_step(&self, grid: &PathGrid, from: Point) -> Option<Facing> {
+        let here = self.value(from).unwrap_or(i64::MAX);
+        grid.neighbours(from)
+            .filter_map(|(direction, next)| Some((self.value(next)?, direction)))
+            .filter(|(value, _)| *value < here)
+            .min_by_key(|(value, _)| *value)
+            .map(|(_, direction)| direction)
+    }
+}
+
+/// What a [`DijkstraMap`] leads to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Goal {
+    Approach,
+    Flee,
+}
+
+/// Keeps a [`PathGrid`], and the paths and maps found on it, for as long as
+/// the entities that shape it stay put.
+///
+/// Whether they did is told by [`EntityMap::generation`], so checking is as
+/// quick as comparing two numbers; if they did not, everything is worked out
+/// again.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    /// The generation of the entities the grid was built from; `None` before
+    /// it first is.
+    generation: Option<u64>,
+    grid: PathGrid,
+    paths: FxHashMap<(Point, Point), Option<Vec<Point>>>,
+    maps: FxHashMap<(Goal, Vec<Point>, Bounds), DijkstraMap>,
+}
+
+impl PathCache {
+    /// The grid as the entities make it now.
+    pub fn grid(&mut self, entities: &EntityMap) -> &PathGrid {
+        self.refresh(entities);
+        &self.grid
+    }
+
+    /// [`find_path`] on the grid as the entities make it now.
+    pub fn find_path(
+        &mut self,
+        entities: &EntityMap,
+        from: Point,
+        to: Point,
+    ) -> Option<Vec<Point>> {
+        self.refresh(entities);
+        if self.paths.len() >= MAX_CACHED {
+            self.paths.clear();
+        }
+        let grid = &self.grid;
+        self.paths
+            .entry((from, to))
+            .or_insert_with(|| find_path(grid, from, to))
+            .clone()
+    }
+
+    /// [`DijkstraMap::approach`] on the grid as the entities make it now.
+    pub fn approach(
+        &mut self,
+        entities: &EntityMap,
+        goals: &[Point],
+        bounds: Bounds,
+    ) -> &DijkstraMap {
+        self.map(entities, Goal::Approach, goals, bounds)
+    }
+
+    /// [`DijkstraMap::flee`] on the grid as the entities make it now.
+    pub fn flee(
+        &mut self,
+        entities: &EntityMap,
+        threats: &[Point],
+        bounds: Bounds,
+    ) -> &DijkstraMap {
+        self.map(entities, Goal::Flee, threats, bounds)
+    }
+
+    fn map(
+        &mut self,
+        entities: &EntityMap,
+        goal: Goal,
+        points: &[Point],
+        bounds: Bounds,
+    ) -> &DijkstraMap {
+        self.refresh(entities);
+        if self.maps.len() >= MAX_CACHED {
+            self.maps.clear();
+        }
+        let mut points = points.to_vec();
+        points.sort_unstable_by_key(|at| (at.x, at.y));
+        points.dedup();
+        let grid = &self.grid;
+        self.maps
+            .entry((goal, points, bounds))
+            .or_insert_with_key(|(goal, points, bounds)| match goal {
+                Goal::Approach => DijkstraMap::approach(grid, points, *bounds),
+                Goal::Flee => DijkstraMap::flee(grid, points, *bounds),
+            })
+    }
+
+    /// Start afresh if the entities that shape the grid changed.
+    fn refresh(&mut self, entities: &EntityMap) {
+        let generation = entities.generation();
+        if self.generation != Some(generation) {
+            self.grid = PathGrid::new(entities);
+            self.paths.clear();
+            self.maps.clear();
+            self.generation = Some(generation);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Blueprint, GameState, spawn, spawn_template, teleport};
+    use crate::testing::empty_state;
+
+    const ORIGIN: Point = Point { x: 0, y: 0 };
+
+    fn wall(state: &mut GameState, x: i32, y: i32) -> EntityID {
+        spawn(
+            state,
+            Blueprint {
+                position: Some(Point { x, y }),
+                blocks_movement: true,
+                ..Blueprint::default()
+            },
+        )
+    }
+
+    /// Whether every step of `path`, starting at `from`, is one `grid` allows.
+    fn walkable(grid: &PathGrid, from: Point, path: &[Point]) -> bool {
+        let mut at = from;
+        path.iter().all(|&next| {
+            let allowed = grid.neighbours(at).any(|(_, to)| to == next);
+            at = next;
+            allowed
+        })
+    }
+
+    #[test]
+    fn open_ground_is_crossed_in_a_straight_line() {
+        let grid = PathGrid::new(&EntityMap::default());
+        let to = Point { x: 5, y: 3 };
+        let path = find_path(&grid, ORIGIN, to).expect("nothing is in the way");
+        assert_eq!(path.len(), 5);
+        assert_eq!(path.last(), Some(&to));
+        assert!(walkable(&grid, ORIGIN, &path));
+        assert_eq!(find_path(&grid, ORIGIN, ORIGIN), Some(Vec::new()));
+    }
+
+    #[test]
+    fn paths_go_round_walls_without_squeezing_through_corners() {
+        let mut state = empty_state();
+        for y in -3..=3 {
+            wall(&mut state, 2, y);
+        }
+        // A diagonal gap between two walls is no way through
+        wall(&mut state, 1, 4);
+        let grid = PathGrid::new(&state.entities);
+        let path = find_path(&grid, ORIGIN, Point { x: 4, y: 0 }).expect("there is a way round");
+        assert!(walkable(&grid, ORIGIN, &path));
+        assert!(path.iter().all(|at| !grid.is_blocked(*at)));
+        assert!(path.iter().any(|at| at.y < -3));
+    }
+
+    #[test]
+    fn mud_is_walked_round_if_that_is_cheaper() {
+        let mut state = empty_state();
+        for y in -1..=1 {
+            spawn_template(&mut state, "mud", Point { x: 2, y }).expect("mud is built in");
+        }
+        let grid = PathGrid::new(&state.entities);
+        assert_eq!(grid.cost(Point { x: 2, y: 0 }), 300);
+        let path = find_path(&grid, ORIGIN, Point { x: 4, y: 0 }).expect("there is a way");
+        assert!(path.iter().all(|at| grid.cost(*at) == NORMAL_COST));
+        assert_eq!(path.len(), 4);
+    }
+
+    #[test]
+    fn walled_in_goals_cannot_be_reached() {
+        let mut state = empty_state();
+        let goal = Point { x: 10, y: 10 };
+        for facing in Facing::ALL {
+            let (dx, dy) = facing.delta();
+            wall(&mut state, goal.x + dx, goal.y + dy);
+        }
+        let grid = PathGrid::new(&state.entities);
+        assert_eq!(find_path(&grid, goal, ORIGIN), None);
+        assert_eq!(find_path(&grid, ORIGIN, Point { x: 11, y: 10 }), None);
+    }
+
+    #[test]
+    fn creatures_roll_down_to_the_goal() {
+        let mut state = empty_state();
+        for y in -3..=3 {
+            wall(&mut state, 2, y);
+        }
+        let grid = PathGrid::new(&state.entities);
+        let goal = Point { x: 4, y: 0 };
+        let map = DijkstraMap::approach(&grid, &[goal], Bounds::around(ORIGIN, 10));
+        assert_eq!(map.value(goal), Some(0));
+
+        let mut at = ORIGIN;
+        for _ in 0..20 {
+            let Some(direction) = map.next_step(&grid, at) else {
+                break;
+            };
+            at = offset(at, direction.delta());
+        }
+        assert_eq!(at, goal);
+    }
+
+    #[test]
+    fn fleeing_creatures_get_away() {
+        let grid = PathGrid::new(&EntityMap::default());
+        let bounds = Bounds::around(ORIGIN, 10);
+        let map = DijkstraMap::flee(&grid, &[ORIGIN], bounds);
+        let mut at = Point { x: 1, y: 1 };
+        for _ in 0..5 {
+            let direction = map.next_step(&grid, at).expect("there is room to run");
+            at = offset(at, direction.delta());
+        }
+        assert_eq!(at.chebyshev_distance(ORIGIN), 6);
+        assert!(bounds.contains(at));
+    }
+
+    #[test]
+    fn cached_paths_are_found_again_when_walls_move() {
+        let mut state = empty_state();
+        let mut cache = PathCache::default();
+        let to = Point { x: 2, y: 0 };
+        let straight = cache.find_path(&state.entities, ORIGIN, to);
+        assert_eq!(straight.as_ref().map(Vec::len), Some(2));
+
+        let blocker = wall(&mut state, 1, 0);
+        wall(&mut state, 1, -1);
+        wall(&mut state, 1, 1);
+        let detour = cache
+            .find_path(&state.entities, ORIGIN, to)
+            .expect("there is a way round");
+        assert_eq!(detour.len(), 4);
+        assert!(!detour.contains(&Point { x: 1, y: 0 }));
+
+        teleport(&mut state, blocker, to);
+        assert_eq!(cache.find_path(&state.entities, ORIGIN, to), None);
+        let map = cache.approach(&state.entities, &[to], Bounds::around(ORIGIN, 4));
+        assert_eq!(
+            map.value(Point { x: 1, y: 0 }),
+            Some(i64::from(NORMAL_COST))
+        );
+    }
+}
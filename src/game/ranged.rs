@@ -0,0 +1,282 @@
+//! Shooting and throwing.
+//!
+//! A character fires whatever [`Ranged`] item it holds in its main hand at a
+//! tile. Bows and guns shoot one of the carried items their `ammo` names;
+//! anything else, like a stone, is thrown itself. Either way the item flies
+//! as a projectile: it keeps its position and gains a [`Projectile`] that
+//! [`fly`] moves a few tiles along its line each tick. It stops short of
+//! anything that blocks movement or sight, may hit each creature it passes
+//! over, as the world's [`GameRng`](super::rng::GameRng) decides, and falls
+//! where it hits or at the end of its range, to be picked up again.
+
+use super::combat::{self, CombatError};
+use super::component::{Projectile, Slot};
+use super::{EntityID, GameEvent, GameState, Point, item, teleport};
+
+/// Shoot or throw at `target` with the item in `shooter`'s main hand.
+///
+/// # Errors
+///
+/// Returns why nothing can be fired; nothing changes then.
+pub fn fire(
+    state: &mut GameState,
+    shooter: EntityID,
+    target: Point,
+) -> Result<Vec<GameEvent>, CombatError> {
+    let entities = &state.entities;
+    let Some(&from) = entities.position().get(&shooter) else {
+        return Err(CombatError::OutOfReach);
+    };
+    if target == from {
+        return Err(CombatError::NoTarget);
+    }
+    let weapon = entities
+        .equipment
+        .get(&shooter)
+        .and_then(|e| e.slots.get(&Slot::MainHand))
+        .copied();
+    let Some((weapon, ranged)) = weapon.and_then(|w| Some((w, entities.ranged.get(&w)?))) else {
+        return Err(CombatError::NothingToFire);
+    };
+    let projectile = match &ranged.ammo {
+        Some(ammo) => item::possessions(entities, shooter)
+            .into_iter()
+            .find(|eid| entities.template.get(eid) == Some(ammo))
+            .ok_or(CombatError::OutOfAmmo)?,
+        None => weapon,
+    };
+    let mut path = line(from, target, ranged.range);
+    path.reverse();
+    let shot = Projectile {
+        shooter,
+        path,
+        speed: ranged.speed,
+        damage: ranged.damage,
+        accuracy: ranged.accuracy,
+    };
+
+    item::take_out(&mut state.entities, projectile);
+    state.entities.set_position(projectile, Some(from));
+    state.entities.projectile.insert(projectile, shot);
+    Ok(vec![GameEvent::Fired {
+        entity_id: shooter,
+        projectile,
+    }])
+}
+
+/// Move everything in flight on by one tick, oldest first.
+pub fn fly(state: &mut GameState) -> Vec<GameEvent> {
+    let mut flying: Vec<EntityID> = state.entities.projectile.keys().copied().collect();
+    flying.sort_unstable_by_key(|eid| eid.0);
+    flying
+        .into_iter()
+        .flat_map(|projectile| fly_one(state, projectile))
+        .collect()
+}
+
+fn fly_one(state: &mut GameState, projectile: EntityID) -> Vec<GameEvent> {
+    // Taken out while it moves; put back only if it is still in flight
+    let Some(mut shot) = state.entities.projectile.remove(&projectile) else {
+        return Vec::new();
+    };
+    for _ in 0..shot.speed {
+        let Some(next) = shot.path.pop() else {
+            break;
+        };
+        let entities = &state.entities;
+        if entities.blocks_movement_at(next) || entities.blocks_sight_at(next) {
+            return Vec::new();
+        }
+        teleport(state, projectile, next);
+        let struck = state
+            .entities
+            .creature_at(next)
+            .filter(|&creature| creature != shot.shooter);
+        if let Some(creature) = struck {
+            if state.rng.chance(shot.accuracy) {
+                return combat::damage(state, creature, shot.damage, Some(shot.shooter), None);
+            }
+        }
+    }
+    if !shot.path.is_empty() {
+        state.entities.projectile.insert(projectile, shot);
+    }
+    Vec::new()
+}
+
+/// The `range` tiles on the straight line from `from` through `to`, not
+/// counting `from` itself; it goes on past `to` if that is nearer.
+pub fn line(from: Point, to: Point, range: u32) -> Vec<Point> {
+    let (dx, dy) = (to.x - from.x, to.y - from.y);
+    let steps = dx.abs().max(dy.abs());
+    if steps == 0 {
+        return Vec::new();
+    }
+    // The offset `i` tiles along, rounded to the nearest whole tile
+    let along = |d: i32, i: i64| {
+        let (d, steps) = (i64::from(d), i64::from(steps));
+        i32::try_from((2 * d * i + steps).div_euclid(2 * steps)).ok()
+    };
+    (1..=i64::from(range))
+        .map_while(|i| {
+            Some(Point {
+                x: from.x.checked_add(along(dx, i)?)?,
+                y: from.y.checked_add(along(dy, i)?)?,
+            })
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameAction, apply, spawn_template, step};
+    use crate::testing::state_with_player;
+
+    const HERE: Point = Point { x: 10, y: 10 };
+    const EAST: Point = Point { x: 20, y: 10 };
+
+    /// Put a new item from `template` in `actor`'s hand.
+    fn wield(state: &mut GameState, actor: EntityID, template: &str) -> EntityID {
+        let weapon = spawn_template(state, template, HERE).expect("template is built in");
+        item::pick_up(state, actor, weapon).expect("within reach");
+        item::equip(state, actor, weapon).expect("held in the main hand");
+        weapon
+    }
+
+    fn carry(state: &mut GameState, actor: EntityID, template: &str) -> EntityID {
+        let thing = spawn_template(state, template, HERE).expect("template is built in");
+        item::pick_up(state, actor, thing).expect("within reach");
+        thing
+    }
+
+    fn land(state: &mut GameState) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            if state.entities.projectile.is_empty() {
+                break;
+            }
+            events.extend(step(state));
+        }
+        events
+    }
+
+    #[test]
+    fn lines_go_on_past_the_target() {
+        let origin = Point { x: 0, y: 0 };
+        let line = line(origin, Point { x: 2, y: 1 }, 4);
+        assert_eq!(
+            line,
+            vec![
+                Point { x: 1, y: 1 },
+                Point { x: 2, y: 1 },
+                Point { x: 3, y: 2 },
+                Point { x: 4, y: 2 },
+            ]
+        );
+        assert!(super::line(origin, origin, 4).is_empty());
+    }
+
+    #[test]
+    fn thrown_stones_fall_at_the_end_of_their_range() {
+        let (mut state, player) = state_with_player();
+        let stone = wield(&mut state, player, "stone");
+
+        let events = apply(&mut state, player, &GameAction::Fire { target: EAST });
+        assert_eq!(
+            events,
+            vec![GameEvent::Fired {
+                entity_id: player,
+                projectile: stone,
+            }]
+        );
+        assert!(!item::carries(&state.entities, player, stone));
+        step(&mut state);
+        assert_eq!(state.entities.position()[&stone], Point { x: 11, y: 10 });
+        assert!(state.entities.items_at(Point { x: 11, y: 10 }).is_empty());
+
+        land(&mut state);
+        assert_eq!(state.entities.items_at(Point { x: 16, y: 10 }), vec![stone]);
+    }
+
+    #[test]
+    fn arrows_hit_creatures_in_their_way() {
+        let (mut state, player) = state_with_player();
+        let bow = wield(&mut state, player, "bow");
+        let arrow = carry(&mut state, player, "arrow");
+        state
+            .entities
+            .ranged
+            .get_mut(&bow)
+            .expect("bows shoot")
+            .accuracy = 100;
+        let sheep = spawn_template(&mut state, "sheep", Point { x: 13, y: 10 }).expect("built in");
+
+        apply(&mut state, player, &GameAction::Fire { target: EAST });
+        let events = land(&mut state);
+        assert!(events.iter().any(|event| matches!(
+            event,
+            GameEvent::Damaged { entity_id, attacker: Some(shooter), amount: 4, .. }
+                if *entity_id == sheep && *shooter == player
+        )));
+        assert_eq!(state.entities.items_at(Point { x: 13, y: 10 }), vec![arrow]);
+        assert!(item::carries(&state.entities, player, bow));
+    }
+
+    #[test]
+    fn walls_stop_what_flies_at_them() {
+        let (mut state, player) = state_with_player();
+        let stone = wield(&mut state, player, "stone");
+        // A tree stands at (10, 5)
+        apply(
+            &mut state,
+            player,
+            &GameAction::Fire {
+                target: Point { x: 10, y: 0 },
+            },
+        );
+        land(&mut state);
+        assert_eq!(state.entities.position()[&stone], Point { x: 10, y: 6 });
+    }
+
+    #[test]
+    fn misses_are_decided_by_the_world_seed() {
+        let shoot = || {
+            let (mut state, player) = state_with_player();
+            wield(&mut state, player, "stone");
+            spawn_template(&mut state, "sheep", Point { x: 12, y: 10 }).expect("built in");
+            apply(&mut state, player, &GameAction::Fire { target: EAST });
+            land(&mut state);
+            state
+        };
+        assert_eq!(shoot(), shoot());
+    }
+
+    #[test]
+    fn firing_needs_a_weapon_and_ammunition() {
+        let (mut state, player) = state_with_player();
+        assert_eq!(
+            fire(&mut state, player, EAST),
+            Err(CombatError::NothingToFire)
+        );
+        wield(&mut state, player, "bow");
+        assert_eq!(fire(&mut state, player, EAST), Err(CombatError::OutOfAmmo));
+        assert_eq!(fire(&mut state, player, HERE), Err(CombatError::NoTarget));
+        assert!(state.entities.projectile.is_empty());
+    }
+
+    #[test]
+    fn nothing_in_flight_can_be_caught() {
+        let (mut state, player) = state_with_player();
+        let stone = wield(&mut state, player, "stone");
+        fire(&mut state, player, EAST).expect("stones can be thrown");
+        assert_eq!(
+            item::pick_up(&mut state, player, stone),
+            Err(item::ItemError::OutOfReach)
+        );
+    }
+}
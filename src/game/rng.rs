@@ -0,0 +1,86 @@
+//! Deterministic randomness.
+//!
+//! Whatever the game leaves to chance draws from the [`GameRng`] in
+//! [`GameState`](super::GameState). It is saved with the world and advanced
+//! only by [`apply`](super::apply) and [`step`](super::step), so replaying the
+//! same actions on the same world always gives the same outcome.
+
+use bitcode::{Decode, Encode};
+
+/// A small, seedable generator (`SplitMix64`); not for anything secret.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct GameRng(u64);
+
+impl GameRng {
+    pub const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A generator seeded from `text`, such as a world's name.
+    pub fn from_text(text: &str) -> Self {
+        // FNV-1a, which unlike the standard hasher is stable across releases
+        let seed = text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self(seed)
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from `0` up to, but not including, `n`; `0` if `n` is.
+    pub fn below(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        // The high bits are the best mixed; the bias is negligible for u32
+        (((self.next_u64() >> 32) * u64::from(n)) >> 32) as u32
+    }
+
+    /// Whether something that succeeds `percent` times in a hundred does.
+    pub fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < percent
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let (mut a, mut b) = (GameRng::from_text("world"), GameRng::from_text("world"));
+        let from_a: Vec<u64> = (0..8).map(|_| a.next_u64()).collect();
+        let from_b: Vec<u64> = (0..8).map(|_| b.next_u64()).collect();
+        assert_eq!(from_a, from_b);
+        assert_ne!(
+            Some(GameRng::from_text("other").next_u64()),
+            from_a.first().copied()
+        );
+    }
+
+    #[test]
+    fn numbers_stay_in_range() {
+        let mut rng = GameRng::new(7);
+        assert!((0..1000).all(|_| rng.below(6) < 6));
+        assert_eq!(rng.below(0), 0);
+        assert!((0..100).all(|_| rng.chance(100)));
+        assert!((0..100).all(|_| !rng.chance(0)));
+    }
+
+    #[test]
+    fn chances_are_roughly_fair() {
+        let mut rng = GameRng::new(42);
+        let hits = (0..10_000).filter(|_| rng.chance(30)).count();
+        assert!((2_700..3_300).contains(&hits), "{hits} hits in 10000");
+    }
+}
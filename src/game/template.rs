@@ -0,0 +1,787 @@
+//! Entity templates loaded from data files.
+//!
+//! Each `.ron` file describes one kind of entity; its file name, without the
+//! extension, is the template's ID. For example `sheep.ron`:
+//!
+//! ```ron
+//! (
+//!     name: "Sheep",
+//!     glyph: "s",
+//!     fg: (230, 230, 230),
+//!     stats: (health: 6),
+//! )
+//! ```
+//!
+//! Besides `name`, `glyph` and `fg`, a template may set `bg`, `size` (in
+//! percent of a tile), `layer` (`"ground"`, `"item"` or `"creature"`),
+//! `tags` (`"blocks_movement"`, `"blocks_sight"`, `"container"`), `stats`
+//! (`health`, `strength`), `item`, which makes
+//! the entity an item that can be carried, as in
+//! `(slot: "waist", weight: 50, volume: 300)` with the weight in grams and
+//! the volume in millilitres, `capacity`, which makes it a container holding
+//! that many millilitres, `drops`, the IDs of templates spawned where the
+//! entity is destroyed, and `move_cost`, how hard the tile it lies on is to
+//! cross in percent of a normal step, as for mud or a road.
+//!
+//! Weapons that shoot or are thrown set `ranged`, as in
+//! `(range: 10, damage: 4, accuracy: 75, speed: 2, ammo: "arrow")`:
+//! `accuracy` is the chance, in percent, to hit (100 if not given), `speed`
+//! the tiles a shot flies per tick (1 if not given), and `ammo` the ID of the
+//! template of what the weapon shoots. Without `ammo`, the item itself is
+//! thrown.
+//!
+//! Species templates lay out a body in `anatomy`, a list of parts such as
+//! `(name: "caput", parent: "thorax", health: 4, size: 10, vital: true)`.
+//! Each part names the part it is attached to, which must come before it;
+//! only the first part has no `parent`. `organ` marks parts that lie inside
+//! their parent, `size` (1 if not given) how big a target the part makes,
+//! and `vital` parts kill the creature when destroyed.
+//!
+//! The templates in `assets/templates` are built in. Files in
+//! [`TEMPLATE_DIR`] are loaded when a server starts and may add templates or
+//! replace built-in ones. Mistakes are reported with the file and field they
+//! were found in.
+
+use super::anatomy::{Anatomy, BodyPart};
+use super::component::{
+    Blueprint, Facing, Health, Inventory, Item, Ranged, Renderable, Rgb, Slot, Stats,
+};
+
+use bitcode::{Decode, Encode};
+use ron::extensions::Extensions;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Directory, next to `worlds`, holding the templates of this installation.
+pub const TEMPLATE_DIR: &str = "templates";
+
+const TEMPLATE_EXTENSION: &str = "ron";
+
+/// Templates shipped with the game, as `(id, source)`.
+const BUILTIN: &[(&str, &str)] = &[
+    ("tree", include_str!("../../assets/templates/tree.ron")),
+    ("sheep", include_str!("../../assets/templates/sheep.ron")),
+    ("stone", include_str!("../../assets/templates/stone.ron")),
+    (
+        "loincloth",
+        include_str!("../../assets/templates/loincloth.ron"),
+    ),
+    ("pouch", include_str!("../../assets/templates/pouch.ron")),
+    ("human", include_str!("../../assets/templates/human.ron")),
+    ("bow", include_str!("../../assets/templates/bow.ron")),
+    ("arrow", include_str!("../../assets/templates/arrow.ron")),
+    ("pistol", include_str!("../../assets/templates/pistol.ron")),
+    ("bullet", include_str!("../../assets/templates/bullet.ron")),
+    ("mud", include_str!("../../assets/templates/mud.ron")),
+];
+
+/// A template as written in its file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateFile {
+    name: Option<String>,
+    glyph: String,
+    fg: (u8, u8, u8),
+    bg: Option<(u8, u8, u8)>,
+    size: Option<u16>,
+    layer: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    stats: StatsFile,
+    item: Option<ItemFile>,
+    ranged: Option<RangedFile>,
+    capacity: Option<u32>,
+    #[serde(default)]
+    drops: Vec<String>,
+    move_cost: Option<u32>,
+    #[serde(default)]
+    anatomy: Vec<PartFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PartFile {
+    name: String,
+    parent: Option<String>,
+    health: u32,
+    size: Option<u32>,
+    #[serde(default)]
+    organ: bool,
+    #[serde(default)]
+    vital: bool,
+}
+
+/// Lay out the body described by `parts`, or say what is wrong with it.
+fn parse_anatomy(parts: Vec<PartFile>) -> Result<Option<Anatomy>, String> {
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    let mut anatomy = Anatomy::default();
+    for part in parts {
+        if anatomy.find(&part.name).is_some() {
+            return Err(format!("there are two parts called \"{}\"", part.name));
+        }
+        if part.health == 0 {
+            return Err(format!("\"{}\" needs at least 1 health", part.name));
+        }
+        let parent = match (&part.parent, anatomy.parts.is_empty()) {
+            (None, true) => None,
+            (None, false) => {
+                return Err(format!(
+                    "\"{}\" needs a parent; only the first part has none",
+                    part.name
+                ));
+            }
+            (Some(parent), _) => Some(anatomy.find(parent).ok_or_else(|| {
+                format!(
+                    "\"{}\" is attached to \"{parent}\", which is not listed before it",
+                    part.name
+                )
+            })?),
+        };
+        let mut body_part = BodyPart::new(part.name, parent, part.health);
+        body_part.size = part.size.unwrap_or(1);
+        body_part.organ = part.organ;
+        body_part.vital = part.vital;
+        anatomy.parts.push(body_part);
+    }
+    Ok(Some(anatomy))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ItemFile {
+    slot: Option<String>,
+    #[serde(default)]
+    weight: u32,
+    #[serde(default)]
+    volume: u32,
+}
+
+impl ItemFile {
+    fn into_item(self) -> Result<Item, String> {
+        let slot = match self.slot {
+            None => None,
+            Some(name) => Some(Slot::from_name(&name).ok_or_else(|| {
+                let slots: Vec<&str> = Slot::ALL.iter().map(|s| s.name()).collect();
+                format!(
+                    "unknown slot \"{name}\"; expected one of {}",
+                    slots.join(", ")
+                )
+            })?),
+        };
+        Ok(Item {
+            slot,
+            weight: self.weight,
+            volume: self.volume,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RangedFile {
+    range: u32,
+    damage: u32,
+    accuracy: Option<u32>,
+    speed: Option<u32>,
+    ammo: Option<String>,
+}
+
+impl RangedFile {
+    fn into_ranged(self) -> Result<Ranged, String> {
+        let accuracy = self.accuracy.unwrap_or(100);
+        if accuracy > 100 {
+            return Err(format!("accuracy is a percentage, not {accuracy}"));
+        }
+        let speed = self.speed.unwrap_or(1);
+        if self.range == 0 || speed == 0 {
+            return Err("range and speed must be at least 1".to_owned());
+        }
+        Ok(Ranged {
+            range: self.range,
+            damage: self.damage,
+            accuracy,
+            speed,
+            ammo: self.ammo,
+        })
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatsFile {
+    health: Option<u32>,
+    strength: Option<u32>,
+}
+
+/// A mistake in a template file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub file: PathBuf,
+    /// The field at fault, if it is known.
+    pub field: Option<String>,
+    pub problem: String,
+}
+
+impl TemplateError {
+    fn new(file: &Path, field: Option<&str>, problem: impl Into<String>) -> Self {
+        Self {
+            file: file.to_owned(),
+            field: field.map(str::to_owned),
+            problem: problem.into(),
+        }
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.file.display())?;
+        if let Some(field) = &self.field {
+            write!(f, "{field}: ")?;
+        }
+        write!(f, "{}", self.problem)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Every mistake found while loading templates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateErrors(pub Vec<TemplateError>);
+
+impl fmt::Display for TemplateErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid entity templates:")?;
+        for error in &self.0 {
+            write!(f, "\n  {error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TemplateErrors {}
+
+/// Entity templates by ID.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Templates(FxHashMap<String, Blueprint>);
+
+impl Templates {
+    /// The templates shipped with the game.
+    pub fn builtin() -> Self {
+        let mut templates = Self::default();
+        for (id, source) in BUILTIN {
+            let file = Path::new("assets/templates").join(format!("{id}.{TEMPLATE_EXTENSION}"));
+            let blueprint = parse(&file, source).expect("built-in templates are valid");
+            templates.insert((*id).to_owned(), blueprint);
+        }
+        templates
+    }
+
+    /// The built-in templates plus those in `dir`, which need not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns every mistake found, so they can all be fixed in one go.
+    pub fn load(dir: &Path) -> Result<Self, TemplateErrors> {
+        let mut templates = Self::builtin();
+        let mut errors = Vec::new();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(templates),
+            Err(e) => {
+                return Err(TemplateErrors(vec![TemplateError::new(
+                    dir,
+                    None,
+                    e.to_string(),
+                )]));
+            }
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == TEMPLATE_EXTENSION)
+            })
+            .collect();
+        files.sort();
+
+        let mut origins = FxHashMap::default();
+        for file in files {
+            let Some(id) = file.file_stem().and_then(|stem| stem.to_str()) else {
+                errors.push(TemplateError::new(
+                    &file,
+                    None,
+                    "file name is not valid UTF-8",
+                ));
+                continue;
+            };
+            let id = id.to_owned();
+            match fs::read_to_string(&file)
+                .map_err(|e| TemplateError::new(&file, None, e.to_string()))
+                .and_then(|source| parse(&file, &source))
+            {
+                Ok(blueprint) => {
+                    templates.insert(id.clone(), blueprint);
+                    origins.insert(id, file);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        #[expect(
+            clippy::iter_over_hash_type,
+            reason = "errors are sorted before they are returned"
+        )]
+        for (id, blueprint) in &templates.0 {
+            let ammo = blueprint.ranged.iter().filter_map(|r| r.ammo.as_ref());
+            let references = blueprint
+                .drops
+                .iter()
+                .map(|drop| ("drops", drop))
+                .chain(ammo.map(|ammo| ("ranged.ammo", ammo)));
+            for (field, reference) in references {
+                if !templates.0.contains_key(reference) {
+                    let file = origins.get(id).map_or_else(
+                        || Path::new("assets/templates").join(format!("{id}.{TEMPLATE_EXTENSION}")),
+                        PathBuf::clone,
+                    );
+                    errors.push(TemplateError::new(
+                        &file,
+                        Some(field),
+                        format!("no template called \"{reference}\""),
+                    ));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(templates)
+        } else {
+            errors.sort_by(|a, b| a.file.cmp(&b.file));
+            Err(TemplateErrors(errors))
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Blueprint> {
+        self.0.get(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.0.contains_key(id)
+    }
+
+    /// Add the template `id`, marking everything made from it as such.
+    pub fn insert(&mut self, id: String, blueprint: Blueprint) {
+        let blueprint = Blueprint {
+            template: Some(id.clone()),
+            ..blueprint
+        };
+        self.0.insert(id, blueprint);
+    }
+
+    /// Template IDs in alphabetical order.
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.0.keys().map(String::as_str).collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+/// Read the fields of a template, without checking their values.
+fn read(file: &Path, source: &str) -> Result<TemplateFile, TemplateError> {
+    // Optional fields are written as plain values, not `Some(..)`
+    let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+    options.from_str(source).map_err(|e| {
+        let field = match &e.code {
+            ron::Error::NoSuchStructField { found, .. } => Some(found.as_str()),
+            ron::Error::MissingStructField { field, .. }
+            | ron::Error::DuplicateStructField { field, .. } => Some(*field),
+            _ => None,
+        };
+        let at = e.span.start;
+        TemplateError::new(
+            file,
+            field,
+            format!("{} (line {}, column {})", e.code, at.line, at.col),
+        )
+    })
+}
+
+/// Parse the template in `source`, read from `file`.
+///
+/// # Errors
+///
+/// Returns the first mistake in the template.
+pub fn parse(file: &Path, source: &str) -> Result<Blueprint, TemplateError> {
+    let template = read(file, source)?;
+    let invalid = |field: &str, problem: String| TemplateError::new(file, Some(field), problem);
+
+    let mut chars = template.glyph.chars();
+    let (Some(glyph), None) = (chars.next(), chars.next()) else {
+        return Err(invalid(
+            "glyph",
+            format!("must be a single character, not \"{}\"", template.glyph),
+        ));
+    };
+    let size_percent = template.size.unwrap_or(100);
+    if !(10..=400).contains(&size_percent) {
+        return Err(invalid(
+            "size",
+            format!("must be between 10 and 400 percent, not {size_percent}"),
+        ));
+    }
+    let layer = parse_layer(template.layer.as_deref(), template.item.is_some())
+        .map_err(|problem| invalid("layer", problem))?;
+    let item = template
+        .item
+        .map(ItemFile::into_item)
+        .transpose()
+        .map_err(|problem| invalid("item.slot", problem))?;
+    let ranged = template
+        .ranged
+        .map(RangedFile::into_ranged)
+        .transpose()
+        .map_err(|problem| invalid("ranged", problem))?;
+    let health = match template.stats.health {
+        Some(0) => return Err(invalid("stats.health", "must be at least 1".to_owned())),
+        health => health.map(Health::full),
+    };
+    let anatomy = parse_anatomy(template.anatomy).map_err(|problem| invalid("anatomy", problem))?;
+    if template.move_cost == Some(0) {
+        return Err(invalid("move_cost", "must be at least 1".to_owned()));
+    }
+
+    let (r, g, b) = template.fg;
+    let mut blueprint = Blueprint {
+        name: template.name,
+        renderable: Some(Renderable {
+            glyph,
+            fg: Rgb(r, g, b),
+            bg: template.bg.map_or(Rgb::BLACK, |(r, g, b)| Rgb(r, g, b)),
+            size_percent,
+            layer,
+        }),
+        // Everything alive looks somewhere
+        facing: health.is_some().then(Facing::default),
+        health,
+        anatomy,
+        stats: template.stats.strength.map(|strength| Stats { strength }),
+        item,
+        ranged,
+        drops: template.drops,
+        move_cost: template.move_cost,
+        ..Blueprint::default()
+    };
+    for tag in &template.tags {
+        match tag.as_str() {
+            "blocks_movement" => blueprint.blocks_movement = true,
+            "blocks_sight" => blueprint.blocks_sight = true,
+            "container" => {
+                blueprint.inventory.get_or_insert_with(Inventory::default);
+            }
+            other => {
+                return Err(invalid(
+                    "tags",
+                    format!(
+                        "unknown tag \"{other}\"; expected \"blocks_movement\", \"blocks_sight\" \
+                         or \"container\""
+                    ),
+                ));
+            }
+        }
+    }
+    if let Some(capacity) = template.capacity {
+        blueprint.inventory = Some(Inventory {
+            items: Vec::new(),
+            capacity: Some(capacity),
+        });
+    }
+    Ok(blueprint)
+}
+
+/// The layer called `name`; items default to lying over the ground,
+/// anything else to standing on it.
+fn parse_layer(name: Option<&str>, is_item: bool) -> Result<u8, String> {
+    match name {
+        None if is_item => Ok(Renderable::ITEM),
+        None | Some("creature") => Ok(Renderable::CREATURE),
+        Some("ground") => Ok(Renderable::GROUND),
+        Some("item") => Ok(Renderable::ITEM),
+        Some(other) => Err(format!(
+            "expected \"ground\", \"item\" or \"creature\", not \"{other}\""
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(source: &str) -> Result<Blueprint, TemplateError> {
+        parse(Path::new("chair.ron"), source)
+    }
+
+    fn field_of(source: &str) -> Option<String> {
+        parse_str(source).expect_err("template is invalid").field
+    }
+
+    /// A fresh, empty directory under the system's temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gamik-templates-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temp dir can be created");
+        dir
+    }
+
+    #[test]
+    fn builtin_templates_are_valid() {
+        let templates = Templates::builtin();
+        assert_eq!(
+            templates.ids(),
+            vec![
+                "arrow",
+                "bow",
+                "bullet",
+                "human",
+                "loincloth",
+                "mud",
+                "pistol",
+                "pouch",
+                "sheep",
+                "stone",
+                "tree"
+            ]
+        );
+        let tree = templates.get("tree").expect("tree is built in");
+        assert!(tree.blocks_movement && tree.blocks_sight);
+        assert_eq!(tree.template.as_deref(), Some("tree"));
+    }
+
+    #[test]
+    fn ranged_weapons_parse() {
+        let sling = parse_str(
+            r#"(
+                glyph: "s",
+                fg: (0, 0, 0),
+                item: (slot: "main_hand"),
+                ranged: (range: 6, damage: 2, accuracy: 60, ammo: "stone"),
+            )"#,
+        )
+        .expect("template is valid");
+        assert_eq!(
+            sling.ranged,
+            Some(Ranged {
+                range: 6,
+                damage: 2,
+                accuracy: 60,
+                speed: 1,
+                ammo: Some("stone".into()),
+            })
+        );
+        assert_eq!(
+            field_of(
+                r#"(glyph: "s", fg: (0, 0, 0), ranged: (range: 6, damage: 2, accuracy: 101))"#
+            )
+            .as_deref(),
+            Some("ranged")
+        );
+    }
+
+    #[test]
+    fn full_template_parses() {
+        let chair = parse_str(
+            r#"(
+                name: "Chair",
+                glyph: "h",
+                fg: (150, 100, 50),
+                bg: (10, 10, 10),
+                size: 80,
+                layer: "ground",
+                tags: ["blocks_movement"],
+                stats: (health: 3, strength: 1),
+                drops: ["tree"],
+            )"#,
+        )
+        .expect("template is valid");
+
+        assert_eq!(chair.name.as_deref(), Some("Chair"));
+        assert_eq!(
+            chair.renderable,
+            Some(Renderable {
+                glyph: 'h',
+                fg: Rgb(150, 100, 50),
+                bg: Rgb(10, 10, 10),
+                size_percent: 80,
+                layer: Renderable::GROUND,
+            })
+        );
+        assert!(chair.blocks_movement && !chair.blocks_sight);
+        assert_eq!(chair.health, Some(Health::full(3)));
+        assert_eq!(chair.stats, Some(Stats { strength: 1 }));
+        assert_eq!(chair.drops, vec!["tree".to_owned()]);
+    }
+
+    #[test]
+    fn items_lie_between_the_ground_and_creatures() {
+        let pouch = parse_str(
+            r#"(
+                glyph: "p",
+                fg: (0, 0, 0),
+                item: (slot: "waist", weight: 50, volume: 300),
+                capacity: 250,
+            )"#,
+        )
+        .expect("template is valid");
+        assert_eq!(
+            pouch.item,
+            Some(Item {
+                slot: Some(Slot::Waist),
+                weight: 50,
+                volume: 300,
+            })
+        );
+        assert_eq!(pouch.renderable.map(|r| r.layer), Some(Renderable::ITEM));
+        assert_eq!(pouch.inventory.and_then(|i| i.capacity), Some(250));
+
+        let stone = parse_str(r#"(glyph: "s", fg: (0, 0, 0), item: ())"#).expect("valid");
+        assert_eq!(stone.item, Some(Item::default()));
+        assert_eq!(stone.inventory, None);
+
+        let sack = parse_str(r#"(glyph: "s", fg: (0, 0, 0), tags: ["container"])"#).expect("valid");
+        assert_eq!(sack.inventory, Some(Inventory::default()));
+        assert_eq!(
+            field_of(r#"(glyph: "s", fg: (0, 0, 0), item: (slot: "tail"))"#).as_deref(),
+            Some("item.slot")
+        );
+    }
+
+    #[test]
+    fn anatomy_is_laid_out_in_order() {
+        let snake = parse_str(
+            r#"(
+                glyph: "S",
+                fg: (0, 0, 0),
+                anatomy: [
+                    (name: "corpus", health: 5, size: 20, vital: true),
+                    (name: "caput", parent: "corpus", health: 2),
+                    (name: "cor", parent: "corpus", health: 1, organ: true, vital: true),
+                ],
+            )"#,
+        )
+        .expect("template is valid");
+        let anatomy = snake.anatomy.expect("snakes have a body");
+        let parents: Vec<Option<usize>> = anatomy.parts.iter().map(|p| p.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(0)]);
+        assert_eq!(anatomy.find("caput").map(|i| anatomy.depth(i)), Some(1));
+        assert_eq!(anatomy.target(), Some(0));
+
+        let human = Templates::builtin();
+        let body = human.get("human").and_then(|h| h.anatomy.as_ref());
+        assert!(body.is_some_and(|b| b.find("cor").is_some()));
+    }
+
+    #[test]
+    fn malformed_anatomies_are_refused() {
+        for anatomy in [
+            r#"[(name: "caput", parent: "corpus", health: 2)]"#,
+            r#"[(name: "corpus", health: 5), (name: "caput", health: 2)]"#,
+            r#"[(name: "corpus", health: 5), (name: "corpus", parent: "corpus", health: 2)]"#,
+            r#"[(name: "corpus", health: 0)]"#,
+        ] {
+            let source = format!(r#"(glyph: "S", fg: (0, 0, 0), anatomy: {anatomy})"#);
+            assert_eq!(field_of(&source).as_deref(), Some("anatomy"), "{anatomy}");
+        }
+    }
+
+    #[test]
+    fn errors_name_the_offending_field() {
+        assert_eq!(
+            field_of(r#"(glyph: "hh", fg: (0, 0, 0))"#).as_deref(),
+            Some("glyph")
+        );
+        assert_eq!(
+            field_of(r#"(glyph: "h", fg: (0, 0, 0), colour: (1, 1, 1))"#).as_deref(),
+            Some("colour")
+        );
+        assert_eq!(field_of(r#"(glyph: "h")"#).as_deref(), Some("fg"));
+        assert_eq!(
+            field_of(r#"(glyph: "h", fg: (0, 0, 0), tags: ["heavy"])"#).as_deref(),
+            Some("tags")
+        );
+        assert_eq!(
+            field_of(r#"(glyph: "h", fg: (0, 0, 0), stats: (health: 0))"#).as_deref(),
+            Some("stats.health")
+        );
+    }
+
+    #[test]
+    fn syntax_errors_give_a_position() {
+        let error = parse_str("(glyph: \"h\",\n fg: (0, 0, 0)").expect_err("unclosed");
+        assert_eq!(error.file, Path::new("chair.ron"));
+        assert!(error.problem.contains("line 2"), "{}", error.problem);
+    }
+
+    #[test]
+    fn missing_directory_leaves_the_builtins() {
+        let templates = Templates::load(Path::new("/nonexistent/gamik/templates"))
+            .expect("a missing directory is fine");
+        assert_eq!(templates, Templates::builtin());
+    }
+
+    #[test]
+    fn files_add_and_replace_templates() {
+        let dir = temp_dir("load");
+        fs::write(dir.join("chair.ron"), r#"(glyph: "h", fg: (1, 2, 3))"#).expect("written");
+        fs::write(dir.join("tree.ron"), r#"(glyph: "T", fg: (0, 255, 0))"#).expect("written");
+        fs::write(dir.join("notes.txt"), "not a template").expect("written");
+
+        let templates = Templates::load(&dir).expect("templates are valid");
+        assert!(templates.contains("chair") && templates.contains("sheep"));
+        let tree = templates.get("tree").and_then(|t| t.renderable.as_ref());
+        assert_eq!(tree.map(|r| r.glyph), Some('T'));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn every_bad_file_is_reported() {
+        let dir = temp_dir("errors");
+        fs::write(dir.join("chair.ron"), r#"(glyph: "", fg: (1, 2, 3))"#).expect("written");
+        fs::write(
+            dir.join("stool.ron"),
+            r#"(glyph: "h", fg: (1, 2, 3), drops: ["plank"])"#,
+        )
+        .expect("written");
+        fs::write(
+            dir.join("sling.ron"),
+            r#"(glyph: "s", fg: (1, 2, 3), ranged: (range: 5, damage: 1, ammo: "pebble"))"#,
+        )
+        .expect("written");
+
+        let TemplateErrors(errors) = Templates::load(&dir).expect_err("templates are invalid");
+        let found: Vec<(PathBuf, Option<&str>)> = errors
+            .iter()
+            .map(|e| (e.file.clone(), e.field.as_deref()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (dir.join("chair.ron"), Some("glyph")),
+                (dir.join("sling.ron"), Some("ranged.ammo")),
+                (dir.join("stool.ron"), Some("drops")),
+            ]
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,136 @@
+//! What creatures can see.
+//!
+//! A creature sees every tile within [`SIGHT_RADIUS`] that no entity which
+//! blocks sight stands in front of; the tile of such an entity is itself
+//! seen, but nothing behind it. Where only what lies ahead should count, the
+//! view is narrowed to a cone around the creature's
+//! [`Facing`]: the compass point it faces and the two next to it.
+
+use super::component::Facing;
+use super::ranged::line;
+use super::{EntityID, EntityMap, Point};
+
+use rustc_hash::FxHashSet;
+
+/// How far creatures can see, in tiles.
+pub const SIGHT_RADIUS: i32 = 12;
+
+/// Squared cosine of half the forward cone's angle, 67.5°, in millionths.
+const CONE_COS_SQUARED: i64 = 146_447;
+
+/// The tiles `viewer` can see, restricted to its forward cone if `cone`.
+///
+/// Nothing is seen by an entity without a position; one without a facing
+/// sees all round even if `cone` is set.
+pub fn visible_tiles(entities: &EntityMap, viewer: EntityID, cone: bool) -> FxHashSet<Point> {
+    let mut seen = FxHashSet::default();
+    let Some(&from) = entities.position().get(&viewer) else {
+        return seen;
+    };
+    let facing = entities.facing.get(&viewer).copied().filter(|_| cone);
+    let walls: FxHashSet<Point> = entities
+        .blocks_sight
+        .iter()
+        .filter_map(|eid| entities.position().get(eid))
+        .copied()
+        .collect();
+
+    for dy in -SIGHT_RADIUS..=SIGHT_RADIUS {
+        for dx in -SIGHT_RADIUS..=SIGHT_RADIUS {
+            if dx * dx + dy * dy > SIGHT_RADIUS * SIGHT_RADIUS {
+                continue;
+            }
+            let to = Point {
+                x: from.x + dx,
+                y: from.y + dy,
+            };
+            let ahead = facing.is_none_or(|facing| in_cone(facing, (dx, dy)));
+            if ahead && in_line_of_sight(&walls, from, to) {
+                seen.insert(to);
+            }
+        }
+    }
+    seen
+}
+
+/// Whether a tile `offset` away lies in the forward cone of something
+/// facing `facing`. The tile it stands on always does.
+pub fn in_cone(facing: Facing, offset: (i32, i32)) -> bool {
+    if offset == (0, 0) {
+        return true;
+    }
+    let (fx, fy) = facing.delta();
+    let (ox, oy) = (i64::from(offset.0), i64::from(offset.1));
+    let dot = i64::from(fx) * ox + i64::from(fy) * oy;
+    let lengths_squared = i64::from(fx * fx + fy * fy) * (ox * ox + oy * oy);
+    dot > 0 && dot * dot * 1_000_000 >= CONE_COS_SQUARED * lengths_squared
+}
+
+/// Whether nothing in `walls` stands between `from` and `to`.
+fn in_line_of_sight(walls: &FxHashSet<Point>, from: Point, to: Point) -> bool {
+    let distance = from.chebyshev_distance(to);
+    line(from, to, distance.unsigned_abs())
+        .iter()
+        .take_while(|&&at| at != to)
+        .all(|at| !walls.contains(at))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameState, spawn_template};
+    use crate::testing::state_with_player;
+
+    #[test]
+    fn walls_hide_what_is_behind_them() {
+        let (state, player) = state_with_player();
+        // A tree stands at (10, 5), north of the player at (10, 10)
+        let seen = visible_tiles(&state.entities, player, false);
+        assert!(seen.contains(&Point { x: 10, y: 6 }));
+        assert!(seen.contains(&Point { x: 10, y: 5 }));
+        assert!(!seen.contains(&Point { x: 10, y: 4 }));
+        assert!(seen.contains(&Point {
+            x: 10 + SIGHT_RADIUS,
+            y: 10
+        }));
+        assert!(!seen.contains(&Point {
+            x: 11 + SIGHT_RADIUS,
+            y: 10
+        }));
+    }
+
+    #[test]
+    fn the_cone_shows_only_what_lies_ahead() {
+        let (state, player) = state_with_player();
+        // Players start out facing south
+        let seen = visible_tiles(&state.entities, player, true);
+        assert!(seen.contains(&Point { x: 10, y: 10 }));
+        assert!(seen.contains(&Point { x: 10, y: 14 }));
+        assert!(seen.contains(&Point { x: 13, y: 13 }));
+        assert!(!seen.contains(&Point { x: 14, y: 10 }));
+        assert!(!seen.contains(&Point { x: 10, y: 9 }));
+    }
+
+    #[test]
+    fn cones_span_three_compass_points() {
+        for facing in Facing::ALL {
+            let ahead = Facing::ALL
+                .iter()
+                .filter(|other| in_cone(facing, other.delta()))
+                .count();
+            assert_eq!(ahead, 3, "{facing:?}");
+        }
+    }
+
+    #[test]
+    fn things_without_a_position_see_nothing() {
+        let mut state = GameState::create_test_world("test".into());
+        let stone = spawn_template(&mut state, "stone", Point { x: 0, y: 0 }).expect("built in");
+        state.entities.set_position(stone, None);
+        assert!(visible_tiles(&state.entities, stone, false).is_empty());
+    }
+}
@@ -6,3 +6,6 @@ pub mod ui;
 
 mod app;
 pub use app::GamikApp;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,240 @@
+//! Reporting fights to the players in them.
+//!
+//! [`game::combat`](crate::game::combat) decides who hurts whom; the server
+//! tells the players involved and deals with characters that die. A dead
+//! character is lost for good: whoever played it is sent
+//! [`ServerMessage::Died`] and may create a new character or pick another one
+//! of their account's. Players also hear about their wounds as they heal.
+
+use super::{ServerMessage, ServerState, account_of};
+use crate::game::{EntityID, GameEvent};
+
+use iroh::EndpointId;
+
+impl ServerState {
+    /// Pass the outcome of attacks, and of wounds healing, on to the players
+    /// involved.
+    pub(super) fn relay_combat(&mut self, events: Vec<GameEvent>) {
+        for event in events {
+            match event {
+                GameEvent::Damaged {
+                    entity_id,
+                    attacker,
+                    amount,
+                    part,
+                } => {
+                    // A lethal blow is reported along with the death instead
+                    if !self.game.entities.health.contains_key(&entity_id) {
+                        continue;
+                    }
+                    let victim = self.name_of(entity_id);
+                    let hit_in = part
+                        .map(|part| format!(" in the {part}"))
+                        .unwrap_or_default();
+                    let Some(attacker) = attacker else {
+                        self.notify_controller(
+                            entity_id,
+                            &format!("You take {amount} damage{hit_in}."),
+                        );
+                        continue;
+                    };
+                    let hitter = self.name_of(attacker);
+                    self.notify_controller(
+                        attacker,
+                        &format!("You hit {victim}{hit_in} for {amount}."),
+                    );
+                    self.notify_controller(
+                        entity_id,
+                        &format!("{hitter} hits you{hit_in} for {amount}."),
+                    );
+                }
+                GameEvent::PartDestroyed { entity_id, part } => {
+                    if self.game.entities.health.contains_key(&entity_id) {
+                        self.notify_controller(entity_id, &format!("You lose your {part}!"));
+                    }
+                }
+                GameEvent::WoundHealed {
+                    entity_id,
+                    part,
+                    scarred,
+                } => {
+                    let text = if scarred {
+                        format!("The wound on your {part} has healed, leaving a scar.")
+                    } else {
+                        format!("The wound on your {part} has healed.")
+                    };
+                    self.notify_controller(entity_id, &text);
+                }
+                GameEvent::Died {
+                    entity_id,
+                    killer,
+                    name,
+                } => self.character_died(entity_id, killer, &name),
+                GameEvent::AttackRefused { entity_id, reason } => {
+                    self.notify_controller(entity_id, &reason.to_string());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `entity_id`, once called `name`, died: tell its killer, and take it
+    /// away from whoever played it.
+    fn character_died(&mut self, entity_id: EntityID, killer: Option<EntityID>, name: &str) {
+        self.next_step.remove(&entity_id);
+        let cause = match killer {
+            Some(killer) => {
+                self.notify_controller(killer, &format!("You killed {name}."));
+                format!("{name} was killed by {}.", self.name_of(killer))
+            }
+            None => format!("{name} died."),
+        };
+
+        let players: Vec<EndpointId> = self
+            .endpoints
+            .iter()
+            .filter(|(_, eid)| **eid == entity_id)
+            .map(|(endpoint_id, _)| *endpoint_id)
+            .collect();
+        if players.is_empty() {
+            return;
+        }
+        for endpoint_id in players {
+            self.endpoints.remove(&endpoint_id);
+            self.send_to(endpoint_id, ServerMessage::Died(cause.clone()));
+            let owned = self.game.characters_of(&account_of(&endpoint_id));
+            self.send_to(endpoint_id, ServerMessage::OwnedCharacters(owned));
+        }
+        self.broadcast(&ServerMessage::Notice(cause));
+    }
+
+    fn name_of(&self, entity_id: EntityID) -> String {
+        self.game
+            .entities
+            .name_of(entity_id)
+            .unwrap_or("Something")
+            .to_owned()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::anatomy::{HEAL_TICKS, SCAR_DEPTH};
+    use crate::game::{Direction, GameAction, GameState, Point, combat, spawn_template, teleport};
+    use crate::net::ActionError;
+    use crate::testing::endpoint;
+
+    /// Take what was sent to `endpoint_id` since the last call.
+    fn sent_to(server: &mut ServerState, endpoint_id: EndpointId) -> Vec<ServerMessage> {
+        server
+            .unique_server_messages
+            .remove(&endpoint_id)
+            .unwrap_or_default()
+    }
+
+    fn noticed(messages: &[ServerMessage], expected: &str) -> bool {
+        messages
+            .iter()
+            .any(|m| matches!(m, ServerMessage::Notice(text) if text == expected))
+    }
+
+    #[test]
+    fn attackers_and_victims_hear_about_blows() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+        let sheep = spawn_template(&mut server.game, "sheep", Point { x: 11, y: 10 })
+            .expect("sheep is built in");
+        server.unique_server_messages.clear();
+
+        server.handle_action(alice, GameAction::Attack(sheep));
+        server.process_events();
+        assert!(noticed(
+            &sent_to(&mut server, alice),
+            "You hit Sheep in the truncus for 3."
+        ));
+
+        server.event_queue.push((sheep, GameAction::Attack(pid)));
+        server.process_events();
+        assert!(noticed(
+            &sent_to(&mut server, alice),
+            "Sheep hits you in the thorax for 2."
+        ));
+    }
+
+    #[test]
+    fn players_hear_their_wounds_heal() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+        combat::damage(&mut server.game, pid, SCAR_DEPTH, None, None);
+        server.unique_server_messages.clear();
+
+        for _ in 0..HEAL_TICKS * SCAR_DEPTH {
+            server.process_events();
+        }
+        assert!(noticed(
+            &sent_to(&mut server, alice),
+            "The wound on your thorax has healed, leaving a scar."
+        ));
+        assert_eq!(server.game.entities.health[&pid].current, 10);
+    }
+
+    #[test]
+    fn dead_characters_are_lost_to_their_players() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, bob) = (endpoint(1), endpoint(2));
+        server.join(alice, "alice".into());
+        server.join(bob, "bob".into());
+        let victim = server.spawn_player_for(alice, "Alice".into());
+        let killer = server.spawn_player_for(bob, "Bob".into());
+        teleport(&mut server.game, killer, Point { x: 11, y: 10 });
+        server
+            .game
+            .entities
+            .health
+            .get_mut(&victim)
+            .expect("characters have health")
+            .current = 1;
+        server.unique_server_messages.clear();
+
+        server.handle_action(bob, GameAction::Attack(victim));
+        server.process_events();
+
+        assert_eq!(server.endpoints.get(&alice), None);
+        assert_eq!(server.endpoints.get(&bob), Some(&killer));
+        let cause = "Alice was killed by Bob.";
+        let to_alice = sent_to(&mut server, alice);
+        assert!(
+            to_alice
+                .iter()
+                .any(|m| matches!(m, ServerMessage::Died(text) if text == cause))
+        );
+        assert!(
+            to_alice
+                .iter()
+                .any(|m| matches!(m, ServerMessage::OwnedCharacters(owned) if owned.is_empty()))
+        );
+        assert!(noticed(&to_alice, cause));
+        assert!(noticed(&sent_to(&mut server, bob), "You killed Alice."));
+
+        server.handle_action(alice, GameAction::Move(Direction::Up));
+        assert!(
+            sent_to(&mut server, alice)
+                .iter()
+                .any(|m| matches!(m, ServerMessage::ActionRejected { .. })),
+            "nothing left to move"
+        );
+        assert_eq!(
+            server.spawn_as(alice, victim),
+            Err(ActionError::NotOwner(victim))
+        );
+    }
+}
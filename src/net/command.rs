@@ -0,0 +1,501 @@
+//! Admin commands.
+//!
+//! Operators manage a running world with slash commands such as `/kick Bob`
+//! or `/teleport 10 4`. They can be typed into the in-game chat box, which
+//! sends them as [`GameAction::Command`](crate::game::GameAction::Command),
+//! or into the dedicated server's console. Either way they end up in
+//! [`ServerState::run_command`], which checks the issuer's
+//! [`PermissionLevel`] before doing anything.
+//!
+//! A player is named by the name their client joined with, which the server
+//! keeps unique, by `#` and the ID of the character they play, or by their
+//! endpoint ID; the latter two work for names with spaces in them.
+
+use super::{ServerMessage, ServerState, account_of};
+use crate::game::{self, PermissionLevel, Point};
+
+use bitcode::{Decode, Encode};
+use iroh::EndpointId;
+
+/// A parsed admin command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Disconnect a player.
+    Kick(String),
+    /// Disconnect a player and refuse their account from now on.
+    Ban(String),
+    /// Move a player's character, or the issuer's own if `target` is `None`.
+    Teleport {
+        target: Option<String>,
+        to: Point,
+    },
+    /// Create an entity from the template with this ID.
+    Spawn {
+        template: String,
+        at: Point,
+    },
+    Save,
+    /// Toggle whether the world is paused.
+    Pause,
+    /// Make a player an operator.
+    Op(String),
+    /// Take operator rights away from a player.
+    Deop(String),
+    /// Let a player host the world from now on.
+    Handoff(String),
+    /// Call off a handoff and let the world carry on here.
+    CancelHandoff,
+}
+
+/// Why a command could not be run.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum CommandError {
+    Empty,
+    Unknown(String),
+    /// The arguments were wrong; holds the correct usage.
+    Usage(String),
+    NotPermitted,
+    NoSuchPlayer(String),
+    /// The command needs a character, but the player is not controlling one.
+    NoCharacter,
+    UnknownTemplate(String),
+    SaveFailed(String),
+    /// The world is already being handed to another player.
+    HandoffInProgress,
+    /// More than one connected player goes by this name.
+    AmbiguousPlayer(String),
+    /// The world cannot be handed to the player hosting it.
+    AlreadyHosting,
+    /// There is no handoff to call off.
+    NoHandoff,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "Type a command after the slash."),
+            Self::Unknown(name) => write!(f, "Unknown command: /{name}"),
+            Self::Usage(usage) => write!(f, "Usage: {usage}"),
+            Self::NotPermitted => write!(f, "Only operators may use admin commands."),
+            Self::NoSuchPlayer(name) => write!(f, "No connected player called {name}."),
+            Self::NoCharacter => write!(f, "That player is not controlling a character."),
+            Self::UnknownTemplate(id) => write!(f, "There is no entity template called {id}."),
+            Self::SaveFailed(e) => write!(f, "Could not save the world: {e}"),
+            Self::HandoffInProgress => write!(f, "The world is already moving to a new host."),
+            Self::AmbiguousPlayer(name) => write!(
+                f,
+                "Several players are called {name}; name them by #character or endpoint ID."
+            ),
+            Self::AlreadyHosting => write!(f, "That player is hosting the world already."),
+            Self::NoHandoff => write!(f, "The world is not moving anywhere."),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Parse a command line, with or without its leading slash.
+///
+/// # Errors
+///
+/// Returns [`CommandError::Unknown`] or [`CommandError::Usage`] if the line
+/// is not a well-formed command.
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let line = line.trim();
+    let line = line.strip_prefix('/').unwrap_or(line);
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Err(CommandError::Empty);
+    };
+    let args: Vec<&str> = words.collect();
+
+    let usage = |usage: &str| CommandError::Usage(usage.to_owned());
+    let point = |x: &str, y: &str| -> Option<Point> {
+        Some(Point {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+        })
+    };
+
+    match (name, args.as_slice()) {
+        ("kick", [player]) => Ok(Command::Kick((*player).to_owned())),
+        ("kick", _) => Err(usage("/kick <player>")),
+        ("ban", [player]) => Ok(Command::Ban((*player).to_owned())),
+        ("ban", _) => Err(usage("/ban <player>")),
+        ("teleport" | "tp", [x, y]) => point(x, y)
+            .map(|to| Command::Teleport { target: None, to })
+            .ok_or_else(|| usage("/teleport [player] <x> <y>")),
+        ("teleport" | "tp", [player, x, y]) => point(x, y)
+            .map(|to| Command::Teleport {
+                target: Some((*player).to_owned()),
+                to,
+            })
+            .ok_or_else(|| usage("/teleport [player] <x> <y>")),
+        ("teleport" | "tp", _) => Err(usage("/teleport [player] <x> <y>")),
+        ("spawn", [kind, x, y]) => {
+            let at = point(x, y).ok_or_else(|| usage("/spawn <template> <x> <y>"))?;
+            Ok(Command::Spawn {
+                template: (*kind).to_owned(),
+                at,
+            })
+        }
+        ("spawn", _) => Err(usage("/spawn <template> <x> <y>")),
+        ("save", []) => Ok(Command::Save),
+        ("save", _) => Err(usage("/save")),
+        ("pause", []) => Ok(Command::Pause),
+        ("pause", _) => Err(usage("/pause")),
+        ("op", [player]) => Ok(Command::Op((*player).to_owned())),
+        ("op", _) => Err(usage("/op <player>")),
+        ("deop", [player]) => Ok(Command::Deop((*player).to_owned())),
+        ("deop", _) => Err(usage("/deop <player>")),
+        ("handoff", ["cancel"]) => Ok(Command::CancelHandoff),
+        ("handoff", [player]) => Ok(Command::Handoff((*player).to_owned())),
+        ("handoff", _) => Err(usage("/handoff <player> | /handoff cancel")),
+        (other, _) => Err(CommandError::Unknown(other.to_owned())),
+    }
+}
+
+impl ServerState {
+    /// Parse and run an admin command on behalf of `issuer`, returning a
+    /// message describing what happened.
+    ///
+    /// `issuer` is `None` for the server console, which may do anything.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the command was not carried out.
+    pub fn run_command(
+        &mut self,
+        issuer: Option<EndpointId>,
+        line: &str,
+    ) -> Result<String, CommandError> {
+        let command = parse_command(line)?;
+        let permission = issuer.map_or(PermissionLevel::Operator, |id| self.permission_of(&id));
+        if permission < PermissionLevel::Operator {
+            return Err(CommandError::NotPermitted);
+        }
+
+        match command {
+            Command::Kick(name) => {
+                let target = self.find_client(&name)?;
+                self.kick(target, "You were kicked by an operator.".to_owned());
+                Ok(format!("Kicked {name}."))
+            }
+            Command::Ban(name) => {
+                let target = self.find_client(&name)?;
+                game::set_banned(&mut self.game, account_of(&target), true);
+                self.kick(target, "You were banned from this server.".to_owned());
+                Ok(format!("Banned {name}."))
+            }
+            Command::Teleport { target, to } => {
+                let endpoint_id = match target {
+                    Some(name) => self.find_client(&name)?,
+                    None => issuer.ok_or_else(|| {
+                        CommandError::Usage("/teleport <player> <x> <y>".to_owned())
+                    })?,
+                };
+                let eid = self
+                    .endpoints
+                    .get(&endpoint_id)
+                    .copied()
+                    .ok_or(CommandError::NoCharacter)?;
+                game::teleport(&mut self.game, eid, to);
+                Ok(format!("Teleported to ({}, {}).", to.x, to.y))
+            }
+            Command::Spawn { template, at } => {
+                let eid = game::spawn_template(&mut self.game, &template, at)
+                    .ok_or(CommandError::UnknownTemplate(template))?;
+                Ok(format!("Spawned entity {} at ({}, {}).", eid.0, at.x, at.y))
+            }
+            Command::Save => match game::save_to_file(&self.game) {
+                Ok(()) => Ok("World saved.".to_owned()),
+                Err(e) => Err(CommandError::SaveFailed(e.to_string())),
+            },
+            Command::Pause => {
+                self.paused = !self.paused;
+                let notice = if self.paused {
+                    "The game has been paused."
+                } else {
+                    "The game has been resumed."
+                };
+                self.broadcast(&ServerMessage::Notice(notice.to_owned()));
+                Ok(notice.to_owned())
+            }
+            Command::Op(name) => self.set_permission(&name, PermissionLevel::Operator),
+            Command::Deop(name) => self.set_permission(&name, PermissionLevel::Player),
+            Command::Handoff(name) => {
+                let target = self.find_client(&name)?;
+                self.begin_handoff(target)
+            }
+            Command::CancelHandoff => self.cancel_handoff(),
+        }
+    }
+
+    /// Disconnect `endpoint_id`, telling it why.
+    ///
+    /// Its character is put to sleep right away; the connection itself is
+    /// closed once the client has read the [`ServerMessage::Kicked`].
+    pub fn kick(&mut self, endpoint_id: EndpointId, reason: String) {
+        if let Some(eid) = self.endpoints.remove(&endpoint_id) {
+            game::set_dormant(&mut self.game, eid, true);
+        }
+        self.send_to(endpoint_id, ServerMessage::Kicked(reason));
+    }
+
+    /// The connected client `who` names: by endpoint ID, by `#` and the ID
+    /// of its character, or by name, ignoring case.
+    fn find_client(&self, who: &str) -> Result<EndpointId, CommandError> {
+        let no_such_player = || CommandError::NoSuchPlayer(who.to_owned());
+        if let Ok(endpoint_id) = who.parse::<EndpointId>() {
+            return Some(endpoint_id)
+                .filter(|id| self.clients.contains_key(id))
+                .ok_or_else(no_such_player);
+        }
+        if let Some(character) = who.strip_prefix('#') {
+            let character: Option<u32> = character.parse().ok();
+            return self
+                .endpoints
+                .iter()
+                .find(|(_, eid)| Some(eid.0) == character)
+                .map(|(id, _)| *id)
+                .ok_or_else(no_such_player);
+        }
+        let mut named = self
+            .clients
+            .iter()
+            .filter(|(_, c)| c.name.eq_ignore_ascii_case(who))
+            .map(|(id, _)| *id);
+        match (named.next(), named.next()) {
+            (Some(endpoint_id), None) => Ok(endpoint_id),
+            (Some(_), Some(_)) => Err(CommandError::AmbiguousPlayer(who.to_owned())),
+            (None, _) => Err(no_such_player()),
+        }
+    }
+
+    fn set_permission(
+        &mut self,
+        name: &str,
+        permission: PermissionLevel,
+    ) -> Result<String, CommandError> {
+        let target = self.find_client(name)?;
+        game::set_permission(&mut self.game, account_of(&target), permission);
+        let notice = match permission {
+            PermissionLevel::Operator => "You are now an operator.",
+            PermissionLevel::Player => "You are no longer an operator.",
+        };
+        self.send_to(target, ServerMessage::Notice(notice.to_owned()));
+        Ok(format!("{name} is now {permission:?}."))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameState;
+    use crate::testing::endpoint;
+
+    /// A server with an operator (Alice) and a regular player (Bob), both
+    /// playing a character.
+    fn server_with_players() -> (ServerState, EndpointId, EndpointId) {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, bob) = (endpoint(1), endpoint(2));
+        server.host = Some(alice);
+        for (endpoint_id, name) in [(alice, "Alice"), (bob, "Bob")] {
+            server.join(endpoint_id, name.into());
+            server.spawn_player_for(endpoint_id, name.into());
+        }
+        (server, alice, bob)
+    }
+
+    #[test]
+    fn commands_parse_with_or_without_slash() {
+        assert_eq!(parse_command("/kick Bob"), Ok(Command::Kick("Bob".into())));
+        assert_eq!(parse_command("  pause "), Ok(Command::Pause));
+    }
+
+    #[test]
+    fn teleport_target_is_optional() {
+        let to = Point { x: 3, y: -2 };
+        assert_eq!(
+            parse_command("/teleport 3 -2"),
+            Ok(Command::Teleport { target: None, to })
+        );
+        assert_eq!(
+            parse_command("/tp Bob 3 -2"),
+            Ok(Command::Teleport {
+                target: Some("Bob".into()),
+                to
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_commands_report_usage() {
+        assert!(matches!(
+            parse_command("/spawn tree here 4"),
+            Err(CommandError::Usage(_))
+        ));
+        assert_eq!(
+            parse_command("/dance"),
+            Err(CommandError::Unknown("dance".into()))
+        );
+        assert_eq!(parse_command("/"), Err(CommandError::Empty));
+    }
+
+    #[test]
+    fn players_cannot_run_commands() {
+        let (mut server, alice, bob) = server_with_players();
+        assert_eq!(
+            server.run_command(Some(bob), "/kick Alice"),
+            Err(CommandError::NotPermitted)
+        );
+        assert!(server.endpoints.contains_key(&alice));
+    }
+
+    #[test]
+    fn op_grants_persistent_permission() {
+        let (mut server, alice, bob) = server_with_players();
+
+        server
+            .run_command(Some(alice), "/op bob")
+            .expect("host may op players");
+
+        assert_eq!(server.permission_of(&bob), PermissionLevel::Operator);
+        assert_eq!(
+            server.game.permission_of(&account_of(&bob)),
+            PermissionLevel::Operator
+        );
+    }
+
+    #[test]
+    fn kick_sends_reason_and_puts_character_to_sleep() {
+        let (mut server, alice, bob) = server_with_players();
+        let pid = server.endpoints[&bob];
+
+        server
+            .run_command(Some(alice), "/kick Bob")
+            .expect("host may kick");
+
+        assert!(server.game.entities.is_dormant(pid));
+        assert!(
+            server.unique_server_messages[&bob]
+                .iter()
+                .any(|m| matches!(m, ServerMessage::Kicked(_)))
+        );
+    }
+
+    #[test]
+    fn ban_marks_account_banned() {
+        let (mut server, _, bob) = server_with_players();
+
+        server
+            .run_command(None, "ban bob")
+            .expect("console may ban");
+
+        assert!(server.game.is_banned(&account_of(&bob)));
+    }
+
+    #[test]
+    fn teleport_moves_the_issuers_character() {
+        let (mut server, alice, _) = server_with_players();
+        let pid = server.endpoints[&alice];
+
+        server
+            .run_command(Some(alice), "/teleport 40 2")
+            .expect("host may teleport");
+
+        assert_eq!(server.game.entities.position()[&pid], Point { x: 40, y: 2 });
+    }
+
+    #[test]
+    fn console_must_name_a_teleport_target() {
+        let (mut server, _, _) = server_with_players();
+        assert!(matches!(
+            server.run_command(None, "teleport 1 1"),
+            Err(CommandError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn spawn_creates_entity() {
+        let (mut server, alice, _) = server_with_players();
+        let before = server.game.entities.len();
+
+        server
+            .run_command(Some(alice), "/spawn tree 7 7")
+            .expect("host may spawn");
+
+        assert_eq!(server.game.entities.len(), before + 1);
+        assert_eq!(
+            server.run_command(Some(alice), "/spawn dragon 1 1"),
+            Err(CommandError::UnknownTemplate("dragon".into()))
+        );
+        assert_eq!(server.game.entities.len(), before + 1);
+    }
+
+    #[test]
+    fn clients_joining_under_a_taken_name_are_numbered() {
+        let (mut server, alice, bob) = server_with_players();
+        let other_bob = endpoint(3);
+        server.join(other_bob, "bob".into());
+        assert_eq!(server.clients[&other_bob].name, "bob2");
+
+        server
+            .run_command(Some(alice), "/kick Bob")
+            .expect("Bob is still Bob");
+        assert!(!server.endpoints.contains_key(&bob));
+        assert!(server.clients.contains_key(&other_bob));
+    }
+
+    #[test]
+    fn players_can_be_named_by_character_or_endpoint() {
+        let (mut server, alice, bob) = server_with_players();
+        server.clients.get_mut(&bob).expect("Bob joined").name = "Bob the Builder".into();
+        let pid = server.endpoints[&bob];
+
+        server
+            .run_command(Some(alice), &format!("/teleport #{} 5 5", pid.0))
+            .expect("characters name their players");
+        assert_eq!(server.game.entities.position()[&pid], Point { x: 5, y: 5 });
+        server
+            .run_command(Some(alice), &format!("/op {bob}"))
+            .expect("endpoint IDs name their players");
+        assert_eq!(server.permission_of(&bob), PermissionLevel::Operator);
+        assert_eq!(
+            server.run_command(Some(alice), "/kick #999"),
+            Err(CommandError::NoSuchPlayer("#999".into()))
+        );
+    }
+
+    #[test]
+    fn ambiguous_names_are_refused() {
+        let (mut server, alice, bob) = server_with_players();
+        server.clients.get_mut(&alice).expect("Alice joined").name = "bob".into();
+
+        assert_eq!(
+            server.run_command(Some(alice), "/kick Bob"),
+            Err(CommandError::AmbiguousPlayer("Bob".into()))
+        );
+        assert!(server.endpoints.contains_key(&bob));
+    }
+
+    #[test]
+    fn pause_toggles_and_is_announced() {
+        let (mut server, alice, bob) = server_with_players();
+
+        server.run_command(Some(alice), "/pause").expect("pause");
+        assert!(server.paused);
+        assert!(
+            server.unique_server_messages[&bob]
+                .iter()
+                .any(|m| matches!(m, ServerMessage::Notice(_)))
+        );
+
+        server.run_command(Some(alice), "/pause").expect("resume");
+        assert!(!server.paused);
+    }
+}
@@ -0,0 +1,388 @@
+//! Local-network discovery of gamik servers.
+//!
+//! Browsing clients periodically broadcast a [`LanPacket::Probe`] on
+//! [`DISCOVERY_PORT`]; every server on the LAN answers the sender directly
+//! with a [`LanPacket::Announce`] describing itself. Echoing the probe's
+//! nonce lets the client measure the round trip as the server's ping.
+//!
+//! Servers only answer probes from private addresses, speaking their
+//! protocol version, and never with more bytes than the probe had; probes
+//! are padded to leave room. That way a forged probe cannot turn a server
+//! into an amplifier aimed at somebody else.
+
+use super::queue::QueueSender;
+use super::tick::ServerHandle;
+
+use bitcode::{Decode, Encode};
+use rustc_hash::FxHashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// UDP port servers listen on for probes.
+pub const DISCOVERY_PORT: u16 = 47474;
+/// Marks our packets so unrelated broadcast traffic is ignored.
+const MAGIC: &[u8] = b"gamik-lan/2";
+const MAX_PACKET_SIZE: usize = 2048;
+/// Padding in every probe, so that the answer, which may not be larger than
+/// the probe, has room for a ticket; the whole still fits in one datagram.
+const PROBE_PADDING: usize = 1400;
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+/// Servers that have not answered for this long are dropped from the list.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(6);
+
+// ---------------------------------------------------------------------------
+// Packets
+// ---------------------------------------------------------------------------
+
+/// What a server tells browsing clients about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct LanServer {
+    pub protocol_version: u32,
+    pub world_name: String,
+    pub host_name: String,
+    pub player_count: u32,
+    /// A [`ServerTicket`](super::ticket::ServerTicket) for joining.
+    pub ticket: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum LanPacket {
+    Probe {
+        nonce: u64,
+        protocol_version: u32,
+        padding: Vec<u8>,
+    },
+    Announce {
+        nonce: u64,
+        server: LanServer,
+    },
+}
+
+impl LanPacket {
+    /// A probe with `nonce` for servers speaking our protocol version.
+    pub fn probe(nonce: u64) -> Self {
+        Self::Probe {
+            nonce,
+            protocol_version: super::PROTOCOL_VERSION,
+            // Counting up, as bitcode would pack runs of one value away
+            padding: (0..=u8::MAX).cycle().take(PROBE_PADDING).collect(),
+        }
+    }
+}
+
+/// Serialize a packet, prefixed with [`MAGIC`].
+pub fn encode_packet(packet: &LanPacket) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(bitcode::encode(packet));
+    bytes
+}
+
+/// Parse a packet written by [`encode_packet`], ignoring anything else.
+pub fn decode_packet(bytes: &[u8]) -> Option<LanPacket> {
+    bitcode::decode(bytes.strip_prefix(MAGIC)?).ok()
+}
+
+// ---------------------------------------------------------------------------
+// Browser state
+// ---------------------------------------------------------------------------
+
+/// A server found on the LAN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredServer {
+    pub server: LanServer,
+    pub ping: Duration,
+    pub last_seen: Instant,
+}
+
+/// Bookkeeping for a browsing client: outstanding probes and the servers
+/// that have answered recently.
+#[derive(Debug, Default)]
+pub struct LanBrowser {
+    next_nonce: u64,
+    probes: FxHashMap<u64, Instant>,
+    servers: FxHashMap<String, DiscoveredServer>,
+}
+
+impl LanBrowser {
+    /// Record a probe sent at `now` and return its nonce.
+    pub fn probe(&mut self, now: Instant) -> u64 {
+        self.next_nonce += 1;
+        self.probes.insert(self.next_nonce, now);
+        self.next_nonce
+    }
+
+    /// Record a server's answer to the probe with `nonce`.
+    ///
+    /// Answers to probes we never sent (or have forgotten) are ignored.
+    pub fn on_announce(&mut self, nonce: u64, server: LanServer, now: Instant) {
+        let Some(sent) = self.probes.get(&nonce) else {
+            return;
+        };
+        let ping = now.saturating_duration_since(*sent);
+        self.servers.insert(
+            server.ticket.clone(),
+            DiscoveredServer {
+                server,
+                ping,
+                last_seen: now,
+            },
+        );
+    }
+
+    /// Forget servers and probes older than [`SERVER_TIMEOUT`].
+    pub fn expire(&mut self, now: Instant) {
+        self.servers
+            .retain(|_, s| now.saturating_duration_since(s.last_seen) < SERVER_TIMEOUT);
+        self.probes
+            .retain(|_, sent| now.saturating_duration_since(*sent) < SERVER_TIMEOUT);
+    }
+
+    /// The currently known servers, sorted by world name.
+    pub fn servers(&self) -> Vec<DiscoveredServer> {
+        let mut servers: Vec<DiscoveredServer> = self.servers.values().cloned().collect();
+        servers.sort_by(|a, b| a.server.world_name.cmp(&b.server.world_name));
+        servers
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tasks
+// ---------------------------------------------------------------------------
+
+/// Answer LAN probes on behalf of `server` until the server shuts down.
+/// Packets that cannot be received or answered are skipped.
+///
+/// # Errors
+///
+/// Returns an error if [`DISCOVERY_PORT`] cannot be bound (for example because
+/// another server on this machine already advertises).
+pub async fn advertise_lan(server: ServerHandle, ticket: String) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).await?;
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                log::warn!("LAN discovery could not receive a probe: {e}");
+                continue;
+            }
+        };
+        let Some(nonce) = buf
+            .get(..len)
+            .and_then(|probe| answerable(probe, from.ip()))
+        else {
+            continue;
+        };
+
+        let Ok(status) = server.status().await else {
+            return Ok(());
+        };
+        let announced = LanServer {
+            protocol_version: super::PROTOCOL_VERSION,
+            world_name: status.world_name,
+            host_name: status
+                .host_name
+                .unwrap_or_else(|| "Unknown host".to_owned()),
+            player_count: status.player_count,
+            ticket: ticket.clone(),
+        };
+
+        let reply = encode_packet(&LanPacket::Announce {
+            nonce,
+            server: announced,
+        });
+        if reply.len() > len {
+            log::warn!("LAN discovery cannot answer: the announcement outgrew the probe");
+            continue;
+        }
+        if let Err(e) = socket.send_to(&reply, from).await {
+            log::warn!("LAN discovery could not answer {from}: {e}");
+        }
+    }
+}
+
+/// The nonce of `packet`, if it is a probe we should answer: one from a
+/// private address, for our protocol version.
+fn answerable(packet: &[u8], from: IpAddr) -> Option<u64> {
+    let local = match from {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(_) => false,
+    };
+    match decode_packet(packet)? {
+        LanPacket::Probe {
+            nonce,
+            protocol_version,
+            ..
+        } if local && protocol_version == super::PROTOCOL_VERSION => Some(nonce),
+        _ => None,
+    }
+}
+
+/// Broadcast probes and send the list of answering servers to `tx` whenever
+/// it may have changed. Stops once the receiver is dropped.
+///
+/// # Errors
+///
+/// Returns an error if the socket cannot be opened or fails.
+pub async fn browse_lan(tx: QueueSender<Vec<DiscoveredServer>>) -> io::Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+
+    let mut browser = LanBrowser::default();
+    let mut interval = tokio::time::interval(PROBE_INTERVAL);
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let now = Instant::now();
+                browser.expire(now);
+                let probe = encode_packet(&LanPacket::probe(browser.probe(now)));
+                socket
+                    .send_to(&probe, (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+                    .await?;
+            }
+            received = socket.recv_from(&mut buf) => {
+                let (len, _) = received?;
+                if let Some(LanPacket::Announce { nonce, server }) =
+                    buf.get(..len).and_then(decode_packet)
+                {
+                    browser.on_announce(nonce, server, Instant::now());
+                }
+            }
+        }
+
+        if tx.send(browser.servers()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_server(world_name: &str) -> LanServer {
+        LanServer {
+            protocol_version: crate::net::PROTOCOL_VERSION,
+            world_name: world_name.into(),
+            host_name: "Alice".into(),
+            player_count: 2,
+            ticket: format!("gamik:{world_name}"),
+        }
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packet = LanPacket::Announce {
+            nonce: 7,
+            server: sample_server("Shire"),
+        };
+        assert_eq!(decode_packet(&encode_packet(&packet)), Some(packet));
+    }
+
+    #[test]
+    fn foreign_packets_are_ignored() {
+        assert_eq!(decode_packet(b"M-SEARCH * HTTP/1.1"), None);
+        assert_eq!(decode_packet(MAGIC), None);
+    }
+
+    #[test]
+    fn only_local_probes_for_our_version_are_answered() {
+        let lan = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        let internet = IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8));
+        let probe = encode_packet(&LanPacket::probe(7));
+        assert_eq!(answerable(&probe, lan), Some(7));
+        assert_eq!(answerable(&probe, internet), None);
+
+        let outdated = encode_packet(&LanPacket::Probe {
+            nonce: 7,
+            protocol_version: crate::net::PROTOCOL_VERSION + 1,
+            padding: Vec::new(),
+        });
+        assert_eq!(answerable(&outdated, lan), None);
+        let announce = encode_packet(&LanPacket::Announce {
+            nonce: 7,
+            server: sample_server("Shire"),
+        });
+        assert_eq!(answerable(&announce, lan), None);
+        assert_eq!(answerable(b"gamik-lan/1 hello", lan), None);
+    }
+
+    #[test]
+    fn probes_leave_room_for_the_answer() {
+        let mut server = sample_server("A rather long world name, as some people like them");
+        // A hex ticket with a relay and a handful of direct addresses
+        server.ticket = format!("gamik:{}", "0f".repeat(400));
+        let announce = encode_packet(&LanPacket::Announce { nonce: 7, server });
+        let probe = encode_packet(&LanPacket::probe(7));
+        assert!(announce.len() <= probe.len());
+        assert!(probe.len() <= MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn announce_measures_ping_from_probe() {
+        let mut browser = LanBrowser::default();
+        let sent = Instant::now();
+        let nonce = browser.probe(sent);
+
+        browser.on_announce(
+            nonce,
+            sample_server("Shire"),
+            sent + Duration::from_millis(30),
+        );
+
+        let servers = browser.servers();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(
+            servers.first().map(|s| s.ping),
+            Some(Duration::from_millis(30))
+        );
+    }
+
+    #[test]
+    fn unsolicited_announces_are_ignored() {
+        let mut browser = LanBrowser::default();
+        browser.on_announce(99, sample_server("Shire"), Instant::now());
+        assert!(browser.servers().is_empty());
+    }
+
+    #[test]
+    fn silent_servers_expire() {
+        let mut browser = LanBrowser::default();
+        let start = Instant::now();
+        let nonce = browser.probe(start);
+        browser.on_announce(nonce, sample_server("Shire"), start);
+
+        browser.expire(start + SERVER_TIMEOUT);
+
+        assert!(browser.servers().is_empty());
+    }
+
+    #[test]
+    fn servers_are_sorted_by_world_name() {
+        let mut browser = LanBrowser::default();
+        let now = Instant::now();
+        let nonce = browser.probe(now);
+        browser.on_announce(nonce, sample_server("Mordor"), now);
+        browser.on_announce(nonce, sample_server("Gondor"), now);
+
+        let names: Vec<String> = browser
+            .servers()
+            .into_iter()
+            .map(|s| s.server.world_name)
+            .collect();
+        assert_eq!(names, vec!["Gondor".to_owned(), "Mordor".to_owned()]);
+    }
+}
@@ -0,0 +1,433 @@
+//! Host migration.
+//!
+//! A world hosted from the game itself would end when its host quits. To
+//! avoid that, an operator can hand the world to another player with
+//! `/handoff <player>`:
+//!
+//! 1. The server freezes the world and, at the end of the tick, sends it to
+//!    the chosen player as [`ServerMessage::Handoff`].
+//! 2. That player's game starts its own server with the world and answers
+//!    with [`GameAction::HandoffReady`] carrying the new server's ticket.
+//! 3. The old server sends everyone [`ServerMessage::Redirect`] with that
+//!    ticket. Clients reconnect to it with the same key, so their accounts,
+//!    and with them their characters, carry over.
+//!
+//! If the chosen player disconnects, declines with
+//! [`GameAction::HandoffDeclined`] because it is hosting already, or does
+//! not answer within [`HANDOFF_TIMEOUT`] ticks before step 3, the handoff is
+//! called off and the world carries on where it is. Operators can also call
+//! it off themselves with `/handoff cancel`, the one command that runs while
+//! the world is frozen.
+//!
+//! [`GameAction::HandoffReady`]: crate::game::GameAction::HandoffReady
+//! [`GameAction::HandoffDeclined`]: crate::game::GameAction::HandoffDeclined
+
+use super::command::{Command, CommandError, parse_command};
+use super::{ActionError, ServerMessage, ServerState, ticket::ServerTicket};
+use crate::game::GameAction;
+
+use iroh::EndpointId;
+
+/// Ticks the new host has to answer before the handoff is called off; half
+/// a minute at the usual tick rate.
+pub const HANDOFF_TIMEOUT: u64 = 600;
+
+/// Whether `action` may be handled while the world is frozen for a handoff.
+///
+/// Only the new host's answer and `/handoff cancel`, so that an operator can
+/// call a stuck handoff off, are. Anything else would change a world that is
+/// about to be replaced by the copy already sent.
+pub fn allowed_during_handoff(action: &GameAction) -> bool {
+    match action {
+        GameAction::HandoffReady(_) | GameAction::HandoffDeclined => true,
+        GameAction::Command(line) => parse_command(line) == Ok(Command::CancelHandoff),
+        _ => false,
+    }
+}
+
+/// How far a handoff has got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandoffStage {
+    /// The world will be sent once the current tick is processed.
+    Requested,
+    /// The world was sent; waiting for the new host's ticket.
+    WorldSent,
+    /// Everyone was told to move to the new host.
+    Redirected,
+}
+
+/// A world on its way to another host. The world stays frozen meanwhile so
+/// that the copy the new host received is the one everybody continues with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handoff {
+    pub to: EndpointId,
+    pub stage: HandoffStage,
+    /// The tick the handoff was requested in.
+    pub since: u64,
+}
+
+impl ServerState {
+    /// Start handing the world to `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CommandError::HandoffInProgress`] if the world is already
+    /// being handed to somebody, or [`CommandError::AlreadyHosting`] if `to`
+    /// hosts it.
+    pub fn begin_handoff(&mut self, to: EndpointId) -> Result<String, CommandError> {
+        if self.handoff.is_some() {
+            return Err(CommandError::HandoffInProgress);
+        }
+        if self.host == Some(to) {
+            return Err(CommandError::AlreadyHosting);
+        }
+        let name = self.client_name(&to);
+        self.handoff = Some(Handoff {
+            to,
+            stage: HandoffStage::Requested,
+            since: self.tick,
+        });
+        self.broadcast(&ServerMessage::Notice(format!(
+            "The world is moving to {name}. Hold on, you will be moved along with it."
+        )));
+        Ok(format!("Handing the world to {name}."))
+    }
+
+    /// Send the world to the new host if a handoff was requested this tick.
+    /// Called at the end of [`Self::process_events`].
+    pub(super) fn send_handoff_world(&mut self) {
+        let Some(handoff) = &mut self.handoff else {
+            return;
+        };
+        if handoff.stage != HandoffStage::Requested {
+            return;
+        }
+        handoff.stage = HandoffStage::WorldSent;
+        let to = handoff.to;
+        self.send_to(to, ServerMessage::Handoff(Box::new(self.game.clone())));
+    }
+
+    /// `endpoint_id` reports that its server is running at `ticket`; send
+    /// everyone there.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ActionError::NotPermitted`] unless `endpoint_id` was sent
+    /// the world and has not answered yet, or [`ActionError::InvalidTicket`]
+    /// if `ticket` does not parse.
+    pub fn finish_handoff(
+        &mut self,
+        endpoint_id: EndpointId,
+        ticket: String,
+    ) -> Result<(), ActionError> {
+        let Some(handoff) = &mut self.handoff else {
+            return Err(ActionError::NotPermitted);
+        };
+        if handoff.to != endpoint_id || handoff.stage != HandoffStage::WorldSent {
+            return Err(ActionError::NotPermitted);
+        }
+        if ticket.parse::<ServerTicket>().is_err() {
+            return Err(ActionError::InvalidTicket);
+        }
+        handoff.stage = HandoffStage::Redirected;
+        self.broadcast(&ServerMessage::Redirect(ticket));
+        Ok(())
+    }
+
+    /// `endpoint_id` cannot take the world it was chosen for; keep it here.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ActionError::NotPermitted`] unless the world is being handed
+    /// to `endpoint_id` and nobody was redirected yet.
+    pub fn decline_handoff(&mut self, endpoint_id: EndpointId) -> Result<(), ActionError> {
+        if !self
+            .handoff
+            .is_some_and(|h| h.to == endpoint_id && h.stage != HandoffStage::Redirected)
+        {
+            return Err(ActionError::NotPermitted);
+        }
+        self.handoff = None;
+        let name = self.client_name(&endpoint_id);
+        self.broadcast(&ServerMessage::Notice(format!(
+            "{name} cannot take the world over; it stays here."
+        )));
+        Ok(())
+    }
+
+    /// Call the handoff off on an operator's request.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`CommandError::NoHandoff`] if the world is not being handed
+    /// to anybody.
+    pub fn cancel_handoff(&mut self) -> Result<String, CommandError> {
+        let Some(handoff) = self.handoff.take() else {
+            return Err(CommandError::NoHandoff);
+        };
+        let name = self.client_name(&handoff.to);
+        self.broadcast(&ServerMessage::Notice(format!(
+            "The move to {name} was called off; the world stays here."
+        )));
+        Ok(format!("Called off the handoff to {name}."))
+    }
+
+    /// Call off a handoff to `endpoint_id`, which disconnected before
+    /// everybody could be moved.
+    pub(super) fn cancel_handoff_to(&mut self, endpoint_id: EndpointId, name: &str) {
+        if self
+            .handoff
+            .is_some_and(|h| h.to == endpoint_id && h.stage != HandoffStage::Redirected)
+        {
+            self.handoff = None;
+            self.broadcast(&ServerMessage::Notice(format!(
+                "{name} left before taking over; the world stays here."
+            )));
+        }
+    }
+
+    /// Call off a handoff whose new host has not answered for
+    /// [`HANDOFF_TIMEOUT`] ticks. Called at the end of
+    /// [`Self::process_events`].
+    pub(super) fn expire_handoff(&mut self) {
+        let Some(handoff) = self.handoff else {
+            return;
+        };
+        if handoff.stage == HandoffStage::Redirected || self.tick < handoff.since + HANDOFF_TIMEOUT
+        {
+            return;
+        }
+        self.handoff = None;
+        let name = self.client_name(&handoff.to);
+        self.broadcast(&ServerMessage::Notice(format!(
+            "{name} did not take over in time; the world stays here."
+        )));
+    }
+
+    fn client_name(&self, endpoint_id: &EndpointId) -> String {
+        self.clients
+            .get(endpoint_id)
+            .map_or_else(|| "another player".to_owned(), |c| c.name.clone())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Direction, GameState};
+    use crate::net::account_of;
+    use crate::testing::endpoint;
+    use iroh::EndpointAddr;
+
+    fn ticket_for(endpoint_id: EndpointId) -> String {
+        ServerTicket::new(EndpointAddr::new(endpoint_id), "test".into()).to_string()
+    }
+
+    /// A server hosted by Alice with Bob and Carol playing along.
+    fn server_with_players() -> (ServerState, [EndpointId; 3]) {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let players = [endpoint(1), endpoint(2), endpoint(3)];
+        server.host = Some(players[0]);
+        for (endpoint_id, name) in players.into_iter().zip(["Alice", "Bob", "Carol"]) {
+            server.join(endpoint_id, name.into());
+            server.spawn_player_for(endpoint_id, name.into());
+        }
+        server.process_events();
+        server.unique_server_messages.clear();
+        (server, players)
+    }
+
+    fn messages(server: &ServerState, endpoint_id: EndpointId) -> &[ServerMessage] {
+        server
+            .unique_server_messages
+            .get(&endpoint_id)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    #[test]
+    fn world_is_sent_after_the_tick_it_was_requested_in() {
+        let (mut server, [alice, bob, _]) = server_with_players();
+        let bob_character = server.endpoints[&bob];
+        // Queued before the handoff, so it must be part of the world Bob gets
+        server.handle_action(bob, GameAction::Move(Direction::Right));
+        server.handle_action(alice, GameAction::Command("/handoff bob".into()));
+        assert!(
+            !messages(&server, bob)
+                .iter()
+                .any(|m| matches!(m, ServerMessage::Handoff(_)))
+        );
+
+        server.process_events();
+        let world = messages(&server, bob)
+            .iter()
+            .find_map(|m| match m {
+                ServerMessage::Handoff(world) => Some(world),
+                _ => None,
+            })
+            .expect("Bob was sent the world");
+        assert_eq!(
+            world.entities.position()[&bob_character],
+            server.game.entities.position()[&bob_character]
+        );
+        assert_eq!(world.characters_of(&account_of(&bob)), vec![bob_character]);
+    }
+
+    #[test]
+    fn world_is_frozen_during_a_handoff() {
+        let (mut server, [_, bob, carol]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.unique_server_messages.clear();
+
+        server.handle_action(carol, GameAction::Move(Direction::Right));
+        assert!(server.event_queue.is_empty());
+        assert!(messages(&server, carol).iter().any(|m| matches!(
+            m,
+            ServerMessage::ActionRejected {
+                reason: ActionError::HandingOff,
+                ..
+            }
+        )));
+        assert_eq!(
+            server.begin_handoff(carol),
+            Err(CommandError::HandoffInProgress)
+        );
+    }
+
+    #[test]
+    fn ready_new_host_redirects_everyone() {
+        let (mut server, [alice, bob, carol]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.process_events();
+        server.unique_server_messages.clear();
+
+        let ticket = ticket_for(bob);
+        server.handle_action(bob, GameAction::HandoffReady(ticket.clone()));
+        for endpoint_id in [alice, bob, carol] {
+            assert!(
+                messages(&server, endpoint_id)
+                    .iter()
+                    .any(|m| matches!(m, ServerMessage::Redirect(t) if *t == ticket))
+            );
+        }
+        assert_eq!(
+            server.handoff.map(|h| h.stage),
+            Some(HandoffStage::Redirected)
+        );
+    }
+
+    #[test]
+    fn only_the_chosen_player_may_report_ready() {
+        let (mut server, [_, bob, carol]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        assert_eq!(
+            server.finish_handoff(bob, ticket_for(bob)),
+            Err(ActionError::NotPermitted),
+            "the world has not been sent yet"
+        );
+        server.process_events();
+
+        assert_eq!(
+            server.finish_handoff(carol, ticket_for(carol)),
+            Err(ActionError::NotPermitted)
+        );
+        assert_eq!(
+            server.finish_handoff(bob, "not a ticket".into()),
+            Err(ActionError::InvalidTicket)
+        );
+        assert!(server.finish_handoff(bob, ticket_for(bob)).is_ok());
+    }
+
+    #[test]
+    fn handoff_is_called_off_when_the_new_host_leaves() {
+        let (mut server, [_, bob, carol]) = server_with_players();
+        let session = server.clients[&bob].session;
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.process_events();
+
+        server.leave(bob, session);
+        assert!(server.handoff.is_none());
+        server.handle_action(carol, GameAction::Move(Direction::Right));
+        server.process_events();
+        let carol_character = server.endpoints[&carol];
+        assert_eq!(
+            server.journal.back().map(|e| e.actor),
+            Some(carol_character)
+        );
+    }
+
+    #[test]
+    fn world_is_not_handed_to_its_host() {
+        let (mut server, [alice, _, _]) = server_with_players();
+        assert_eq!(
+            server.begin_handoff(alice),
+            Err(CommandError::AlreadyHosting)
+        );
+        assert!(server.handoff.is_none());
+    }
+
+    #[test]
+    fn new_host_may_decline() {
+        let (mut server, [_, bob, carol]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.process_events();
+
+        assert_eq!(
+            server.decline_handoff(carol),
+            Err(ActionError::NotPermitted)
+        );
+        server.handle_action(bob, GameAction::HandoffDeclined);
+        assert!(server.handoff.is_none());
+    }
+
+    #[test]
+    fn unanswered_handoff_is_called_off() {
+        let (mut server, [_, bob, _]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        for _ in 0..HANDOFF_TIMEOUT {
+            assert!(server.handoff.is_some());
+            server.process_events();
+        }
+        server.process_events();
+        assert!(server.handoff.is_none());
+    }
+
+    #[test]
+    fn operators_can_call_a_handoff_off() {
+        let (mut server, [alice, bob, _]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.process_events();
+        server.unique_server_messages.clear();
+
+        server.handle_action(alice, GameAction::Command("/handoff cancel".into()));
+        assert!(server.handoff.is_none());
+        assert!(
+            messages(&server, alice)
+                .iter()
+                .any(|m| matches!(m, ServerMessage::CommandResult(Ok(_))))
+        );
+        assert_eq!(server.cancel_handoff(), Err(CommandError::NoHandoff));
+    }
+
+    #[test]
+    fn other_commands_wait_for_the_handoff() {
+        let (mut server, [alice, bob, _]) = server_with_players();
+        server.begin_handoff(bob).expect("no handoff yet");
+        server.process_events();
+        server.unique_server_messages.clear();
+        let entities = server.game.entities.len();
+
+        server.handle_action(alice, GameAction::Command("/spawn tree 3 3".into()));
+        assert_eq!(server.game.entities.len(), entities);
+        assert!(messages(&server, alice).iter().any(|m| matches!(
+            m,
+            ServerMessage::ActionRejected {
+                reason: ActionError::HandingOff,
+                ..
+            }
+        )));
+    }
+}
@@ -0,0 +1,2043 @@
+//! Networking layer.
+//!
+//! Provides a [`Transport`] trait abstracting over real sockets and test
+//! channels, protocol message types, and the iroh-based server/client.
+
+pub mod combat;
+pub mod command;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod discovery;
+pub mod handoff;
+pub mod queue;
+pub mod sim;
+pub mod stats;
+pub mod tick;
+pub mod ticket;
+pub mod validation;
+pub mod wire;
+
+use crate::game::{
+    self, AccountID, ChatChannel, EntityID, EntityMap, GameAction, GameEvent, GameState,
+    PermissionLevel, Templates, carry,
+};
+
+use bitcode::{Decode, Encode};
+use iroh::{
+    Endpoint, EndpointAddr, EndpointId, SecretKey,
+    endpoint::{Connection, SendStream},
+    protocol::{AcceptError, ProtocolHandler, Router},
+};
+use n0_error::{Result, StdResultExt};
+use rustc_hash::FxHashMap;
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use command::CommandError;
+use handoff::Handoff;
+use queue::{OverflowPolicy, QueueReceiver, QueueSender};
+use sim::{Link, LinkClosed, NetworkConditions, Side};
+use stats::{ConnectionStats, NetStats, STATS_INTERVAL, ServerStats};
+use tick::ServerHandle;
+use ticket::ServerTicket;
+use tokio::sync::{Semaphore, mpsc};
+use validation::Validator;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// ALPN identifying the gamik wire protocol. The suffix is the major protocol
+/// generation; peers that disagree on it cannot even open a connection.
+const ALPN: &[u8] = b"gamik/1";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a client waits without hearing from the server before it
+/// considers the connection lost.
+const SERVER_SILENCE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive failed reconnects after which the client gives up.
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+/// How long a session has to stay up before a drop starts counting
+/// reconnects afresh. Shorter ones count as failed attempts, so a server
+/// that lets us in and drops us straight away is eventually given up on.
+const STABLE_SESSION: Duration = Duration::from_secs(10);
+/// Client streams the server reads at once; further ones wait in flow control.
+const MAX_CONCURRENT_READS: usize = 16;
+/// Events the client task may queue for a UI that stopped polling.
+pub const CLIENT_EVENT_CAPACITY: usize = 256;
+/// Actions the UI may queue while the connection is stalled.
+pub const CLIENT_ACTION_CAPACITY: usize = 64;
+/// Journal entries the server keeps; older ones are forgotten.
+pub const JOURNAL_CAPACITY: usize = 10_000;
+
+/// Revision of the message format within the current [`ALPN`] generation.
+///
+/// Bump this whenever [`Message`] or anything it contains changes shape,
+/// appended enum variants included: a peer on the older version would pass
+/// the handshake and then fail to decode the first message that uses one.
+pub const PROTOCOL_VERSION: u32 = 15;
+
+// ---------------------------------------------------------------------------
+// Type aliases
+// ---------------------------------------------------------------------------
+
+/// Maps connected endpoints to the entity they control.
+pub type EndpointMap = FxHashMap<EndpointId, EntityID>;
+
+// ---------------------------------------------------------------------------
+// Protocol messages
+// ---------------------------------------------------------------------------
+
+/// First message a client sends after connecting.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub client_name: String,
+    /// Watch the world without playing a character.
+    pub spectator: bool,
+}
+
+/// Server's reply to an accepted [`Hello`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct Welcome {
+    pub protocol_version: u32,
+    pub world_name: String,
+    pub player_count: u32,
+}
+
+/// Why the server refused a connection.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum RejectReason {
+    VersionMismatch {
+        server: u32,
+        client: u32,
+    },
+    HandshakeExpected,
+    /// An operator banned the client's account.
+    Banned,
+    /// The client's name was empty, too long, or had control characters in
+    /// it.
+    InvalidName {
+        max: u32,
+    },
+}
+
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::VersionMismatch { server, client } => write!(
+                f,
+                "Incompatible game version: the server speaks protocol v{server}, \
+                 this client speaks v{client}."
+            ),
+            Self::HandshakeExpected => write!(f, "The client did not start with a handshake."),
+            Self::Banned => write!(f, "You are banned from this server."),
+            Self::InvalidName { max } => write!(
+                f,
+                "Names must be 1-{max} characters long, without control characters."
+            ),
+        }
+    }
+}
+
+/// Why the server refused to carry out a [`GameAction`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum ActionError {
+    /// The requested character belongs to another account.
+    NotOwner(EntityID),
+    /// The action needs a character, but the client is not controlling one.
+    NoCharacter,
+    /// The client is sending actions faster than the server allows.
+    RateLimited,
+    /// The client's permission level is too low for this action.
+    NotPermitted,
+    /// The account already owns as many characters as allowed.
+    TooManyCharacters { max: u32 },
+    /// A chat message was empty or longer than `max` characters.
+    InvalidMessage { max: u32 },
+    /// Spectators can watch but not act.
+    Spectating,
+    /// The world is frozen while it moves to another host.
+    HandingOff,
+    /// A handoff ticket could not be parsed.
+    InvalidTicket,
+    /// A character name was empty, too long, or had control characters in it.
+    InvalidName { max: u32 },
+}
+
+impl std::fmt::Display for ActionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotOwner(eid) => write!(f, "Character {} belongs to someone else.", eid.0),
+            Self::NoCharacter => write!(f, "You are not controlling a character."),
+            Self::RateLimited => write!(f, "Slow down! You are acting too quickly."),
+            Self::NotPermitted => write!(f, "You are not allowed to do that."),
+            Self::TooManyCharacters { max } => {
+                write!(f, "You already have the maximum of {max} characters.")
+            }
+            Self::InvalidMessage { max } => {
+                write!(f, "Chat messages must be 1-{max} characters long.")
+            }
+            Self::Spectating => write!(f, "Spectators cannot do that."),
+            Self::HandingOff => write!(f, "Hold on, the world is moving to a new host."),
+            Self::InvalidTicket => write!(f, "That is not a valid server ticket."),
+            Self::InvalidName { max } => write!(
+                f,
+                "Names must be 1-{max} characters long, without control characters."
+            ),
+        }
+    }
+}
+
+/// A chat message as delivered to a listener.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct ChatLine {
+    pub speaker: EntityID,
+    /// The speaker's name at the time they spoke.
+    pub from: String,
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum ServerMessage {
+    EntityMap(Box<EntityMap>),
+    PlayerID(EntityID),
+    Welcome(Welcome),
+    Rejected(RejectReason),
+    /// The characters the receiving client's account may play.
+    OwnedCharacters(Vec<EntityID>),
+    /// Another player connected.
+    PlayerJoined(String),
+    /// Another player disconnected.
+    PlayerLeft(String),
+    Chat(ChatLine),
+    /// A message from the server itself, e.g. that the game was paused.
+    Notice(String),
+    /// The receiving client is being disconnected by an operator.
+    Kicked(String),
+    /// The outcome of an admin command the receiving client sent.
+    CommandResult(std::result::Result<String, CommandError>),
+    /// The server's side of the connection statistics, sent periodically.
+    Stats(ServerStats),
+    ActionRejected {
+        action: GameAction,
+        reason: ActionError,
+    },
+    /// The receiving client is to host the world from now on: it should
+    /// start a server with it and answer with [`GameAction::HandoffReady`].
+    Handoff(Box<GameState>),
+    /// The world moved to the server with this ticket; reconnect there.
+    Redirect(String),
+    /// The receiving client's character died, for the given reason, and
+    /// can no longer be played.
+    Died(String),
+}
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub enum Message {
+    Hello(Hello),
+    Client(GameAction),
+    Server(ServerMessage),
+}
+
+/// Notifications from the client networking task to the application.
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A message received from the server.
+    Server(ServerMessage),
+    /// The connection dropped and the client is trying to re-establish it.
+    Reconnecting { attempt: u32, reason: String },
+    /// The connection could not be established or has been closed for good.
+    Disconnected(String),
+    /// Connection statistics, reported periodically.
+    Stats(NetStats),
+}
+
+/// The persistent account an endpoint's public key belongs to.
+pub fn account_of(endpoint_id: &EndpointId) -> AccountID {
+    AccountID(*endpoint_id.as_bytes())
+}
+
+/// Check whether a client's [`Hello`] is acceptable to this server.
+///
+/// # Errors
+///
+/// Returns the reason to send back if the client must be turned away.
+pub fn check_hello(hello: &Hello) -> std::result::Result<(), RejectReason> {
+    if hello.protocol_version != PROTOCOL_VERSION {
+        return Err(RejectReason::VersionMismatch {
+            server: PROTOCOL_VERSION,
+            client: hello.protocol_version,
+        });
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Transport trait
+// ---------------------------------------------------------------------------
+
+/// Abstraction over a network transport so that game logic and tests can work
+/// with both real sockets and in-memory channels.
+pub trait Transport: Send + Sync + 'static {
+    /// Send a message to the remote peer.
+    fn send(&self, msg: Message) -> std::result::Result<(), Box<dyn std::error::Error + Send>>;
+    /// Try to receive a message (non-blocking).
+    fn try_recv(&mut self) -> Option<Message>;
+}
+
+/// In-memory transport for testing, one end of a simulated [`Link`].
+pub struct MockTransport {
+    link: Arc<std::sync::Mutex<Link>>,
+    side: Side,
+}
+
+impl MockTransport {
+    fn link(&self) -> std::sync::MutexGuard<'_, Link> {
+        self.link
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Move the link's clock forward, letting messages that were in flight
+    /// arrive. Both ends share the clock.
+    pub fn advance(&self, by: Duration) {
+        self.link().advance(by);
+    }
+
+    /// Time since the pair was created.
+    pub fn now(&self) -> Duration {
+        self.link().now()
+    }
+
+    /// Messages sent to this end that have not arrived yet.
+    pub fn in_flight(&self) -> usize {
+        self.link().in_flight(self.side)
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, msg: Message) -> std::result::Result<(), Box<dyn std::error::Error + Send>> {
+        if Arc::strong_count(&self.link) < 2 {
+            return Err(Box::new(LinkClosed));
+        }
+        self.link().send(self.side, msg);
+        Ok(())
+    }
+
+    fn try_recv(&mut self) -> Option<Message> {
+        self.link().recv(self.side)
+    }
+}
+
+/// Create a pair of connected [`MockTransport`]s for testing.
+pub fn mock_transport_pair() -> (MockTransport, MockTransport) {
+    mock_transport_pair_with(NetworkConditions::default())
+}
+
+/// Create a pair of [`MockTransport`]s joined by a link that behaves as
+/// `conditions` describe, in both directions.
+pub fn mock_transport_pair_with(conditions: NetworkConditions) -> (MockTransport, MockTransport) {
+    let link = Arc::new(std::sync::Mutex::new(Link::new(conditions)));
+    (
+        MockTransport {
+            link: link.clone(),
+            side: Side::A,
+        },
+        MockTransport {
+            link,
+            side: Side::B,
+        },
+    )
+}
+
+// ---------------------------------------------------------------------------
+// Server state (game state + networking bookkeeping)
+// ---------------------------------------------------------------------------
+
+/// An action the server applied, kept so a session can be reviewed or
+/// replayed later.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub tick: u64,
+    pub actor: EntityID,
+    pub action: GameAction,
+}
+
+/// What the server knows about a connected client from its [`Hello`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    /// Distinguishes successive connections from the same endpoint, so a
+    /// stale connection closing late cannot tear down its replacement.
+    pub session: u64,
+    /// Spectators receive the world but never control a character.
+    pub spectator: bool,
+}
+
+/// State owned by the server: the authoritative game state plus networking
+/// metadata that does not belong in the pure game layer.
+#[derive(Debug)]
+pub struct ServerState {
+    pub game: GameState,
+    pub endpoints: EndpointMap,
+    pub clients: FxHashMap<EndpointId, ClientInfo>,
+    /// Traffic counters for every connected client.
+    pub stats: FxHashMap<EndpointId, ConnectionStats>,
+    pub unique_server_messages: FxHashMap<EndpointId, Vec<ServerMessage>>,
+    pub event_queue: Vec<(EntityID, GameAction)>,
+    /// The last [`JOURNAL_CAPACITY`] actions applied, including chat, in
+    /// order.
+    pub journal: VecDeque<JournalEntry>,
+    pub validator: Validator,
+    /// Number of completed [`Self::process_events`] calls.
+    pub tick: u64,
+    /// How long the last [`Self::process_events`] call took.
+    pub tick_duration: Duration,
+    /// The endpoint of the player hosting this server, who may do anything.
+    pub host: Option<EndpointId>,
+    /// While set, characters cannot move; chat still works.
+    pub paused: bool,
+    /// The world is being handed to another host and is frozen until then.
+    pub handoff: Option<Handoff>,
+    /// The tick in which each character may take its next step; burdened
+    /// characters step less often.
+    next_step: FxHashMap<EntityID, u64>,
+    next_session: u64,
+}
+
+impl ServerState {
+    pub fn new(mut game: GameState) -> Self {
+        // Nobody is connected yet, so every character starts out asleep.
+        for eid in game.get_playable_entities() {
+            game::set_dormant(&mut game, eid, true);
+        }
+
+        Self {
+            game,
+            endpoints: EndpointMap::default(),
+            clients: FxHashMap::default(),
+            stats: FxHashMap::default(),
+            unique_server_messages: FxHashMap::default(),
+            event_queue: Vec::new(),
+            journal: VecDeque::new(),
+            validator: Validator::default(),
+            tick: 0,
+            tick_duration: Duration::ZERO,
+            host: None,
+            paused: false,
+            handoff: None,
+            next_step: FxHashMap::default(),
+            next_session: 0,
+        }
+    }
+
+    /// What `endpoint_id` is allowed to do on this server.
+    pub fn permission_of(&self, endpoint_id: &EndpointId) -> PermissionLevel {
+        if self.host.as_ref() == Some(endpoint_id) {
+            PermissionLevel::Operator
+        } else {
+            self.game.permission_of(&account_of(endpoint_id))
+        }
+    }
+
+    /// Number of connected clients that are playing rather than spectating.
+    pub fn player_count(&self) -> u32 {
+        self.clients.values().filter(|c| !c.spectator).count() as u32
+    }
+
+    /// Register a player that completed the handshake and return its session
+    /// number, to be handed back to [`Self::leave`].
+    ///
+    /// A reconnect from the same endpoint replaces the previous session.
+    pub fn join(&mut self, endpoint_id: EndpointId, name: String) -> u64 {
+        let session = self.connect(endpoint_id, name, false);
+        let name = self
+            .clients
+            .get(&endpoint_id)
+            .map(|c| c.name.clone())
+            .unwrap_or_default();
+
+        let owned = self.game.characters_of(&account_of(&endpoint_id));
+        self.send_to(endpoint_id, ServerMessage::OwnedCharacters(owned));
+        self.broadcast_except(endpoint_id, &ServerMessage::PlayerJoined(name));
+        session
+    }
+
+    /// Register a spectator, who is sent the world every tick like a player
+    /// but never controls a character. Returns its session number.
+    pub fn spectate(&mut self, endpoint_id: EndpointId, name: String) -> u64 {
+        self.connect(endpoint_id, name, true)
+    }
+
+    fn connect(&mut self, endpoint_id: EndpointId, name: String, spectator: bool) -> u64 {
+        self.next_session += 1;
+        let session = self.next_session;
+
+        if let Some(previous) = self.endpoints.remove(&endpoint_id) {
+            game::set_dormant(&mut self.game, previous, true);
+        }
+        self.unique_server_messages.remove(&endpoint_id);
+        self.stats
+            .insert(endpoint_id, ConnectionStats::new(Instant::now()));
+        let name = self.unique_name(endpoint_id, name);
+        self.clients.insert(
+            endpoint_id,
+            ClientInfo {
+                name,
+                session,
+                spectator,
+            },
+        );
+        session
+    }
+
+    /// `name`, or if another connected client goes by it, ignoring case,
+    /// `name` with the lowest number after it that nobody goes by, so that
+    /// admin commands can tell clients apart.
+    fn unique_name(&self, endpoint_id: EndpointId, name: String) -> String {
+        let taken = |candidate: &str| {
+            self.clients
+                .iter()
+                .any(|(id, c)| *id != endpoint_id && c.name.eq_ignore_ascii_case(candidate))
+        };
+        if !taken(&name) {
+            return name;
+        }
+        let mut n = 2;
+        while taken(&format!("{name}{n}")) {
+            n += 1;
+        }
+        format!("{name}{n}")
+    }
+
+    /// Forget a client whose connection closed and put its character to sleep.
+    ///
+    /// Does nothing if `session` has already been replaced by a newer
+    /// connection from the same endpoint.
+    pub fn leave(&mut self, endpoint_id: EndpointId, session: u64) {
+        if self
+            .clients
+            .get(&endpoint_id)
+            .is_none_or(|c| c.session != session)
+        {
+            return;
+        }
+
+        let Some(info) = self.clients.remove(&endpoint_id) else {
+            return;
+        };
+        self.unique_server_messages.remove(&endpoint_id);
+        self.validator.forget(&endpoint_id);
+        self.stats.remove(&endpoint_id);
+        if let Some(eid) = self.endpoints.remove(&endpoint_id) {
+            game::set_dormant(&mut self.game, eid, true);
+        }
+        self.cancel_handoff_to(endpoint_id, &info.name);
+        if !info.spectator {
+            self.broadcast(&ServerMessage::PlayerLeft(info.name));
+        }
+    }
+
+    /// Queue a message for delivery to a single client on its next update.
+    pub fn send_to(&mut self, endpoint_id: EndpointId, msg: ServerMessage) {
+        self.unique_server_messages
+            .entry(endpoint_id)
+            .or_default()
+            .push(msg);
+    }
+
+    /// Queue a message for every connected client.
+    pub fn broadcast(&mut self, msg: &ServerMessage) {
+        let recipients: Vec<EndpointId> = self.clients.keys().copied().collect();
+        for endpoint_id in recipients {
+            self.send_to(endpoint_id, msg.clone());
+        }
+    }
+
+    /// Queue a message for every connected client except `excluded`.
+    pub fn broadcast_except(&mut self, excluded: EndpointId, msg: &ServerMessage) {
+        let recipients: Vec<EndpointId> = self
+            .clients
+            .keys()
+            .copied()
+            .filter(|id| *id != excluded)
+            .collect();
+        for endpoint_id in recipients {
+            self.send_to(endpoint_id, msg.clone());
+        }
+    }
+
+    /// Hand `entity_id` to `endpoint_id`, waking it up and putting the
+    /// endpoint's previous character (if any) to sleep.
+    fn control(&mut self, endpoint_id: EndpointId, entity_id: EntityID) {
+        if let Some(previous) = self.endpoints.insert(endpoint_id, entity_id) {
+            if previous != entity_id {
+                game::set_dormant(&mut self.game, previous, true);
+            }
+        }
+        game::set_dormant(&mut self.game, entity_id, false);
+        self.send_to(endpoint_id, ServerMessage::PlayerID(entity_id));
+    }
+
+    /// Create a new character owned by `endpoint_id`'s account and make it
+    /// the entity that endpoint controls.
+    pub fn spawn_player_for(&mut self, endpoint_id: EndpointId, name: String) -> EntityID {
+        let account = account_of(&endpoint_id);
+        let pid = game::spawn_player(&mut self.game, name);
+        game::claim_character(&mut self.game, account, pid);
+
+        let owned = self.game.characters_of(&account);
+        self.send_to(endpoint_id, ServerMessage::OwnedCharacters(owned));
+        self.control(endpoint_id, pid);
+        pid
+    }
+
+    /// Let `endpoint_id` take control of an existing character, provided its
+    /// account owns it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ActionError::NotOwner`] if the character belongs to another
+    /// account.
+    pub fn spawn_as(
+        &mut self,
+        endpoint_id: EndpointId,
+        entity_id: EntityID,
+    ) -> std::result::Result<(), ActionError> {
+        if !self.game.owns(&account_of(&endpoint_id), entity_id) {
+            return Err(ActionError::NotOwner(entity_id));
+        }
+        self.control(endpoint_id, entity_id);
+        Ok(())
+    }
+
+    /// Tell `endpoint_id` that its `action` was not carried out.
+    fn reject(&mut self, endpoint_id: EndpointId, action: GameAction, reason: ActionError) {
+        if let Some(stats) = self.stats.get_mut(&endpoint_id) {
+            stats.rejected_actions += 1;
+        }
+        self.send_to(
+            endpoint_id,
+            ServerMessage::ActionRejected { action, reason },
+        );
+    }
+
+    /// Validate and handle an action received from `endpoint_id`.
+    ///
+    /// Connection-level actions take effect immediately; everything else is
+    /// queued for the controlled entity until the next [`Self::process_events`].
+    /// Rejected actions are reported back with [`ServerMessage::ActionRejected`].
+    pub fn handle_action(&mut self, endpoint_id: EndpointId, action: GameAction) {
+        let permission = self.permission_of(&endpoint_id);
+        let owned = self.game.characters_of(&account_of(&endpoint_id)).len();
+        if let Err(reason) = self
+            .validator
+            .check(endpoint_id, &action, permission, owned)
+        {
+            self.reject(endpoint_id, action, reason);
+            return;
+        }
+
+        let networking = matches!(
+            action,
+            GameAction::Command(_) | GameAction::HandoffReady(_) | GameAction::HandoffDeclined
+        );
+        let spectating = self.clients.get(&endpoint_id).is_some_and(|c| c.spectator);
+        if spectating && !networking {
+            self.reject(endpoint_id, action, ActionError::Spectating);
+            return;
+        }
+        if self.handoff.is_some() && !handoff::allowed_during_handoff(&action) {
+            self.reject(endpoint_id, action, ActionError::HandingOff);
+            return;
+        }
+
+        match action {
+            GameAction::SpawnPlayer(name) => {
+                self.spawn_player_for(endpoint_id, name);
+            }
+            GameAction::SpawnAs(eid) => {
+                if let Err(reason) = self.spawn_as(endpoint_id, eid) {
+                    self.reject(endpoint_id, GameAction::SpawnAs(eid), reason);
+                }
+            }
+            GameAction::Command(line) => {
+                let result = self.run_command(Some(endpoint_id), &line);
+                self.send_to(endpoint_id, ServerMessage::CommandResult(result));
+            }
+            GameAction::HandoffReady(ticket) => {
+                if let Err(reason) = self.finish_handoff(endpoint_id, ticket.clone()) {
+                    self.reject(endpoint_id, GameAction::HandoffReady(ticket), reason);
+                }
+            }
+            GameAction::HandoffDeclined => {
+                if let Err(reason) = self.decline_handoff(endpoint_id) {
+                    self.reject(endpoint_id, GameAction::HandoffDeclined, reason);
+                }
+            }
+            other => match self.endpoints.get(&endpoint_id).copied() {
+                Some(pid) => self.event_queue.push((pid, other)),
+                None => self.reject(endpoint_id, other, ActionError::NoCharacter),
+            },
+        }
+    }
+
+    /// Drain the event queue and apply each action to the game state.
+    pub fn process_events(&mut self) {
+        let started = Instant::now();
+        let events: Vec<(EntityID, GameAction)> = self.event_queue.drain(..).collect();
+
+        for (eid, action) in events {
+            match &action {
+                GameAction::Move(_)
+                | GameAction::MoveDiagonal(_)
+                | GameAction::Turn(_)
+                | GameAction::Walk { .. } => {
+                    if self.paused || !self.ready_to_step(eid) {
+                        continue;
+                    }
+                    game::apply(&mut self.game, eid, &action);
+                }
+                GameAction::Attack(_) | GameAction::AttackPart { .. } | GameAction::Fire { .. } => {
+                    if self.paused || !self.game.entities.contains(eid) {
+                        continue;
+                    }
+                    let events = game::apply(&mut self.game, eid, &action);
+                    self.relay_combat(events);
+                }
+                GameAction::PickUp(_)
+                | GameAction::Drop(_)
+                | GameAction::Equip(_)
+                | GameAction::Unequip(_)
+                | GameAction::Store { .. }
+                | GameAction::Organize => {
+                    if self.paused {
+                        continue;
+                    }
+                    for event in game::apply(&mut self.game, eid, &action) {
+                        if let GameEvent::ItemRefused { entity_id, reason } = event {
+                            self.notify_controller(entity_id, &reason.to_string());
+                        }
+                    }
+                }
+                GameAction::Modify { .. } => {
+                    if self.paused {
+                        continue;
+                    }
+                    for event in game::apply(&mut self.game, eid, &action) {
+                        if let GameEvent::ModifyRefused { entity_id, reason } = event {
+                            self.notify_controller(entity_id, &reason.to_string());
+                        }
+                    }
+                }
+                GameAction::SpawnPlayer(_)
+                | GameAction::SpawnAs(_)
+                | GameAction::Command(_)
+                | GameAction::HandoffReady(_)
+                | GameAction::HandoffDeclined => {
+                    // Handled as soon as they arrive, in `handle_action`.
+                }
+                GameAction::SaveWorld => {
+                    let _ = game::save_to_file(&self.game);
+                }
+                GameAction::SpawnTemplate { .. } => {
+                    game::apply(&mut self.game, eid, &action);
+                }
+                GameAction::Say(_)
+                | GameAction::Whisper { .. }
+                | GameAction::Emote(_)
+                | GameAction::SayGlobal(_) => {
+                    for event in game::apply(&mut self.game, eid, &action) {
+                        if let GameEvent::Chat {
+                            speaker,
+                            channel,
+                            text,
+                        } = event
+                        {
+                            self.relay_chat(speaker, channel, text);
+                        }
+                    }
+                }
+            }
+            self.record(JournalEntry {
+                tick: self.tick,
+                actor: eid,
+                action,
+            });
+        }
+
+        if !self.paused {
+            let events = game::step(&mut self.game);
+            self.relay_combat(events);
+        }
+        self.expire_handoff();
+        self.send_handoff_world();
+        self.tick += 1;
+        self.validator.begin_tick(self.tick);
+        self.tick_duration = started.elapsed();
+    }
+
+    /// Add `entry` to the journal, forgetting the oldest entry if it is full.
+    fn record(&mut self, entry: JournalEntry) {
+        if self.journal.len() >= JOURNAL_CAPACITY {
+            self.journal.pop_front();
+        }
+        self.journal.push_back(entry);
+    }
+
+    /// Summarise the server's side of `endpoint_id`'s connection.
+    pub fn server_stats(&mut self, endpoint_id: EndpointId) -> ServerStats {
+        let mut report = ServerStats {
+            tick: self.tick,
+            tick_duration_micros: self.tick_duration.as_micros() as u64,
+            queue_depth: self
+                .unique_server_messages
+                .get(&endpoint_id)
+                .map_or(0, Vec::len) as u32,
+            ..ServerStats::default()
+        };
+        if let Some(stats) = self.stats.get_mut(&endpoint_id) {
+            report.traffic = stats.traffic(Instant::now());
+            report.rejected_actions = stats.rejected_actions;
+            report.dropped_actions = stats.dropped_messages;
+            report.skipped_updates = stats.skipped_updates;
+        }
+        report
+    }
+
+    /// Whether what `entity_id` carries lets it take a step, or turn, this
+    /// tick. If so, the step is booked and the next one has to wait
+    /// accordingly.
+    fn ready_to_step(&mut self, entity_id: EntityID) -> bool {
+        let encumbrance = carry::encumbrance(&self.game.entities, entity_id);
+        let Some(ticks) = encumbrance.ticks_per_step() else {
+            self.notify_controller(entity_id, "You are carrying too much to move.");
+            return false;
+        };
+        if self
+            .next_step
+            .get(&entity_id)
+            .is_some_and(|&tick| self.tick < tick)
+        {
+            return false;
+        }
+        self.next_step.insert(entity_id, self.tick + ticks);
+        true
+    }
+
+    /// Send a notice to whoever controls `entity_id`, if anyone does.
+    fn notify_controller(&mut self, entity_id: EntityID, text: &str) {
+        let controllers: Vec<EndpointId> = self
+            .endpoints
+            .iter()
+            .filter(|(_, eid)| **eid == entity_id)
+            .map(|(endpoint_id, _)| *endpoint_id)
+            .collect();
+        for endpoint_id in controllers {
+            self.send_to(endpoint_id, ServerMessage::Notice(text.to_owned()));
+        }
+    }
+
+    /// Deliver a chat message to every client whose character can hear it.
+    ///
+    /// Clients without a character only receive the global channel.
+    fn relay_chat(&mut self, speaker: EntityID, channel: ChatChannel, text: String) {
+        let entities = &self.game.entities;
+        if !entities.contains(speaker) {
+            return;
+        }
+        let from = entities.name_of(speaker).unwrap_or("Someone").to_owned();
+        let recipients: Vec<EndpointId> = self
+            .clients
+            .keys()
+            .copied()
+            .filter(|id| match self.endpoints.get(id) {
+                Some(listener) => game::can_hear(&self.game, speaker, *listener, &channel),
+                None => channel == ChatChannel::Global,
+            })
+            .collect();
+
+        let line = ChatLine {
+            speaker,
+            from,
+            channel,
+            text,
+        };
+        for endpoint_id in recipients {
+            self.send_to(endpoint_id, ServerMessage::Chat(line.clone()));
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Iroh helpers
+// ---------------------------------------------------------------------------
+
+/// Send one message on a new unidirectional stream, returning its size in
+/// bytes.
+async fn send_one_way(conn: &Connection, msg: &Message) -> Result<usize> {
+    let mut send = conn.open_uni().await.anyerr()?;
+    write_message(&mut send, msg).await
+}
+
+/// Write one message to `send` and finish the stream, returning its size in
+/// bytes.
+async fn write_message(send: &mut SendStream, msg: &Message) -> Result<usize> {
+    let encoded = wire::encode(msg);
+    send.write_all(&encoded).await.anyerr()?;
+    send.finish().anyerr()?;
+    Ok(encoded.len())
+}
+
+/// Receive one message of at most `limit` bytes from a unidirectional
+/// stream.
+async fn recv_one_way(recv: iroh::endpoint::RecvStream, limit: usize) -> Result<Message> {
+    read_message(recv, limit).await.map(|(msg, _)| msg)
+}
+
+/// Receive one message of at most `limit` bytes, as sent and decompressed,
+/// and its size, from a unidirectional stream.
+async fn read_message(
+    mut recv: iroh::endpoint::RecvStream,
+    limit: usize,
+) -> Result<(Message, usize)> {
+    let bytes = recv.read_to_end(limit).await.anyerr()?;
+    let msg = wire::decode(&bytes, limit).anyerr()?;
+    Ok((msg, bytes.len()))
+}
+
+// ---------------------------------------------------------------------------
+// Server
+// ---------------------------------------------------------------------------
+
+/// Start serving `game`.
+///
+/// `host` is the endpoint of the player running the server, if any; it gets
+/// operator permissions.
+///
+/// # Errors
+///
+/// Returns an error if the entity templates are invalid or the endpoint
+/// cannot be bound.
+pub async fn run_server_internal(game: GameState, host: Option<EndpointId>) -> Result<Router> {
+    let (router, _) = start_server(game, host).await?;
+    Ok(router)
+}
+
+/// Serve `game` without a local player, reading admin commands from stdin
+/// until it is closed.
+///
+/// # Errors
+///
+/// Returns an error if the entity templates are invalid or the endpoint
+/// cannot be bound.
+#[expect(clippy::print_stdout, reason = "stdout is the server console")]
+pub async fn run_dedicated_server(game: GameState) -> Result<()> {
+    let world_name = game.world_name.clone();
+    let (router, server) = start_server(game, None).await?;
+    let ticket = ServerTicket::new(router.endpoint().addr(), world_name.clone());
+    println!("Serving \"{world_name}\". Players can join with this ticket:\n{ticket}");
+
+    let mut console = console_lines();
+    while let Some(line) = console.recv().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match server.run_command(line).await {
+            Ok(Ok(output)) => println!("{output}"),
+            Ok(Err(e)) => println!("{e}"),
+            Err(e) => {
+                println!("{e}");
+                break;
+            }
+        }
+    }
+
+    router.shutdown().await.anyerr()?;
+    Ok(())
+}
+
+/// Lines typed on stdin, read on a dedicated thread since stdin blocks.
+fn console_lines() -> mpsc::Receiver<String> {
+    // Typing blocks once this many lines are waiting, which nobody will notice
+    let (tx, rx) = mpsc::channel(64);
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Load the entity templates, bind an endpoint, start accepting players and
+/// advertise on the LAN.
+async fn start_server(
+    mut game: GameState,
+    host: Option<EndpointId>,
+) -> Result<(Router, ServerHandle)> {
+    game.templates = Templates::load(Path::new(game::template::TEMPLATE_DIR)).anyerr()?;
+    let endpoint = Endpoint::bind().await?;
+    #[cfg(not(target_arch = "wasm32"))]
+    let world_name = game.world_name.clone();
+    let echo = Echo::new(game, host);
+    let server = echo.server.clone();
+
+    let router = Router::builder(endpoint).accept(ALPN, echo).spawn();
+
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+
+    // Let players on the same network find us without exchanging tickets
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let ticket = ServerTicket::new(router.endpoint().addr(), world_name).to_string();
+        let lan_server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = discovery::advertise_lan(lan_server, ticket).await {
+                log::warn!("LAN discovery unavailable: {e}");
+            }
+        });
+    }
+
+    Ok((router, server))
+}
+
+#[derive(Debug, Clone)]
+struct Echo {
+    server: ServerHandle,
+}
+
+impl Echo {
+    fn new(game: GameState, host: Option<EndpointId>) -> Self {
+        let mut state = ServerState::new(game);
+        state.host = host;
+        Self {
+            server: ServerHandle::spawn(state),
+        }
+    }
+}
+
+/// A client that completed the handshake.
+struct Admitted {
+    session: u64,
+    /// Updates the tick task produces for this client.
+    updates: QueueReceiver<Message>,
+}
+
+/// Perform the server side of the [`Hello`]/[`Welcome`] exchange on the first
+/// bidirectional stream of `connection`, joining the client to the game.
+///
+/// Returns `None` if a [`ServerMessage::Rejected`] was sent instead.
+async fn accept_handshake(
+    connection: &Connection,
+    server: &ServerHandle,
+) -> Result<Option<Admitted>> {
+    let (mut send, recv) = connection.accept_bi().await.anyerr()?;
+    let endpoint_id = connection.remote_id();
+
+    let hello = match recv_one_way(recv, wire::MAX_CLIENT_MESSAGE_SIZE).await? {
+        Message::Hello(hello) => check_hello(&hello).map(|()| hello),
+        Message::Client(_) | Message::Server(_) => Err(RejectReason::HandshakeExpected),
+    };
+    let (outbox, updates) = tick::outbox();
+    let verdict = match hello {
+        Ok(hello) => server.join(endpoint_id, hello, outbox).await.anyerr()?,
+        Err(reason) => Err(reason),
+    };
+
+    match verdict {
+        Ok((welcome, session)) => {
+            let welcomed =
+                write_message(&mut send, &Message::Server(ServerMessage::Welcome(welcome))).await;
+            if let Err(e) = welcomed {
+                server.leave(endpoint_id, session).await.anyerr()?;
+                return Err(e);
+            }
+            Ok(Some(Admitted { session, updates }))
+        }
+        Err(reason) => {
+            write_message(&mut send, &Message::Server(ServerMessage::Rejected(reason))).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Write everything the tick task queues for `endpoint_id` until the client
+/// goes away, is kicked, or falls so far behind that its outbox overflows.
+async fn write_updates(
+    connection: Connection,
+    server: ServerHandle,
+    endpoint_id: EndpointId,
+    mut updates: QueueReceiver<Message>,
+) {
+    while let Some(first) = updates.recv().await {
+        let batch: Vec<Message> = std::iter::once(first)
+            .chain(std::iter::from_fn(|| updates.try_recv()))
+            .collect();
+        let kicked = batch
+            .iter()
+            .any(|r| matches!(r, Message::Server(ServerMessage::Kicked(_))));
+
+        let mut sizes = Vec::with_capacity(batch.len());
+        for msg in &batch {
+            match send_one_way(&connection, msg).await {
+                Ok(bytes) => sizes.push(bytes),
+                Err(e) => {
+                    log::warn!("Error sending periodic update to client: {e}");
+                    return;
+                }
+            }
+        }
+        if server.sent(endpoint_id, sizes).await.is_err() {
+            return;
+        }
+
+        if kicked {
+            hang_up(&connection, b"kicked").await;
+            return;
+        }
+    }
+
+    if updates.metrics().overflowed {
+        let reason = "Your connection could not keep up with the server.".to_owned();
+        let kicked = Message::Server(ServerMessage::Kicked(reason));
+        if let Err(e) = send_one_way(&connection, &kicked).await {
+            log::warn!("Could not tell a client that is too slow why it is dropped: {e}");
+        }
+        hang_up(&connection, b"too slow").await;
+    }
+}
+
+/// Let the client read why it is being disconnected before hanging up on it,
+/// unless it closes the connection itself first.
+async fn hang_up(connection: &Connection, reason: &[u8]) {
+    let closed = tokio::time::timeout(HANDSHAKE_TIMEOUT, connection.closed()).await;
+    if closed.is_err() {
+        connection.close(0u32.into(), reason);
+    }
+}
+
+impl ProtocolHandler for Echo {
+    async fn accept(&self, connection: Connection) -> std::result::Result<(), AcceptError> {
+        let admitted = match tokio::time::timeout(
+            HANDSHAKE_TIMEOUT,
+            accept_handshake(&connection, &self.server),
+        )
+        .await
+        {
+            Ok(Ok(Some(admitted))) => admitted,
+            Ok(Ok(None)) => {
+                hang_up(&connection, b"rejected").await;
+                return Ok(());
+            }
+            Ok(Err(e)) => {
+                log::warn!("Handshake with client failed: {e}");
+                return Ok(());
+            }
+            Err(_) => {
+                log::warn!("Client did not complete the handshake in time");
+                return Ok(());
+            }
+        };
+
+        let endpoint_id = connection.remote_id();
+        let writer = tokio::spawn(write_updates(
+            connection.clone(),
+            self.server.clone(),
+            endpoint_id,
+            admitted.updates,
+        ));
+
+        // Accept incoming streams until the client goes away. Only a few are
+        // read at once; the rest wait in the client's flow control.
+        let reads = Arc::new(Semaphore::new(MAX_CONCURRENT_READS));
+        loop {
+            let Ok(permit) = reads.clone().acquire_owned().await else {
+                break;
+            };
+            let Ok(recv) = connection.accept_uni().await else {
+                break;
+            };
+            let server = self.server.clone();
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                let delivered = match read_message(recv, wire::MAX_CLIENT_MESSAGE_SIZE).await {
+                    Ok((Message::Client(action), bytes)) => {
+                        server.action(endpoint_id, action, bytes).await
+                    }
+                    Ok((Message::Server(_) | Message::Hello(_), _)) => {
+                        log::warn!("Server received unexpected message after handshake");
+                        server.unreadable(endpoint_id).await
+                    }
+                    Err(e) => {
+                        log::warn!("Error receiving message: {e}");
+                        server.unreadable(endpoint_id).await
+                    }
+                };
+                if delivered.is_err() {
+                    // Nothing is left to play on; the loop above ends with it
+                    connection.close(0u32.into(), b"server gone");
+                }
+            });
+        }
+
+        writer.abort();
+        self.server
+            .leave(endpoint_id, admitted.session)
+            .await
+            .map_err(AcceptError::from_err)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Client
+// ---------------------------------------------------------------------------
+
+/// Perform the client side of the [`Hello`]/[`Welcome`] exchange and return
+/// the server's answer.
+async fn client_handshake(conn: &Connection, hello: Hello) -> Result<Message> {
+    let (mut send, recv) = conn.open_bi().await.anyerr()?;
+    write_message(&mut send, &Message::Hello(hello)).await?;
+    recv_one_way(recv, wire::MAX_MESSAGE_SIZE).await
+}
+
+/// Connect to a server, perform the handshake and then pump messages in both
+/// directions until the application hangs up, reconnecting automatically if
+/// an established connection drops.
+///
+/// Every outcome the player should see — including failing to connect — is
+/// reported through `tx`.
+///
+/// `secret_key` is the client's long-lived identity; reusing it across
+/// sessions is what lets the server recognise the player's account.
+///
+/// # Errors
+///
+/// Returns an error if the first connection attempt fails.
+pub async fn run_client_internal(
+    addr: impl Into<EndpointAddr>,
+    secret_key: SecretKey,
+    hello: Hello,
+    tx: QueueSender<ClientEvent>,
+    rx: QueueReceiver<GameAction>,
+) -> Result<()> {
+    let result = run_client_sessions(addr.into(), secret_key, hello, &tx, rx).await;
+    if let Err(e) = &result {
+        let event = ClientEvent::Disconnected(format!(
+            "Could not connect to the server (it may be running an incompatible \
+             version of gamik): {e}"
+        ));
+        if !deliver(&tx, event) {
+            log::warn!("Could not connect to the server: {e}");
+        }
+    }
+    result
+}
+
+/// Queue `event` for the application. Returns `false` once the application
+/// stopped listening, which ends the session as if it had quit.
+fn deliver(tx: &QueueSender<ClientEvent>, event: ClientEvent) -> bool {
+    tx.send(event).is_ok()
+}
+
+/// Create the queues between the UI and [`run_client_internal`].
+///
+/// Events for the UI drop the oldest news, but no control events, once
+/// full; actions for the server refuse new ones once full.
+pub fn client_queues() -> (queue::Channel<ClientEvent>, queue::Channel<GameAction>) {
+    (
+        queue::channel(CLIENT_EVENT_CAPACITY, OverflowPolicy::DropOldest),
+        queue::channel(CLIENT_ACTION_CAPACITY, OverflowPolicy::DropNewest),
+    )
+}
+
+/// Delay before reconnection attempt number `attempt` (starting at 1).
+pub fn reconnect_delay(attempt: u32) -> Duration {
+    Duration::from_millis(500 * 2u64.pow(attempt.min(4)))
+}
+
+/// How a client session that got as far as the handshake ended.
+enum SessionEnd {
+    /// The application dropped its action sender.
+    Quit,
+    /// The server refused the handshake or kicked us.
+    Rejected,
+    /// An established connection dropped.
+    Lost(String),
+    /// The world moved to another host, which we should connect to instead.
+    Redirected(EndpointAddr),
+}
+
+/// Run sessions back to back until the player quits, the server refuses us,
+/// or [`MAX_RECONNECT_ATTEMPTS`] consecutive reconnects have failed.
+///
+/// Errors before the first successful handshake are returned to the caller.
+async fn run_client_sessions(
+    mut addr: EndpointAddr,
+    secret_key: SecretKey,
+    hello: Hello,
+    tx: &QueueSender<ClientEvent>,
+    mut rx: QueueReceiver<GameAction>,
+) -> Result<()> {
+    let endpoint = Endpoint::builder().secret_key(secret_key).bind().await?;
+    let mut controlled = None;
+    let mut reconnecting = false;
+    let mut attempt = 0;
+
+    loop {
+        let started = Instant::now();
+        let session = run_client_session(
+            &endpoint,
+            addr.clone(),
+            hello.clone(),
+            tx,
+            &mut rx,
+            &mut controlled,
+        )
+        .await;
+
+        let reason = match session {
+            Ok(SessionEnd::Quit | SessionEnd::Rejected) => return Ok(()),
+            Ok(SessionEnd::Lost(reason)) => {
+                reconnecting = true;
+                if started.elapsed() >= STABLE_SESSION {
+                    attempt = 0;
+                }
+                reason
+            }
+            Ok(SessionEnd::Redirected(new_host)) => {
+                // The new host is already up, so connect straight away
+                addr = new_host;
+                reconnecting = true;
+                attempt = 0;
+                continue;
+            }
+            Err(e) if !reconnecting => return Err(e),
+            Err(e) => e.to_string(),
+        };
+
+        attempt += 1;
+        let giving_up = attempt > MAX_RECONNECT_ATTEMPTS;
+        let event = if giving_up {
+            ClientEvent::Disconnected(format!("Connection lost: {reason}"))
+        } else {
+            ClientEvent::Reconnecting { attempt, reason }
+        };
+        // Nobody is left to reconnect for once the application stopped listening
+        if !deliver(tx, event) || giving_up {
+            return Ok(());
+        }
+        tokio::time::sleep(reconnect_delay(attempt)).await;
+    }
+}
+
+/// How `msg` ends the session it arrived on, if it does, and the reason to
+/// close the connection with.
+fn ends_session(msg: &ServerMessage) -> Option<(SessionEnd, &'static [u8])> {
+    match msg {
+        ServerMessage::Kicked(_) => Some((SessionEnd::Rejected, b"kicked")),
+        ServerMessage::Redirect(ticket) => {
+            let ticket = ticket.parse::<ServerTicket>().ok()?;
+            Some((SessionEnd::Redirected(ticket.addr), b"redirected"))
+        }
+        _ => None,
+    }
+}
+
+/// Connect once, perform the handshake and pump messages until the
+/// connection ends.
+///
+/// `controlled` remembers the character this client plays so that a later
+/// session can resume it.
+async fn run_client_session(
+    endpoint: &Endpoint,
+    addr: EndpointAddr,
+    hello: Hello,
+    tx: &QueueSender<ClientEvent>,
+    rx: &mut QueueReceiver<GameAction>,
+    controlled: &mut Option<EntityID>,
+) -> Result<SessionEnd> {
+    let conn = endpoint.connect(addr, ALPN).await?;
+
+    match client_handshake(&conn, hello).await? {
+        Message::Server(ServerMessage::Welcome(welcome)) => {
+            if !deliver(tx, ClientEvent::Server(ServerMessage::Welcome(welcome))) {
+                conn.close(0u32.into(), b"bye");
+                return Ok(SessionEnd::Quit);
+            }
+        }
+        Message::Server(ServerMessage::Rejected(reason)) => {
+            conn.close(0u32.into(), b"rejected");
+            if !deliver(tx, ClientEvent::Server(ServerMessage::Rejected(reason))) {
+                return Ok(SessionEnd::Quit);
+            }
+            return Ok(SessionEnd::Rejected);
+        }
+        other => {
+            conn.close(0u32.into(), b"bad handshake");
+            let event = ClientEvent::Disconnected(format!(
+                "Server sent an unexpected handshake reply: {other:?}"
+            ));
+            if !deliver(tx, event) {
+                return Ok(SessionEnd::Quit);
+            }
+            return Ok(SessionEnd::Rejected);
+        }
+    }
+
+    // Pick up the character we were playing before the connection dropped
+    if let Some(eid) = *controlled {
+        send_one_way(&conn, &Message::Client(GameAction::SpawnAs(eid))).await?;
+    }
+
+    let mut stats = ConnectionStats::new(Instant::now());
+    let mut server_stats = None;
+    let mut report = tokio::time::interval(STATS_INTERVAL);
+    let mut last_heard = tokio::time::Instant::now();
+
+    loop {
+        tokio::select! {
+            incoming = conn.accept_uni() => {
+                let recv = match incoming {
+                    Ok(recv) => recv,
+                    Err(e) => return Ok(SessionEnd::Lost(e.to_string())),
+                };
+                last_heard = tokio::time::Instant::now();
+                match read_message(recv, wire::MAX_MESSAGE_SIZE).await {
+                    Ok((Message::Server(msg), bytes)) => {
+                        stats.received(bytes, Instant::now());
+                        match &msg {
+                            ServerMessage::PlayerID(pid) => *controlled = Some(*pid),
+                            ServerMessage::Died(_) => *controlled = None,
+                            ServerMessage::ActionRejected { .. } => stats.rejected_actions += 1,
+                            ServerMessage::Stats(server) => {
+                                // Reported to the app along with our own numbers
+                                server_stats = Some(*server);
+                                continue;
+                            }
+                            _ => {}
+                        }
+                        let end = ends_session(&msg);
+                        if !deliver(tx, ClientEvent::Server(msg)) {
+                            conn.close(0u32.into(), b"bye");
+                            return Ok(SessionEnd::Quit);
+                        }
+                        if let Some((end, reason)) = end {
+                            conn.close(0u32.into(), reason);
+                            return Ok(end);
+                        }
+                    }
+                    Ok((other, _)) => {
+                        log::warn!("Client received unexpected message: {other:?}");
+                    }
+                    Err(e) => {
+                        stats.dropped_messages += 1;
+                        log::warn!("Error receiving server message: {e}");
+                    }
+                }
+            }
+            action = rx.recv() => {
+                let Some(action) = action else {
+                    conn.close(0u32.into(), b"bye");
+                    return Ok(SessionEnd::Quit);
+                };
+                match send_one_way(&conn, &Message::Client(action)).await {
+                    Ok(bytes) => stats.sent(bytes, Instant::now()),
+                    Err(e) => return Ok(SessionEnd::Lost(e.to_string())),
+                }
+            }
+            _ = report.tick() => {
+                let report = net_stats(&conn, &mut stats, tx, rx, server_stats);
+                if !deliver(tx, ClientEvent::Stats(report)) {
+                    conn.close(0u32.into(), b"bye");
+                    return Ok(SessionEnd::Quit);
+                }
+            }
+            () = tokio::time::sleep_until(last_heard + SERVER_SILENCE_TIMEOUT) => {
+                // The server streams state every tick, so silence means it is gone
+                conn.close(0u32.into(), b"timed out");
+                return Ok(SessionEnd::Lost("The server stopped responding.".to_owned()));
+            }
+        }
+    }
+}
+
+/// The session's numbers for the F3 overlay, with the server's own latest
+/// report if one arrived.
+fn net_stats(
+    conn: &Connection,
+    stats: &mut ConnectionStats,
+    tx: &QueueSender<ClientEvent>,
+    rx: &QueueReceiver<GameAction>,
+    server: Option<ServerStats>,
+) -> NetStats {
+    let events = tx.metrics();
+    let actions = rx.metrics();
+    NetStats {
+        rtt: conn.rtt(),
+        traffic: stats.traffic(Instant::now()),
+        queue_depth: rx.len() as u32,
+        rejected_actions: stats.rejected_actions,
+        dropped_messages: stats.dropped_messages,
+        skipped_updates: events.superseded + events.dropped,
+        dropped_actions: actions.coalesced + actions.dropped,
+        server,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::endpoint;
+
+    #[test]
+    fn mock_transport_pair_round_trips() {
+        let (a, mut b) = mock_transport_pair();
+
+        a.send(Message::Client(GameAction::SaveWorld))
+            .expect("send should succeed");
+        let received = b.try_recv();
+        assert!(received.is_some());
+    }
+
+    #[test]
+    fn sending_to_a_dropped_transport_fails() {
+        let (a, b) = mock_transport_pair();
+        drop(b);
+        assert!(a.send(Message::Client(GameAction::SaveWorld)).is_err());
+    }
+
+    #[test]
+    fn game_converges_over_a_slow_lossy_link() {
+        const TICK: Duration = Duration::from_millis(50);
+        let (mut client, mut server_end) = mock_transport_pair_with(NetworkConditions {
+            latency: Duration::from_millis(200),
+            loss: 0.05,
+            seed: 1,
+            ..Default::default()
+        });
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        server.spawn_player_for(alice, "Alice".into());
+
+        let mut view = None;
+        let mut arrived = 0;
+        for tick in 0..100 {
+            if tick < 60 {
+                let dir = if tick % 2 == 0 {
+                    game::Direction::Right
+                } else {
+                    game::Direction::Left
+                };
+                client
+                    .send(Message::Client(GameAction::Move(dir)))
+                    .expect("send should succeed");
+            }
+            client.advance(TICK);
+
+            while let Some(msg) = server_end.try_recv() {
+                if let Message::Client(action) = msg {
+                    arrived += 1;
+                    server.handle_action(alice, action);
+                }
+            }
+            server.process_events();
+            server_end
+                .send(Message::Server(ServerMessage::EntityMap(Box::new(
+                    server.game.entities.clone(),
+                ))))
+                .expect("send should succeed");
+
+            while let Some(msg) = client.try_recv() {
+                if let Message::Server(ServerMessage::EntityMap(map)) = msg {
+                    view = Some(map);
+                }
+            }
+        }
+
+        // Some moves were lost, but the client still ends up seeing exactly
+        // the world the server has.
+        assert!((50..60).contains(&arrived), "{arrived} moves arrived");
+        assert_eq!(view.as_deref(), Some(&server.game.entities));
+    }
+
+    #[test]
+    fn protocol_message_encodes_and_decodes() {
+        let original = Message::Server(ServerMessage::PlayerID(EntityID(42)));
+        let bytes = bitcode::encode(&original);
+        let decoded: Message = bitcode::decode(&bytes).expect("decode should succeed");
+
+        match decoded {
+            Message::Server(ServerMessage::PlayerID(id)) => assert_eq!(id, EntityID(42)),
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn hello_round_trips() {
+        let original = Message::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: "Alice".into(),
+            spectator: false,
+        });
+        let bytes = bitcode::encode(&original);
+        let decoded: Message = bitcode::decode(&bytes).expect("decode should succeed");
+
+        match decoded {
+            Message::Hello(hello) => {
+                assert_eq!(hello.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(hello.client_name, "Alice");
+            }
+            other => panic!("unexpected variant: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_hello_accepts_matching_version() {
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: "Alice".into(),
+            spectator: false,
+        };
+        assert_eq!(check_hello(&hello), Ok(()));
+    }
+
+    #[test]
+    fn check_hello_rejects_other_versions() {
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION + 1,
+            client_name: "Alice".into(),
+            spectator: false,
+        };
+        assert_eq!(
+            check_hello(&hello),
+            Err(RejectReason::VersionMismatch {
+                server: PROTOCOL_VERSION,
+                client: PROTOCOL_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn spawned_characters_belong_to_their_creator() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+
+        let pid = server.spawn_player_for(alice, "Alice".into());
+
+        assert_eq!(server.endpoints.get(&alice), Some(&pid));
+        assert!(server.game.owns(&account_of(&alice), pid));
+    }
+
+    #[test]
+    fn spawn_as_rejects_other_accounts_characters() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let mallory = endpoint(2);
+        let pid = server.spawn_player_for(alice, "Alice".into());
+
+        assert_eq!(
+            server.spawn_as(mallory, pid),
+            Err(ActionError::NotOwner(pid))
+        );
+        assert!(!server.endpoints.contains_key(&mallory));
+    }
+
+    #[test]
+    fn spawn_as_reclaims_own_character_from_new_session() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let pid = server.spawn_player_for(alice, "Alice".into());
+        server.endpoints.clear();
+
+        // Same key, new connection: ownership was persisted in the game state.
+        assert_eq!(server.spawn_as(alice, pid), Ok(()));
+        assert_eq!(server.endpoints.get(&alice), Some(&pid));
+    }
+
+    #[test]
+    fn leave_cleans_up_and_puts_character_to_sleep() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let session = server.join(alice, "Alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+        assert!(!server.game.entities.is_dormant(pid));
+
+        server.leave(alice, session);
+
+        assert!(!server.clients.contains_key(&alice));
+        assert!(!server.endpoints.contains_key(&alice));
+        assert!(!server.unique_server_messages.contains_key(&alice));
+        assert!(server.game.entities.is_dormant(pid));
+    }
+
+    #[test]
+    fn stale_session_leave_keeps_newer_connection() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let old_session = server.join(alice, "Alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+
+        let new_session = server.join(alice, "Alice".into());
+        server
+            .spawn_as(alice, pid)
+            .expect("Alice owns the character");
+        server.leave(alice, old_session);
+
+        assert_eq!(server.clients[&alice].session, new_session);
+        assert_eq!(server.endpoints.get(&alice), Some(&pid));
+        assert!(!server.game.entities.is_dormant(pid));
+    }
+
+    #[test]
+    fn join_and_leave_are_announced_to_others() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let bob = endpoint(2);
+        server.join(alice, "Alice".into());
+        let bob_session = server.join(bob, "Bob".into());
+        server.leave(bob, bob_session);
+
+        let alice_inbox = &server.unique_server_messages[&alice];
+        assert!(
+            alice_inbox
+                .iter()
+                .any(|m| matches!(m, ServerMessage::PlayerJoined(name) if name == "Bob"))
+        );
+        assert!(
+            alice_inbox
+                .iter()
+                .any(|m| matches!(m, ServerMessage::PlayerLeft(name) if name == "Bob"))
+        );
+    }
+
+    #[test]
+    fn loaded_characters_start_dormant() {
+        let mut game = GameState::create_test_world("test".into());
+        let pid = game::spawn_player(&mut game, "Alice".into());
+
+        let server = ServerState::new(game);
+
+        assert!(server.game.entities.is_dormant(pid));
+    }
+
+    #[test]
+    fn reconnect_delay_backs_off_and_caps() {
+        assert!(reconnect_delay(1) < reconnect_delay(2));
+        assert_eq!(reconnect_delay(10), reconnect_delay(4));
+    }
+
+    #[test]
+    fn move_flood_is_limited_to_one_step_per_tick() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+        let start = server.game.entities.position()[&pid];
+
+        for _ in 0..10 {
+            server.handle_action(alice, GameAction::Move(game::Direction::Right));
+        }
+        server.process_events();
+
+        assert_eq!(server.game.entities.position()[&pid].x, start.x + 1);
+        assert!(
+            server.unique_server_messages[&alice]
+                .iter()
+                .any(|m| matches!(
+                    m,
+                    ServerMessage::ActionRejected {
+                        reason: ActionError::RateLimited,
+                        ..
+                    }
+                ))
+        );
+    }
+
+    #[test]
+    fn only_the_host_may_save() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let host = endpoint(1);
+        let guest = endpoint(2);
+        server.host = Some(host);
+        server.join(host, "Host".into());
+        server.join(guest, "Guest".into());
+        server.spawn_player_for(host, "Host".into());
+        server.spawn_player_for(guest, "Guest".into());
+
+        server.handle_action(guest, GameAction::SaveWorld);
+        assert!(server.event_queue.is_empty());
+        assert!(
+            server.unique_server_messages[&guest]
+                .iter()
+                .any(|m| matches!(
+                    m,
+                    ServerMessage::ActionRejected {
+                        reason: ActionError::NotPermitted,
+                        ..
+                    }
+                ))
+        );
+
+        server.handle_action(host, GameAction::SaveWorld);
+        assert_eq!(server.event_queue.len(), 1);
+    }
+
+    #[test]
+    fn actions_without_a_character_are_rejected() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+
+        server.handle_action(alice, GameAction::Move(game::Direction::Up));
+
+        assert!(server.event_queue.is_empty());
+        assert!(
+            server.unique_server_messages[&alice]
+                .iter()
+                .any(|m| matches!(
+                    m,
+                    ServerMessage::ActionRejected {
+                        reason: ActionError::NoCharacter,
+                        ..
+                    }
+                ))
+        );
+    }
+
+    fn chat_lines(server: &ServerState, endpoint_id: EndpointId) -> Vec<ChatLine> {
+        server
+            .unique_server_messages
+            .get(&endpoint_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|m| match m {
+                ServerMessage::Chat(line) => Some(line.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn local_chat_reaches_only_nearby_players() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, bob, carol) = (endpoint(1), endpoint(2), endpoint(3));
+        for (endpoint_id, name) in [(alice, "Alice"), (bob, "Bob"), (carol, "Carol")] {
+            server.join(endpoint_id, name.into());
+            server.spawn_player_for(endpoint_id, name.into());
+        }
+        let carol_pid = server.endpoints[&carol];
+        let near = server.game.entities.position()[&carol_pid];
+        let far = game::offset(near, (game::HEARING_RADIUS + 1, 0));
+        server.game.entities.set_position(carol_pid, Some(far));
+
+        server.handle_action(alice, GameAction::Say("hello".into()));
+        server.process_events();
+
+        assert_eq!(chat_lines(&server, alice).len(), 1);
+        assert_eq!(
+            chat_lines(&server, bob).first().map(|l| l.text.as_str()),
+            Some("hello")
+        );
+        assert!(chat_lines(&server, carol).is_empty());
+    }
+
+    #[test]
+    fn chat_is_recorded_in_the_journal() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        let pid = server.spawn_player_for(alice, "Alice".into());
+
+        server.handle_action(alice, GameAction::SayGlobal("hi all".into()));
+        server.process_events();
+
+        assert_eq!(
+            server.journal,
+            vec![JournalEntry {
+                tick: 0,
+                actor: pid,
+                action: GameAction::SayGlobal("hi all".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn the_journal_forgets_its_oldest_entries() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        for tick in 0..=JOURNAL_CAPACITY as u64 {
+            server.record(JournalEntry {
+                tick,
+                actor: EntityID(1),
+                action: GameAction::Organize,
+            });
+        }
+
+        assert_eq!(server.journal.len(), JOURNAL_CAPACITY);
+        assert_eq!(server.journal.front().map(|e| e.tick), Some(1));
+    }
+
+    #[test]
+    fn spectators_join_quietly_and_cannot_act() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, eve) = (endpoint(1), endpoint(2));
+        server.join(alice, "Alice".into());
+        server.spectate(eve, "Eve".into());
+
+        server.handle_action(eve, GameAction::SpawnPlayer("Eve".into()));
+
+        assert_eq!(server.player_count(), 1);
+        assert!(!server.endpoints.contains_key(&eve));
+        assert!(
+            !server.unique_server_messages[&alice]
+                .iter()
+                .any(|m| matches!(m, ServerMessage::PlayerJoined(_)))
+        );
+        assert!(server.unique_server_messages[&eve].iter().any(|m| matches!(
+            m,
+            ServerMessage::ActionRejected {
+                reason: ActionError::Spectating,
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn spectators_hear_only_global_chat() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, eve) = (endpoint(1), endpoint(2));
+        server.join(alice, "Alice".into());
+        server.spawn_player_for(alice, "Alice".into());
+        server.spectate(eve, "Eve".into());
+
+        server.handle_action(alice, GameAction::Say("psst".into()));
+        server.handle_action(alice, GameAction::SayGlobal("hello all".into()));
+        server.process_events();
+
+        let texts: Vec<String> = chat_lines(&server, eve)
+            .into_iter()
+            .map(|l| l.text)
+            .collect();
+        assert_eq!(texts, vec!["hello all".to_owned()]);
+    }
+
+    #[test]
+    fn rejections_are_counted_in_server_stats() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+
+        server.handle_action(alice, GameAction::Move(game::Direction::Up));
+        server.process_events();
+
+        let stats = server.server_stats(alice);
+        assert_eq!(stats.rejected_actions, 1);
+        assert_eq!(stats.tick, 1);
+        assert!(stats.queue_depth > 0);
+    }
+
+    #[test]
+    fn moves_are_dropped_while_paused() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let pid = game::spawn_player(&mut server.game, "Alice".into());
+        let start = server.game.entities.position()[&pid];
+        server.paused = true;
+
+        server
+            .event_queue
+            .push((pid, GameAction::Move(game::Direction::Right)));
+        server.process_events();
+
+        assert_eq!(server.game.entities.position()[&pid], start);
+        assert!(server.event_queue.is_empty());
+    }
+
+    #[test]
+    fn heavy_loads_slow_characters_down() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let pid = game::spawn_player(&mut server.game, "Alice".into());
+        let start = server.game.entities.position()[&pid];
+        let limit = game::carry::carry_limit(&server.game.entities, pid);
+        let load = game::spawn(
+            &mut server.game,
+            game::Blueprint {
+                item: Some(game::component::Item {
+                    weight: limit,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+            .at(start),
+        );
+        game::item::pick_up(&mut server.game, pid, load).expect("within reach");
+        assert_eq!(
+            game::carry::encumbrance(&server.game.entities, pid),
+            game::carry::Encumbrance::Burdened
+        );
+
+        for _ in 0..4 {
+            server
+                .event_queue
+                .push((pid, GameAction::Move(game::Direction::Down)));
+            server.process_events();
+        }
+        assert_eq!(server.game.entities.position()[&pid].y, start.y + 2);
+    }
+
+    #[test]
+    fn refused_item_actions_are_explained_to_the_player() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        server.spawn_player_for(alice, "Alice".into());
+        server.unique_server_messages.clear();
+
+        let tree = EntityID(1);
+        assert_eq!(server.game.entities.name_of(tree), Some("Tree"));
+        server.handle_action(alice, GameAction::PickUp(tree));
+        server.process_events();
+
+        let expected = game::ItemError::NotAnItem.to_string();
+        assert!(
+            server.unique_server_messages[&alice]
+                .iter()
+                .any(|m| matches!(m, ServerMessage::Notice(text) if *text == expected))
+        );
+    }
+
+    #[test]
+    fn server_state_process_events_applies_moves() {
+        let game = GameState::create_test_world("test".into());
+        let mut server = ServerState::new(game);
+
+        let pid = game::spawn_player(&mut server.game, "Alice".into());
+        let start = server.game.entities.position()[&pid];
+
+        server
+            .event_queue
+            .push((pid, GameAction::Move(game::Direction::Right)));
+        server.process_events();
+
+        assert_eq!(
+            server.game.entities.position()[&pid],
+            game::Point {
+                x: start.x + 1,
+                y: start.y
+            }
+        );
+    }
+}
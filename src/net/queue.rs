@@ -0,0 +1,472 @@
+//! Bounded message queues with an explicit overflow policy.
+//!
+//! Every queue between the UI, the client task, the tick task and the
+//! connection writers holds at most a fixed number of messages. Messages
+//! that make older ones pointless (a newer world snapshot, say) replace
+//! them as they are queued; see [`Coalesce`]. What happens when a queue is
+//! still full is up to its [`OverflowPolicy`], and [`QueueMetrics`] count
+//! every time one of these kicks in. One-off control messages, such as being
+//! told which character to play, are never dropped to make room.
+
+use super::{ClientEvent, Message, ServerMessage};
+use crate::game::GameAction;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use tokio::sync::Notify;
+
+/// What to do with a new message when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Make room by dropping the oldest message that may be dropped.
+    DropOldest,
+    /// Drop the new message, unless it may not be dropped.
+    DropNewest,
+    /// Close the queue; the receiver gets what is left, then `None`.
+    Disconnect,
+}
+
+/// How a message relates to older ones still waiting in the same queue.
+pub trait Coalesce {
+    /// Whether `self` makes `older` pointless to deliver. Superseded
+    /// messages are dropped as soon as `self` is queued.
+    fn supersedes(&self, _older: &Self) -> bool {
+        false
+    }
+
+    /// Whether `self` may take the place of `older` when the queue is full.
+    fn replaces(&self, older: &Self) -> bool {
+        self.supersedes(older)
+    }
+
+    /// Whether a full queue may drop `self` to make room. Messages the
+    /// receiver cannot do without are queued past capacity instead; they
+    /// are rare enough not to pile up.
+    fn droppable(&self) -> bool {
+        true
+    }
+}
+
+/// How often a queue had to step in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueMetrics {
+    /// Messages dropped because a newer one superseded them.
+    pub superseded: u64,
+    /// Messages a newer one replaced because the queue was full.
+    pub coalesced: u64,
+    /// Messages dropped because the queue was full.
+    pub dropped: u64,
+    /// The queue overflowed with [`OverflowPolicy::Disconnect`].
+    pub overflowed: bool,
+}
+
+/// Why a message could not be queued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The receiver is gone, or the queue was closed on overflow.
+    Closed,
+    /// The queue is full and its policy drops new messages.
+    Full,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => write!(f, "the queue is closed"),
+            Self::Full => write!(f, "the queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[derive(Debug)]
+struct State<T> {
+    items: VecDeque<T>,
+    metrics: QueueMetrics,
+    closed: bool,
+    senders: usize,
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    notify: Notify,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Both halves of a queue.
+pub type Channel<T> = (QueueSender<T>, QueueReceiver<T>);
+
+/// Create a queue holding at most `capacity` messages.
+pub fn channel<T: Coalesce>(capacity: usize, policy: OverflowPolicy) -> Channel<T> {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::with_capacity(capacity),
+            metrics: QueueMetrics::default(),
+            closed: false,
+            senders: 1,
+        }),
+        notify: Notify::new(),
+        capacity: capacity.max(1),
+        policy,
+    });
+    (
+        QueueSender {
+            shared: shared.clone(),
+        },
+        QueueReceiver { shared },
+    )
+}
+
+/// The sending half of a queue; clone it to send from several places.
+#[derive(Debug)]
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            state.closed = true;
+            drop(state);
+            // The receiver may be waiting to find out
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+impl<T: Coalesce> QueueSender<T> {
+    /// Queue `item` without waiting.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`SendError::Closed`] if nobody will receive it, or
+    /// [`SendError::Full`] if the queue is full and drops new messages.
+    pub fn send(&self, item: T) -> Result<(), SendError> {
+        let shared = &self.shared;
+        let mut state = shared.lock();
+        if state.closed {
+            return Err(SendError::Closed);
+        }
+
+        let before = state.items.len();
+        state.items.retain(|older| !item.supersedes(older));
+        state.metrics.superseded += (before - state.items.len()) as u64;
+
+        if state.items.len() >= shared.capacity {
+            if let Some(slot) = state.items.iter_mut().find(|older| item.replaces(older)) {
+                *slot = item;
+                state.metrics.coalesced += 1;
+                return Ok(());
+            }
+            match shared.policy {
+                OverflowPolicy::DropOldest => {
+                    if let Some(oldest) = state.items.iter().position(Coalesce::droppable) {
+                        state.items.remove(oldest);
+                        state.metrics.dropped += 1;
+                    } else if item.droppable() {
+                        state.metrics.dropped += 1;
+                        return Ok(());
+                    }
+                }
+                OverflowPolicy::DropNewest => {
+                    if item.droppable() {
+                        state.metrics.dropped += 1;
+                        return Err(SendError::Full);
+                    }
+                }
+                OverflowPolicy::Disconnect => {
+                    state.closed = true;
+                    state.metrics.overflowed = true;
+                    drop(state);
+                    shared.notify.notify_one();
+                    return Err(SendError::Closed);
+                }
+            }
+        }
+
+        state.items.push_back(item);
+        drop(state);
+        shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> QueueSender<T> {
+    /// Whether messages can no longer be delivered.
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.lock().metrics
+    }
+}
+
+/// The receiving half of a queue.
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.items.clear();
+    }
+}
+
+impl<T> QueueReceiver<T> {
+    /// Take the oldest message, if one is waiting.
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.shared.lock().items.pop_front()
+    }
+
+    /// Wait for the next message. Returns `None` once the queue is empty and
+    /// closed, or every sender is gone.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            {
+                let mut state = self.shared.lock();
+                if let Some(item) = state.items.pop_front() {
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.lock().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        self.shared.lock().metrics
+    }
+}
+
+// ---------------------------------------------------------------------------
+// What supersedes what
+// ---------------------------------------------------------------------------
+
+impl Coalesce for ServerMessage {
+    /// Full snapshots and statistics are useless once newer ones exist.
+    fn supersedes(&self, older: &Self) -> bool {
+        matches!(
+            (self, older),
+            (Self::EntityMap(_), Self::EntityMap(_)) | (Self::Stats(_), Self::Stats(_))
+        )
+    }
+
+    /// A client that misses being told who it plays, that it died or must go
+    /// elsewhere is stuck; anything else is news that may be lost.
+    fn droppable(&self) -> bool {
+        !matches!(
+            self,
+            Self::PlayerID(_)
+                | Self::Welcome(_)
+                | Self::Rejected(_)
+                | Self::OwnedCharacters(_)
+                | Self::Kicked(_)
+                | Self::Handoff(_)
+                | Self::Redirect(_)
+                | Self::Died(_)
+        )
+    }
+}
+
+impl Coalesce for Message {
+    fn supersedes(&self, older: &Self) -> bool {
+        match (self, older) {
+            (Self::Server(new), Self::Server(old)) => new.supersedes(old),
+            _ => false,
+        }
+    }
+}
+
+impl Coalesce for ClientEvent {
+    fn supersedes(&self, older: &Self) -> bool {
+        match (self, older) {
+            (Self::Server(new), Self::Server(old)) => new.supersedes(old),
+            (Self::Stats(_), Self::Stats(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn droppable(&self) -> bool {
+        match self {
+            Self::Server(message) => message.droppable(),
+            Self::Stats(_) => true,
+            Self::Reconnecting { .. } | Self::Disconnected(_) => false,
+        }
+    }
+}
+
+impl Coalesce for GameAction {
+    /// Every step counts, but once the queue is full only the latest
+    /// direction the player asked for is kept.
+    fn replaces(&self, older: &Self) -> bool {
+        let is_step = |action: &Self| matches!(action, Self::Move(_) | Self::MoveDiagonal(_));
+        is_step(self) && is_step(older)
+    }
+}
+
+impl<T> Coalesce for Vec<T> {
+    /// Lists such as the LAN servers are always sent whole.
+    fn supersedes(&self, _older: &Self) -> bool {
+        true
+    }
+}
+
+impl Coalesce for String {}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Direction, EntityID};
+
+    fn snapshot() -> ServerMessage {
+        ServerMessage::EntityMap(Box::default())
+    }
+
+    #[test]
+    fn newer_snapshots_supersede_older_ones() {
+        let (tx, mut rx) = channel(8, OverflowPolicy::Disconnect);
+        tx.send(snapshot()).expect("queue has room");
+        tx.send(ServerMessage::PlayerID(EntityID(1)))
+            .expect("queue has room");
+        tx.send(snapshot()).expect("queue has room");
+
+        assert!(matches!(rx.try_recv(), Some(ServerMessage::PlayerID(_))));
+        assert!(matches!(rx.try_recv(), Some(ServerMessage::EntityMap(_))));
+        assert!(rx.try_recv().is_none());
+        assert_eq!(rx.metrics().superseded, 1);
+    }
+
+    #[test]
+    fn full_queue_coalesces_moves() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropNewest);
+        tx.send(GameAction::Move(Direction::Up))
+            .expect("queue has room");
+        tx.send(GameAction::SaveWorld).expect("queue has room");
+        tx.send(GameAction::Move(Direction::Left))
+            .expect("replaces the queued move");
+        assert_eq!(tx.send(GameAction::SaveWorld), Err(SendError::Full));
+
+        assert_eq!(rx.try_recv(), Some(GameAction::Move(Direction::Left)));
+        assert_eq!(rx.try_recv(), Some(GameAction::SaveWorld));
+        let metrics = rx.metrics();
+        assert_eq!((metrics.coalesced, metrics.dropped), (1, 1));
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_messages() {
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropOldest);
+        for n in 0..4 {
+            tx.send(format!("line {n}")).expect("never refuses");
+        }
+        assert_eq!(rx.try_recv().as_deref(), Some("line 2"));
+        assert_eq!(rx.try_recv().as_deref(), Some("line 3"));
+        assert_eq!(rx.metrics().dropped, 2);
+    }
+
+    #[test]
+    fn control_events_survive_a_full_queue() {
+        let chat = |n: u32| ClientEvent::Server(ServerMessage::Notice(format!("line {n}")));
+        let (tx, mut rx) = channel(2, OverflowPolicy::DropOldest);
+        tx.send(ClientEvent::Server(ServerMessage::PlayerID(EntityID(1))))
+            .expect("queue has room");
+        for n in 0..4 {
+            tx.send(chat(n)).expect("never refuses");
+        }
+        tx.send(ClientEvent::Server(ServerMessage::Redirect(
+            "gamik:".into(),
+        )))
+        .expect("never refuses");
+        tx.send(ClientEvent::Disconnected("gone".into()))
+            .expect("never refuses");
+
+        assert!(matches!(
+            rx.try_recv(),
+            Some(ClientEvent::Server(ServerMessage::PlayerID(_)))
+        ));
+        assert!(matches!(
+            rx.try_recv(),
+            Some(ClientEvent::Server(ServerMessage::Redirect(_)))
+        ));
+        assert!(matches!(rx.try_recv(), Some(ClientEvent::Disconnected(_))));
+        assert!(rx.try_recv().is_none());
+        assert_eq!(rx.metrics().dropped, 4);
+    }
+
+    #[tokio::test]
+    async fn overflow_can_disconnect() {
+        let (tx, mut rx) = channel(1, OverflowPolicy::Disconnect);
+        tx.send("first".to_owned()).expect("queue has room");
+        assert_eq!(tx.send("second".to_owned()), Err(SendError::Closed));
+        assert!(tx.is_closed());
+
+        assert_eq!(rx.recv().await.as_deref(), Some("first"));
+        assert_eq!(rx.recv().await, None);
+        assert!(rx.metrics().overflowed);
+    }
+
+    #[tokio::test]
+    async fn recv_ends_when_senders_are_gone() {
+        let (tx, mut rx) = channel::<String>(4, OverflowPolicy::DropOldest);
+        let waiter = tokio::spawn(async move { rx.recv().await });
+        tokio::task::yield_now().await;
+        drop(tx);
+        assert_eq!(waiter.await.expect("task runs"), None);
+    }
+
+    #[test]
+    fn sending_to_a_dropped_receiver_fails() {
+        let (tx, rx) = channel::<String>(4, OverflowPolicy::DropOldest);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send("lost".to_owned()), Err(SendError::Closed));
+    }
+}
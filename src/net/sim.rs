@@ -0,0 +1,333 @@
+//! Simulated network conditions for the in-memory transport.
+//!
+//! A [`Link`] carries messages between the two ends of a
+//! [`mock_transport_pair_with`](super::mock_transport_pair_with), delaying,
+//! dropping and reordering them according to [`NetworkConditions`]. Time on a
+//! link only moves when a test calls [`MockTransport::advance`](super::MockTransport::advance),
+//! and all randomness comes from a seeded generator, so a test sees exactly
+//! the same network every time it runs.
+
+use super::Message;
+
+use rand::{Rng as _, SeedableRng as _, rngs::StdRng};
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+/// How badly a simulated link behaves. The default is a perfect link:
+/// every message arrives, instantly and in order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NetworkConditions {
+    /// One-way delay added to every message.
+    pub latency: Duration,
+    /// Up to this much extra delay, chosen at random per message.
+    pub jitter: Duration,
+    /// Chance between 0 and 1 that a message is lost.
+    pub loss: f64,
+    /// Chance between 0 and 1 that a message is held back long enough for
+    /// the ones sent after it to overtake it.
+    pub reorder: f64,
+    /// Bytes per second the link can carry; messages queue behind each
+    /// other once it is saturated. `None` means unlimited.
+    pub bandwidth: Option<u64>,
+    /// Seed for the loss, jitter and reordering decisions.
+    pub seed: u64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            reorder: 0.0,
+            bandwidth: None,
+            seed: 0,
+        }
+    }
+}
+
+/// Which end of a [`Link`] a transport is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+/// The other end of the link has been dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkClosed;
+
+impl fmt::Display for LinkClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the other end of the link is closed")
+    }
+}
+
+impl std::error::Error for LinkClosed {}
+
+/// Messages travelling in one direction.
+#[derive(Debug, Default)]
+struct Lane {
+    /// In the order they arrive.
+    in_flight: BTreeMap<Arrival, Message>,
+    /// When the link finishes putting the last message on the wire.
+    busy_until: Duration,
+    /// Delivery time of the last message that was not reordered.
+    last_delivery: Duration,
+}
+
+/// When a message in flight arrives. Messages due at the same time arrive
+/// in the order they were sent; `seq` is unique on a link, so no two
+/// messages ever share an arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Arrival {
+    deliver_at: Duration,
+    seq: u64,
+}
+
+/// Both directions of a simulated connection, with the clock they share.
+#[derive(Debug)]
+pub struct Link {
+    conditions: NetworkConditions,
+    rng: StdRng,
+    now: Duration,
+    sent: u64,
+    to_a: Lane,
+    to_b: Lane,
+}
+
+impl Link {
+    pub fn new(conditions: NetworkConditions) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(conditions.seed),
+            conditions,
+            now: Duration::ZERO,
+            sent: 0,
+            to_a: Lane::default(),
+            to_b: Lane::default(),
+        }
+    }
+
+    /// Time since the link was created.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.now += by;
+    }
+
+    /// Put `msg` on the wire from `from` to the other side.
+    pub fn send(&mut self, from: Side, msg: Message) {
+        let c = self.conditions;
+        if c.loss > 0.0 && self.rng.random_bool(c.loss.min(1.0)) {
+            return;
+        }
+
+        let transmit = c.bandwidth.map_or(Duration::ZERO, |bytes_per_sec| {
+            let bytes = super::wire::encode(&msg).len() as f64;
+            Duration::from_secs_f64(bytes / bytes_per_sec.max(1) as f64)
+        });
+        let jitter = if c.jitter.is_zero() {
+            Duration::ZERO
+        } else {
+            self.rng.random_range(Duration::ZERO..=c.jitter)
+        };
+        let reordered = c.reorder > 0.0 && self.rng.random_bool(c.reorder.min(1.0));
+
+        let now = self.now;
+        let lane = self.lane_mut(from);
+        lane.busy_until = lane.busy_until.max(now) + transmit;
+        let mut deliver_at = lane.busy_until + c.latency + jitter;
+        if reordered {
+            // Held back by another full trip so later messages overtake it
+            deliver_at += c.latency.max(Duration::from_millis(1)) + c.jitter;
+        } else {
+            // Jitter alone never reorders, like a single TCP-ish stream
+            deliver_at = deliver_at.max(lane.last_delivery);
+            lane.last_delivery = deliver_at;
+        }
+
+        let seq = self.sent;
+        self.sent += 1;
+        self.lane_mut(from)
+            .in_flight
+            .insert(Arrival { deliver_at, seq }, msg);
+    }
+
+    /// The next message that has reached `to` by now, if any.
+    pub fn recv(&mut self, to: Side) -> Option<Message> {
+        let now = self.now;
+        let lane = match to {
+            Side::A => &mut self.to_a,
+            Side::B => &mut self.to_b,
+        };
+        let (next, _) = lane.in_flight.first_key_value()?;
+        if next.deliver_at > now {
+            return None;
+        }
+        lane.in_flight.pop_first().map(|(_, msg)| msg)
+    }
+
+    /// Messages sent towards `to` that have not been received yet.
+    pub fn in_flight(&self, to: Side) -> usize {
+        match to {
+            Side::A => self.to_a.in_flight.len(),
+            Side::B => self.to_b.in_flight.len(),
+        }
+    }
+
+    fn lane_mut(&mut self, from: Side) -> &mut Lane {
+        match from {
+            Side::A => &mut self.to_b,
+            Side::B => &mut self.to_a,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{EntityID, GameAction};
+    use crate::net::ServerMessage;
+
+    fn numbered(n: u32) -> Message {
+        Message::Server(ServerMessage::PlayerID(EntityID(n)))
+    }
+
+    fn number(msg: &Message) -> u32 {
+        match msg {
+            Message::Server(ServerMessage::PlayerID(EntityID(n))) => *n,
+            other => panic!("unexpected message: {other:?}"),
+        }
+    }
+
+    fn drain(link: &mut Link, to: Side) -> Vec<u32> {
+        std::iter::from_fn(|| link.recv(to))
+            .map(|m| number(&m))
+            .collect()
+    }
+
+    #[test]
+    fn perfect_link_delivers_immediately_in_order() {
+        let mut link = Link::new(NetworkConditions::default());
+        for n in 0..5 {
+            link.send(Side::A, numbered(n));
+        }
+        assert_eq!(drain(&mut link, Side::A), Vec::<u32>::new());
+        assert_eq!(drain(&mut link, Side::B), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn latency_holds_messages_back() {
+        let mut link = Link::new(NetworkConditions {
+            latency: Duration::from_millis(200),
+            ..Default::default()
+        });
+        link.send(Side::B, Message::Client(GameAction::SaveWorld));
+
+        link.advance(Duration::from_millis(199));
+        assert!(link.recv(Side::A).is_none());
+        link.advance(Duration::from_millis(1));
+        assert!(link.recv(Side::A).is_some());
+    }
+
+    #[test]
+    fn loss_drops_roughly_the_configured_share() {
+        let mut link = Link::new(NetworkConditions {
+            loss: 0.05,
+            seed: 7,
+            ..Default::default()
+        });
+        for n in 0..1000 {
+            link.send(Side::A, numbered(n));
+        }
+        let received = drain(&mut link, Side::B);
+        assert!((920..=980).contains(&received.len()), "{}", received.len());
+        assert!(received.is_sorted());
+    }
+
+    #[test]
+    fn jitter_without_reordering_keeps_order() {
+        let mut link = Link::new(NetworkConditions {
+            latency: Duration::from_millis(50),
+            jitter: Duration::from_millis(40),
+            seed: 3,
+            ..Default::default()
+        });
+        for n in 0..50 {
+            link.send(Side::A, numbered(n));
+            link.advance(Duration::from_millis(5));
+        }
+        link.advance(Duration::from_secs(1));
+        assert_eq!(drain(&mut link, Side::B), (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reordering_lets_later_messages_overtake() {
+        let mut link = Link::new(NetworkConditions {
+            latency: Duration::from_millis(20),
+            reorder: 0.3,
+            seed: 11,
+            ..Default::default()
+        });
+        for n in 0..50 {
+            link.send(Side::A, numbered(n));
+            link.advance(Duration::from_millis(5));
+        }
+        link.advance(Duration::from_secs(1));
+        let received = drain(&mut link, Side::B);
+        assert_eq!(received.len(), 50);
+        assert!(!received.is_sorted());
+    }
+
+    #[test]
+    fn bandwidth_cap_queues_messages() {
+        let msg = numbered(1);
+        let size = crate::net::wire::encode(&msg).len() as u64;
+        // Exactly one message per 100 ms
+        let mut link = Link::new(NetworkConditions {
+            bandwidth: Some(size * 10),
+            ..Default::default()
+        });
+        for n in 0..3 {
+            link.send(Side::A, numbered(n));
+        }
+
+        link.advance(Duration::from_millis(100));
+        assert_eq!(drain(&mut link, Side::B), vec![0]);
+        link.advance(Duration::from_millis(100));
+        assert_eq!(drain(&mut link, Side::B), vec![1]);
+        assert_eq!(link.in_flight(Side::B), 1);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_network() {
+        let conditions = NetworkConditions {
+            latency: Duration::from_millis(30),
+            jitter: Duration::from_millis(30),
+            loss: 0.2,
+            reorder: 0.2,
+            seed: 42,
+            ..Default::default()
+        };
+        let run = || {
+            let mut link = Link::new(conditions);
+            let mut arrivals = Vec::new();
+            for n in 0..100 {
+                link.send(Side::A, numbered(n));
+                link.advance(Duration::from_millis(7));
+                arrivals.extend(
+                    drain(&mut link, Side::B)
+                        .into_iter()
+                        .map(|m| (m, link.now())),
+                );
+            }
+            arrivals
+        };
+        assert_eq!(run(), run());
+    }
+}
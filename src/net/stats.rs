@@ -0,0 +1,209 @@
+//! Network statistics.
+//!
+//! Both ends of a connection count the traffic they see in a
+//! [`ConnectionStats`]. Once a second the server sends each client a
+//! [`ServerStats`] describing its side; the client merges that with its own
+//! measurements into a [`NetStats`] for the debug overlay.
+
+use bitcode::{Decode, Encode};
+use std::time::{Duration, Instant};
+
+/// How often statistics are summarised and reported.
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts events and reports how many happened during the last full
+/// [`STATS_INTERVAL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateCounter {
+    pub total: u64,
+    window_start: Instant,
+    in_window: u64,
+    last_rate: u64,
+}
+
+impl RateCounter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            total: 0,
+            window_start: now,
+            in_window: 0,
+            last_rate: 0,
+        }
+    }
+
+    /// Count `amount` more events happening at `now`.
+    pub fn add(&mut self, amount: u64, now: Instant) {
+        self.roll(now);
+        self.total += amount;
+        self.in_window += amount;
+    }
+
+    /// Events per second over the last complete window.
+    pub fn per_second(&mut self, now: Instant) -> u64 {
+        self.roll(now);
+        self.last_rate
+    }
+
+    /// Start a new window if the current one is over. A window with no
+    /// events after it at all reports a rate of zero.
+    fn roll(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < STATS_INTERVAL {
+            return;
+        }
+        self.last_rate = if elapsed < STATS_INTERVAL * 2 {
+            self.in_window
+        } else {
+            0
+        };
+        self.in_window = 0;
+        self.window_start = now;
+    }
+}
+
+/// Traffic counters for one connection, as seen from one end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_sent: RateCounter,
+    pub bytes_received: RateCounter,
+    pub messages_sent: RateCounter,
+    pub messages_received: RateCounter,
+    /// Actions the other end refused.
+    pub rejected_actions: u64,
+    /// Messages that could not be delivered at all.
+    pub dropped_messages: u64,
+    /// Snapshots replaced by a newer one before they were sent.
+    pub skipped_updates: u64,
+}
+
+impl ConnectionStats {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            bytes_sent: RateCounter::new(now),
+            bytes_received: RateCounter::new(now),
+            messages_sent: RateCounter::new(now),
+            messages_received: RateCounter::new(now),
+            rejected_actions: 0,
+            dropped_messages: 0,
+            skipped_updates: 0,
+        }
+    }
+
+    /// Record a message of `bytes` bytes sent at `now`.
+    pub fn sent(&mut self, bytes: usize, now: Instant) {
+        self.bytes_sent.add(bytes as u64, now);
+        self.messages_sent.add(1, now);
+    }
+
+    /// Record a message of `bytes` bytes received at `now`.
+    pub fn received(&mut self, bytes: usize, now: Instant) {
+        self.bytes_received.add(bytes as u64, now);
+        self.messages_received.add(1, now);
+    }
+
+    /// Rates over the last complete window.
+    pub fn traffic(&mut self, now: Instant) -> Traffic {
+        Traffic {
+            bytes_sent_per_sec: self.bytes_sent.per_second(now),
+            bytes_received_per_sec: self.bytes_received.per_second(now),
+            messages_sent_per_sec: self.messages_sent.per_second(now),
+            messages_received_per_sec: self.messages_received.per_second(now),
+        }
+    }
+}
+
+/// Per-second traffic in both directions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct Traffic {
+    pub bytes_sent_per_sec: u64,
+    pub bytes_received_per_sec: u64,
+    pub messages_sent_per_sec: u64,
+    pub messages_received_per_sec: u64,
+}
+
+/// The server's view of one client's connection, sent to that client.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct ServerStats {
+    pub tick: u64,
+    /// How long the last [`ServerState::process_events`](super::ServerState::process_events) took.
+    pub tick_duration_micros: u64,
+    pub traffic: Traffic,
+    /// Messages waiting to be sent to this client.
+    pub queue_depth: u32,
+    /// Actions from this client the server refused.
+    pub rejected_actions: u64,
+    /// Actions from this client that arrived unreadable.
+    pub dropped_actions: u64,
+    /// Updates for this client replaced by newer ones before it could be
+    /// sent them, because it is falling behind.
+    pub skipped_updates: u64,
+}
+
+/// Everything the client knows about its connection, for display.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetStats {
+    pub rtt: Duration,
+    pub traffic: Traffic,
+    /// Actions waiting to be sent to the server.
+    pub queue_depth: u32,
+    /// Actions the server refused.
+    pub rejected_actions: u64,
+    /// Messages from the server that arrived unreadable.
+    pub dropped_messages: u64,
+    /// Server messages the UI never saw because newer ones replaced them
+    /// or its queue was full.
+    pub skipped_updates: u64,
+    /// Actions merged into a later one or dropped because the queue to the
+    /// server was full.
+    pub dropped_actions: u64,
+    /// The latest report from the server, if one has arrived.
+    pub server: Option<ServerStats>,
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_reports_the_previous_window() {
+        let start = Instant::now();
+        let mut counter = RateCounter::new(start);
+
+        counter.add(300, start);
+        counter.add(200, start + Duration::from_millis(500));
+        assert_eq!(counter.per_second(start + Duration::from_millis(900)), 0);
+
+        counter.add(7, start + Duration::from_millis(1100));
+        assert_eq!(counter.per_second(start + Duration::from_millis(1200)), 500);
+        assert_eq!(counter.total, 507);
+    }
+
+    #[test]
+    fn rate_drops_to_zero_after_silence() {
+        let start = Instant::now();
+        let mut counter = RateCounter::new(start);
+
+        counter.add(100, start);
+        assert_eq!(counter.per_second(start + Duration::from_secs(5)), 0);
+    }
+
+    #[test]
+    fn connection_stats_count_bytes_and_messages() {
+        let start = Instant::now();
+        let mut stats = ConnectionStats::new(start);
+
+        stats.sent(40, start);
+        stats.sent(60, start);
+        stats.received(10, start);
+
+        let traffic = stats.traffic(start + STATS_INTERVAL);
+        assert_eq!(traffic.bytes_sent_per_sec, 100);
+        assert_eq!(traffic.messages_sent_per_sec, 2);
+        assert_eq!(traffic.bytes_received_per_sec, 10);
+        assert_eq!(traffic.messages_received_per_sec, 1);
+    }
+}
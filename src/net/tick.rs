@@ -0,0 +1,679 @@
+//! The server's authoritative tick.
+//!
+//! A single task owns the [`ServerState`]. Connections never touch it
+//! directly: they send [`ServerRequest`]s through a [`ServerHandle`], and the
+//! task answers them between ticks. Every [`TICK_INTERVAL`] it handles the
+//! actions that arrived since the last tick in a fixed order, steps the
+//! game once, and hands each client its updates through that client's
+//! [`Outbox`].
+//!
+//! Requests travel over a bounded channel, so a connection flooding the
+//! server waits for room rather than growing its queue without limit.
+
+use super::{
+    ActionError, ClientInfo, Hello, Message, PROTOCOL_VERSION, RejectReason, ServerMessage,
+    ServerState, Welcome, account_of,
+    command::CommandError,
+    queue::{self, OverflowPolicy, QueueSender},
+    stats::STATS_INTERVAL,
+};
+use crate::game::{GameAction, item};
+
+use iroh::EndpointId;
+use rustc_hash::FxHashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// How often the server steps the game and updates its clients.
+pub const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Requests waiting for the tick task before connections have to wait.
+const REQUEST_QUEUE_CAPACITY: usize = 1024;
+/// Messages waiting for a client's writer. Snapshots replace each other, so
+/// only a client that cannot keep up with everything else fills this.
+pub const OUTBOX_CAPACITY: usize = 256;
+
+/// Messages for one client, in order.
+pub type Outbox = QueueSender<Message>;
+
+/// Create an outbox that disconnects its client once it overflows.
+pub fn outbox() -> (Outbox, queue::QueueReceiver<Message>) {
+    queue::channel(OUTBOX_CAPACITY, OverflowPolicy::Disconnect)
+}
+
+/// A request from a connection (or the console) to the tick task.
+#[derive(Debug)]
+pub enum ServerRequest {
+    /// A client completed its [`Hello`]; admit it or say why not.
+    Join {
+        endpoint_id: EndpointId,
+        hello: Hello,
+        outbox: Outbox,
+        reply: oneshot::Sender<Result<(Welcome, u64), RejectReason>>,
+    },
+    /// The connection of `session` closed.
+    Leave {
+        endpoint_id: EndpointId,
+        session: u64,
+    },
+    /// An action `bytes` long arrived; it is handled on the next tick.
+    Action {
+        endpoint_id: EndpointId,
+        action: GameAction,
+        bytes: usize,
+    },
+    /// A message from the client could not be read.
+    Unreadable {
+        endpoint_id: EndpointId,
+    },
+    /// Messages of these sizes were written to the client.
+    Sent {
+        endpoint_id: EndpointId,
+        sizes: Vec<usize>,
+    },
+    /// A line typed on the server console.
+    Command {
+        line: String,
+        reply: oneshot::Sender<Result<String, CommandError>>,
+    },
+    Status(oneshot::Sender<ServerStatus>),
+}
+
+/// A summary of the server for listings such as LAN discovery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerStatus {
+    pub world_name: String,
+    /// Name of the hosting player, if they are connected.
+    pub host_name: Option<String>,
+    pub player_count: u32,
+}
+
+/// The tick task stopped, so the server is shutting down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerGone;
+
+impl std::fmt::Display for ServerGone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the server is shutting down")
+    }
+}
+
+impl std::error::Error for ServerGone {}
+
+impl<T> From<mpsc::error::SendError<T>> for ServerGone {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        Self
+    }
+}
+
+impl From<oneshot::error::RecvError> for ServerGone {
+    fn from(_: oneshot::error::RecvError) -> Self {
+        Self
+    }
+}
+
+/// Cheap, cloneable way to reach the tick task.
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    requests: mpsc::Sender<ServerRequest>,
+}
+
+impl ServerHandle {
+    /// Start the tick task for `state` and return a handle to it. The task
+    /// runs until every handle has been dropped.
+    pub fn spawn(state: ServerState) -> Self {
+        let (requests, rx) = mpsc::channel(REQUEST_QUEUE_CAPACITY);
+        tokio::spawn(run_ticks(state, rx));
+        Self { requests }
+    }
+
+    /// Send a request that needs no answer.
+    async fn notify(&self, request: ServerRequest) -> Result<(), ServerGone> {
+        Ok(self.requests.send(request).await?)
+    }
+
+    async fn ask<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<T>) -> ServerRequest,
+    ) -> Result<T, ServerGone> {
+        let (reply, answer) = oneshot::channel();
+        self.requests.send(request(reply)).await?;
+        Ok(answer.await?)
+    }
+
+    /// Admit the client behind `hello`. Its updates will arrive on `outbox`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn join(
+        &self,
+        endpoint_id: EndpointId,
+        hello: Hello,
+        outbox: Outbox,
+    ) -> Result<Result<(Welcome, u64), RejectReason>, ServerGone> {
+        self.ask(|reply| ServerRequest::Join {
+            endpoint_id,
+            hello,
+            outbox,
+            reply,
+        })
+        .await
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn leave(&self, endpoint_id: EndpointId, session: u64) -> Result<(), ServerGone> {
+        self.notify(ServerRequest::Leave {
+            endpoint_id,
+            session,
+        })
+        .await
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn action(
+        &self,
+        endpoint_id: EndpointId,
+        action: GameAction,
+        bytes: usize,
+    ) -> Result<(), ServerGone> {
+        self.notify(ServerRequest::Action {
+            endpoint_id,
+            action,
+            bytes,
+        })
+        .await
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn unreadable(&self, endpoint_id: EndpointId) -> Result<(), ServerGone> {
+        self.notify(ServerRequest::Unreadable { endpoint_id }).await
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn sent(&self, endpoint_id: EndpointId, sizes: Vec<usize>) -> Result<(), ServerGone> {
+        self.notify(ServerRequest::Sent { endpoint_id, sizes })
+            .await
+    }
+
+    /// Run a console command with operator permissions.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn run_command(
+        &self,
+        line: String,
+    ) -> Result<Result<String, CommandError>, ServerGone> {
+        self.ask(|reply| ServerRequest::Command { line, reply })
+            .await
+    }
+
+    /// # Errors
+    ///
+    /// Returns [`ServerGone`] if the tick task has stopped.
+    pub async fn status(&self) -> Result<ServerStatus, ServerGone> {
+        self.ask(ServerRequest::Status).await
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tick steps
+// ---------------------------------------------------------------------------
+
+impl ServerState {
+    /// Admit a client that sent `hello`, unless its account is banned or its
+    /// name would not be accepted for a character.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RejectReason::Banned`] for banned accounts, and
+    /// [`RejectReason::InvalidName`] for unacceptable names.
+    pub fn admit(
+        &mut self,
+        endpoint_id: EndpointId,
+        hello: Hello,
+    ) -> Result<(Welcome, u64), RejectReason> {
+        if self.game.is_banned(&account_of(&endpoint_id)) {
+            return Err(RejectReason::Banned);
+        }
+        let config = &self.validator.config;
+        if !config.accepts_name(&hello.client_name) {
+            return Err(RejectReason::InvalidName {
+                max: config.max_name_length as u32,
+            });
+        }
+        let welcome = Welcome {
+            protocol_version: PROTOCOL_VERSION,
+            world_name: self.game.world_name.clone(),
+            player_count: self.player_count(),
+        };
+        let session = if hello.spectator {
+            self.spectate(endpoint_id, hello.client_name)
+        } else {
+            self.join(endpoint_id, hello.client_name)
+        };
+        Ok((welcome, session))
+    }
+
+    /// Run one tick: handle `actions`, then apply everything queued.
+    ///
+    /// Actions are handled one client at a time, ordered by endpoint, each
+    /// client's in the order they arrived. The outcome therefore does not
+    /// depend on how the clients' messages interleaved on the way in.
+    pub fn step(&mut self, mut actions: Vec<(EndpointId, GameAction)>) {
+        actions.sort_by_key(|(endpoint_id, _)| *endpoint_id);
+        for (endpoint_id, action) in actions {
+            self.handle_action(endpoint_id, action);
+        }
+        self.process_events();
+    }
+
+    /// Queue a [`ServerMessage::Stats`] report for every client.
+    pub fn report_stats(&mut self) {
+        let clients: Vec<EndpointId> = self.clients.keys().copied().collect();
+        for endpoint_id in clients {
+            let stats = self.server_stats(endpoint_id);
+            self.send_to(endpoint_id, ServerMessage::Stats(stats));
+        }
+    }
+
+    /// Take every client's queued messages, followed by the current world as
+    /// that client's character sees it.
+    pub fn take_updates(&mut self) -> Vec<(EndpointId, Vec<Message>)> {
+        let clients: Vec<EndpointId> = self.clients.keys().copied().collect();
+        clients
+            .into_iter()
+            .map(|endpoint_id| {
+                let mut batch: Vec<Message> = self
+                    .unique_server_messages
+                    .remove(&endpoint_id)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Message::Server)
+                    .collect();
+                let viewer = self.endpoints.get(&endpoint_id).copied();
+                batch.push(Message::Server(ServerMessage::EntityMap(Box::new(
+                    item::view_for(&self.game.entities, viewer),
+                ))));
+                (endpoint_id, batch)
+            })
+            .collect()
+    }
+
+    pub fn status(&self) -> ServerStatus {
+        ServerStatus {
+            world_name: self.game.world_name.clone(),
+            host_name: self
+                .host
+                .and_then(|host| self.clients.get(&host))
+                .map(|c: &ClientInfo| c.name.clone()),
+            player_count: self.player_count(),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Task
+// ---------------------------------------------------------------------------
+
+/// The tick task: owns `state` and serves `requests` until every
+/// [`ServerHandle`] is gone.
+async fn run_ticks(mut state: ServerState, mut requests: mpsc::Receiver<ServerRequest>) {
+    let mut interval = tokio::time::interval(TICK_INTERVAL);
+    let mut outboxes: FxHashMap<EndpointId, Outbox> = FxHashMap::default();
+    let mut pending = Vec::new();
+    let mut last_report = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                state.step(std::mem::take(&mut pending));
+                if last_report.elapsed() >= STATS_INTERVAL {
+                    last_report = Instant::now();
+                    state.report_stats();
+                }
+                fan_out(&mut state, &mut outboxes);
+            }
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    return;
+                };
+                serve(&mut state, &mut outboxes, &mut pending, request);
+            }
+        }
+    }
+}
+
+/// Queue each client's updates in its outbox. A client whose outbox
+/// overflows is dropped; its writer notices and hangs up on it.
+fn fan_out(state: &mut ServerState, outboxes: &mut FxHashMap<EndpointId, Outbox>) {
+    for (endpoint_id, batch) in state.take_updates() {
+        let Some(outbox) = outboxes.get(&endpoint_id) else {
+            continue;
+        };
+        let delivered = batch.into_iter().try_for_each(|msg| outbox.send(msg));
+        if let Some(stats) = state.stats.get_mut(&endpoint_id) {
+            stats.skipped_updates = outbox.metrics().superseded;
+        }
+        if delivered.is_err() {
+            outboxes.remove(&endpoint_id);
+        }
+    }
+}
+
+/// Answer one request between ticks.
+fn serve(
+    state: &mut ServerState,
+    outboxes: &mut FxHashMap<EndpointId, Outbox>,
+    pending: &mut Vec<(EndpointId, GameAction)>,
+    request: ServerRequest,
+) {
+    match request {
+        ServerRequest::Join {
+            endpoint_id,
+            hello,
+            outbox,
+            reply,
+        } => {
+            let verdict = state.admit(endpoint_id, hello);
+            let session = verdict.as_ref().ok().map(|&(_, session)| session);
+            if reply.send(verdict).is_err() {
+                // The connection stopped waiting, so it never learns it was let in
+                if let Some(session) = session {
+                    state.leave(endpoint_id, session);
+                }
+            } else if session.is_some() {
+                // Replacing the old outbox ends the old connection's writer
+                outboxes.insert(endpoint_id, outbox);
+            }
+        }
+        ServerRequest::Leave {
+            endpoint_id,
+            session,
+        } => {
+            state.leave(endpoint_id, session);
+            if !state.clients.contains_key(&endpoint_id) {
+                outboxes.remove(&endpoint_id);
+            }
+        }
+        ServerRequest::Action {
+            endpoint_id,
+            action,
+            bytes,
+        } => {
+            if let Some(stats) = state.stats.get_mut(&endpoint_id) {
+                stats.received(bytes, Instant::now());
+            }
+            // Anything past the budget would be rejected next tick anyway
+            let queued = pending.iter().filter(|(id, _)| *id == endpoint_id).count();
+            if queued >= state.validator.config.actions_per_tick as usize {
+                state.reject(endpoint_id, action, ActionError::RateLimited);
+            } else {
+                pending.push((endpoint_id, action));
+            }
+        }
+        ServerRequest::Unreadable { endpoint_id } => {
+            if let Some(stats) = state.stats.get_mut(&endpoint_id) {
+                stats.dropped_messages += 1;
+            }
+        }
+        ServerRequest::Sent { endpoint_id, sizes } => {
+            if let Some(stats) = state.stats.get_mut(&endpoint_id) {
+                let now = Instant::now();
+                for bytes in sizes {
+                    stats.sent(bytes, now);
+                }
+            }
+        }
+        ServerRequest::Command { line, reply } => {
+            if reply.send(state.run_command(None, &line)).is_err() {
+                log::warn!("The console stopped waiting for the outcome of {line:?}");
+            }
+        }
+        ServerRequest::Status(reply) => {
+            if reply.send(state.status()).is_err() {
+                log::debug!("A status request was given up on");
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{self, Direction, GameState};
+    use crate::testing::endpoint;
+
+    fn hello(name: &str) -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: name.into(),
+            spectator: false,
+        }
+    }
+
+    #[test]
+    fn admit_welcomes_players_and_turns_away_banned_accounts() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        let mallory = endpoint(2);
+        game::set_banned(&mut server.game, account_of(&mallory), true);
+
+        let (welcome, _) = server
+            .admit(alice, hello("Alice"))
+            .expect("Alice is welcome");
+        assert_eq!(welcome.world_name, "test");
+        assert_eq!(welcome.player_count, 0);
+        assert_eq!(server.player_count(), 1);
+
+        assert_eq!(
+            server.admit(mallory, hello("Mallory")),
+            Err(RejectReason::Banned)
+        );
+        assert_eq!(server.player_count(), 1);
+    }
+
+    #[test]
+    fn admit_turns_away_names_no_character_could_have() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let max = server.validator.config.max_name_length;
+        let too_long = "a".repeat(max + 1);
+
+        for name in ["  ", "Mal\u{7}lory", too_long.as_str()] {
+            assert_eq!(
+                server.admit(endpoint(1), hello(name)),
+                Err(RejectReason::InvalidName { max: max as u32 })
+            );
+        }
+        assert_eq!(server.player_count(), 0);
+    }
+
+    #[test]
+    fn step_does_not_depend_on_arrival_order() {
+        let run = |bob_first: bool| {
+            let mut server = ServerState::new(GameState::create_test_world("test".into()));
+            let alice = endpoint(1);
+            let bob = endpoint(2);
+            for (endpoint_id, name) in [(alice, "Alice"), (bob, "Bob")] {
+                server.join(endpoint_id, name.into());
+                server.spawn_player_for(endpoint_id, name.into());
+            }
+            // Alice's and Bob's moves and chat all land in the same tick
+            let mut actions = vec![
+                (alice, GameAction::Move(Direction::Right)),
+                (alice, GameAction::Say("hi".into())),
+            ];
+            let bobs = (bob, GameAction::Move(Direction::Left));
+            if bob_first {
+                actions.insert(0, bobs);
+            } else {
+                actions.insert(1, bobs);
+            }
+            server.step(actions);
+            (server.game, server.journal)
+        };
+        let (game, journal) = run(false);
+        assert_eq!(journal.len(), 3);
+        assert_eq!((game, journal), run(true));
+    }
+
+    #[test]
+    fn updates_end_with_the_world() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        server.step(Vec::new());
+
+        let updates = server.take_updates();
+        let [(endpoint_id, batch)] = updates.as_slice() else {
+            panic!("expected one client, got {updates:?}");
+        };
+        assert_eq!(*endpoint_id, alice);
+        assert!(matches!(
+            batch.first(),
+            Some(Message::Server(ServerMessage::OwnedCharacters(_)))
+        ));
+        assert!(matches!(
+            batch.last(),
+            Some(Message::Server(ServerMessage::EntityMap(_)))
+        ));
+
+        // Queued messages are only sent once
+        assert_eq!(server.take_updates()[0].1.len(), 1);
+    }
+
+    #[test]
+    fn players_only_see_their_own_possessions() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let (alice, bob) = (endpoint(1), endpoint(2));
+        for (endpoint_id, name) in [(alice, "Alice"), (bob, "Bob")] {
+            server.join(endpoint_id, name.into());
+            server.spawn_player_for(endpoint_id, name.into());
+        }
+        server.step(Vec::new());
+        let alices_things = item::possessions(&server.game.entities, server.endpoints[&alice]);
+        assert!(!alices_things.is_empty());
+
+        for (endpoint_id, batch) in server.take_updates() {
+            let Some(Message::Server(ServerMessage::EntityMap(view))) = batch.last() else {
+                panic!("updates end with the world");
+            };
+            let sees_them = alices_things.iter().all(|eid| view.contains(*eid));
+            assert_eq!(sees_them, endpoint_id == alice);
+        }
+    }
+
+    #[test]
+    fn status_names_the_connected_host() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let host = endpoint(1);
+        server.host = Some(host);
+        assert_eq!(server.status().host_name, None);
+
+        server.join(host, "Hoster".into());
+        let status = server.status();
+        assert_eq!(status.host_name.as_deref(), Some("Hoster"));
+        assert_eq!(status.player_count, 1);
+    }
+
+    #[test]
+    fn slow_clients_skip_snapshots_then_get_dropped() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        let (tx, mut rx) = queue::channel(4, OverflowPolicy::Disconnect);
+        let mut outboxes = FxHashMap::default();
+        outboxes.insert(alice, tx);
+
+        // Nobody reads Alice's outbox, but snapshots only ever replace each other
+        for _ in 0..10 {
+            server.step(Vec::new());
+            fan_out(&mut server, &mut outboxes);
+        }
+        assert!(outboxes.contains_key(&alice));
+        assert_eq!(rx.len(), 2, "her character list and one snapshot");
+        assert_eq!(server.stats[&alice].skipped_updates, 9);
+
+        // Messages that cannot be skipped eventually overflow it
+        for n in 0..4 {
+            server.send_to(alice, ServerMessage::Notice(format!("notice {n}")));
+        }
+        fan_out(&mut server, &mut outboxes);
+        assert!(!outboxes.contains_key(&alice));
+        assert!(rx.metrics().overflowed);
+        assert!(rx.try_recv().is_some());
+    }
+
+    #[test]
+    fn actions_past_the_budget_are_rejected_on_arrival() {
+        let mut server = ServerState::new(GameState::create_test_world("test".into()));
+        let alice = endpoint(1);
+        server.join(alice, "Alice".into());
+        let mut outboxes = FxHashMap::default();
+        let mut pending = Vec::new();
+
+        for _ in 0..10 {
+            let action = ServerRequest::Action {
+                endpoint_id: alice,
+                action: GameAction::Say("spam".into()),
+                bytes: 8,
+            };
+            serve(&mut server, &mut outboxes, &mut pending, action);
+        }
+        let budget = server.validator.config.actions_per_tick as usize;
+        assert_eq!(pending.len(), budget);
+        assert_eq!(server.stats[&alice].rejected_actions, (10 - budget) as u64);
+    }
+
+    #[tokio::test]
+    async fn handle_talks_to_the_tick_task() {
+        let handle = ServerHandle::spawn(ServerState::new(GameState::create_test_world(
+            "test".into(),
+        )));
+        let alice = endpoint(1);
+        let (outbox, mut updates) = outbox();
+
+        let (welcome, session) = handle
+            .join(alice, hello("Alice"), outbox)
+            .await
+            .expect("server is running")
+            .expect("Alice is welcome");
+        assert_eq!(welcome.world_name, "test");
+
+        let update = updates.recv().await.expect("a tick sends updates");
+        assert!(matches!(
+            update,
+            Message::Server(ServerMessage::OwnedCharacters(_))
+        ));
+
+        let status = handle.status().await.expect("server is running");
+        assert_eq!(status.player_count, 1);
+
+        handle
+            .leave(alice, session)
+            .await
+            .expect("server is running");
+        let status = handle.status().await.expect("server is running");
+        assert_eq!(status.player_count, 0);
+        assert_eq!(
+            handle
+                .run_command("/nonsense".into())
+                .await
+                .map(|r| r.is_err()),
+            Ok(true)
+        );
+    }
+}
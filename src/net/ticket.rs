@@ -0,0 +1,187 @@
+//! Shareable connection tickets.
+//!
+//! A [`ServerTicket`] bundles everything a client needs to join a server —
+//! the host's endpoint ID, its relay and direct addresses, and the world
+//! name — into a single string the host can copy and send to friends.
+
+use bitcode::{Decode, Encode};
+use iroh::{EndpointAddr, EndpointId, RelayUrl};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+/// Prefix identifying a gamik ticket string.
+const PREFIX: &str = "gamik:";
+
+/// Everything needed to join a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerTicket {
+    pub addr: EndpointAddr,
+    pub world_name: String,
+}
+
+/// On-the-wire form of a [`ServerTicket`], using only plain types.
+#[derive(Encode, Decode)]
+struct WireTicket {
+    endpoint_id: [u8; 32],
+    relay_urls: Vec<String>,
+    direct_addrs: Vec<String>,
+    world_name: String,
+}
+
+/// Why a string could not be parsed as a [`ServerTicket`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicketError {
+    /// The string does not start with `gamik:`.
+    MissingPrefix,
+    /// The payload is not valid hex or does not decode.
+    Malformed,
+    /// The endpoint ID is not a valid public key.
+    InvalidEndpointId,
+    /// A relay URL or direct address could not be parsed.
+    InvalidAddress(String),
+}
+
+impl fmt::Display for TicketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPrefix => write!(f, "Tickets start with \"{PREFIX}\"."),
+            Self::Malformed => write!(f, "The ticket is damaged or incomplete."),
+            Self::InvalidEndpointId => write!(f, "The ticket contains an invalid server ID."),
+            Self::InvalidAddress(addr) => write!(f, "The ticket contains a bad address: {addr}"),
+        }
+    }
+}
+
+impl std::error::Error for TicketError {}
+
+impl ServerTicket {
+    pub fn new(addr: EndpointAddr, world_name: String) -> Self {
+        Self { addr, world_name }
+    }
+}
+
+impl fmt::Display for ServerTicket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let wire = WireTicket {
+            endpoint_id: *self.addr.id.as_bytes(),
+            relay_urls: self.addr.relay_urls().map(ToString::to_string).collect(),
+            direct_addrs: self.addr.ip_addrs().map(ToString::to_string).collect(),
+            world_name: self.world_name.clone(),
+        };
+        write!(f, "{PREFIX}{}", encode_hex(&bitcode::encode(&wire)))
+    }
+}
+
+impl FromStr for ServerTicket {
+    type Err = TicketError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let payload = s
+            .trim()
+            .strip_prefix(PREFIX)
+            .ok_or(TicketError::MissingPrefix)?;
+        let bytes = decode_hex(payload).ok_or(TicketError::Malformed)?;
+        let Ok(wire) = bitcode::decode::<WireTicket>(&bytes) else {
+            return Err(TicketError::Malformed);
+        };
+        let Ok(id) = EndpointId::from_bytes(&wire.endpoint_id) else {
+            return Err(TicketError::InvalidEndpointId);
+        };
+
+        let mut addr = EndpointAddr::new(id);
+        for url in wire.relay_urls {
+            let Ok(relay) = url.parse::<RelayUrl>() else {
+                return Err(TicketError::InvalidAddress(url));
+            };
+            addr = addr.with_relay_url(relay);
+        }
+        for direct in wire.direct_addrs {
+            let Ok(ip) = direct.parse::<SocketAddr>() else {
+                return Err(TicketError::InvalidAddress(direct));
+            };
+            addr = addr.with_ip_addr(ip);
+        }
+
+        Ok(Self {
+            addr,
+            world_name: wire.world_name,
+        })
+    }
+}
+
+/// Encode bytes as lowercase hex.
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decode a hex string written by [`encode_hex`].
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use iroh::SecretKey;
+
+    fn sample_ticket() -> ServerTicket {
+        let id = SecretKey::from_bytes(&[7; 32]).public();
+        let addr = EndpointAddr::new(id)
+            .with_relay_url("https://relay.example.com".parse().expect("valid url"))
+            .with_ip_addr("192.168.1.20:4433".parse().expect("valid socket addr"));
+        ServerTicket::new(addr, "Shire".into())
+    }
+
+    #[test]
+    fn ticket_round_trips() {
+        let ticket = sample_ticket();
+        let parsed: ServerTicket = ticket.to_string().parse().expect("ticket should parse");
+        assert_eq!(parsed, ticket);
+    }
+
+    #[test]
+    fn ticket_tolerates_surrounding_whitespace() {
+        let ticket = sample_ticket();
+        let parsed: ServerTicket = format!("  {ticket}\n")
+            .parse()
+            .expect("ticket should parse");
+        assert_eq!(parsed, ticket);
+    }
+
+    #[test]
+    fn ticket_without_prefix_is_rejected() {
+        assert_eq!(
+            "deadbeef".parse::<ServerTicket>(),
+            Err(TicketError::MissingPrefix)
+        );
+    }
+
+    #[test]
+    fn truncated_ticket_is_rejected() {
+        let text = sample_ticket().to_string();
+        let truncated = text.get(..text.len() - 10).expect("ticket is long enough");
+        assert_eq!(
+            truncated.parse::<ServerTicket>(),
+            Err(TicketError::Malformed)
+        );
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(decode_hex(&encode_hex(&bytes)), Some(bytes.to_vec()));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
@@ -0,0 +1,474 @@
+//! Server-side validation of client actions.
+//!
+//! Every [`GameAction`] a client sends passes through a [`Validator`] before
+//! it reaches [`ServerState::event_queue`](super::ServerState::event_queue).
+//! The validator enforces per-endpoint action budgets for each tick, the
+//! permission each action needs, and how many characters an account may own.
+
+use super::ActionError;
+use crate::game::anatomy::Modification;
+use crate::game::{GameAction, PermissionLevel};
+
+use iroh::EndpointId;
+use rustc_hash::FxHashMap;
+
+/// Limits applied by the [`Validator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidationConfig {
+    /// Moves, turns and attacks an endpoint may make per tick; more moves
+    /// would let it teleport, more turns spin round at once, and more attacks
+    /// strike faster than it walks.
+    pub moves_per_tick: u32,
+    /// Actions of any kind an endpoint may send per tick.
+    pub actions_per_tick: u32,
+    /// Characters a single account may own.
+    pub max_characters_per_account: usize,
+    /// Ticks that must pass between two world saves.
+    pub save_cooldown_ticks: u64,
+    /// Longest chat message, or description of a tattoo or piercing, in
+    /// characters.
+    pub max_chat_length: usize,
+    /// Longest character name, in characters; names are shown to everyone
+    /// in every snapshot.
+    pub max_name_length: usize,
+}
+
+impl ValidationConfig {
+    /// Whether `name` may be used for a character or a client: not blank,
+    /// no longer than [`Self::max_name_length`], and free of control
+    /// characters.
+    pub fn accepts_name(&self, name: &str) -> bool {
+        !name.trim().is_empty()
+            && name.chars().count() <= self.max_name_length
+            && !name.chars().any(char::is_control)
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            moves_per_tick: 1,
+            actions_per_tick: 4,
+            max_characters_per_account: 5,
+            save_cooldown_ticks: 100,
+            max_chat_length: 256,
+            max_name_length: 32,
+        }
+    }
+}
+
+/// Actions an endpoint has used up in the current tick.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Budget {
+    moves: u32,
+    actions: u32,
+}
+
+/// Tracks per-endpoint budgets and decides whether actions are allowed.
+#[derive(Debug, Default)]
+pub struct Validator {
+    pub config: ValidationConfig,
+    tick: u64,
+    budgets: FxHashMap<EndpointId, Budget>,
+    last_save: Option<u64>,
+}
+
+impl Validator {
+    pub fn new(config: ValidationConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Start a new tick, refreshing every endpoint's budget.
+    pub fn begin_tick(&mut self, tick: u64) {
+        self.tick = tick;
+        self.budgets.clear();
+    }
+
+    /// Forget everything about an endpoint that disconnected.
+    pub fn forget(&mut self, endpoint_id: &EndpointId) {
+        self.budgets.remove(endpoint_id);
+    }
+
+    /// Check whether `endpoint_id` may perform `action` right now, charging
+    /// it against the endpoint's budget if so.
+    ///
+    /// `owned_characters` is how many characters the endpoint's account
+    /// already has.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the action must be rejected.
+    pub fn check(
+        &mut self,
+        endpoint_id: EndpointId,
+        action: &GameAction,
+        permission: PermissionLevel,
+        owned_characters: usize,
+    ) -> Result<(), ActionError> {
+        if permission < required_permission(action) {
+            return Err(ActionError::NotPermitted);
+        }
+
+        let budget = self.budgets.entry(endpoint_id).or_default();
+        if budget.actions >= self.config.actions_per_tick {
+            return Err(ActionError::RateLimited);
+        }
+
+        match action {
+            GameAction::Move(_)
+            | GameAction::MoveDiagonal(_)
+            | GameAction::Turn(_)
+            | GameAction::Walk { .. }
+            | GameAction::Attack(_)
+            | GameAction::AttackPart { .. }
+            | GameAction::Fire { .. } => {
+                if budget.moves >= self.config.moves_per_tick {
+                    return Err(ActionError::RateLimited);
+                }
+                budget.moves += 1;
+            }
+            GameAction::SpawnPlayer(name) => {
+                if !self.config.accepts_name(name) {
+                    return Err(ActionError::InvalidName {
+                        max: self.config.max_name_length as u32,
+                    });
+                }
+                if owned_characters >= self.config.max_characters_per_account {
+                    return Err(ActionError::TooManyCharacters {
+                        max: self.config.max_characters_per_account as u32,
+                    });
+                }
+            }
+            GameAction::SaveWorld => {
+                if self
+                    .last_save
+                    .is_some_and(|last| self.tick < last + self.config.save_cooldown_ticks)
+                {
+                    return Err(ActionError::RateLimited);
+                }
+                self.last_save = Some(self.tick);
+            }
+            GameAction::Say(text)
+            | GameAction::Whisper { text, .. }
+            | GameAction::Emote(text)
+            | GameAction::SayGlobal(text)
+            | GameAction::Command(text)
+            | GameAction::Modify {
+                modification:
+                    Modification {
+                        description: text, ..
+                    },
+                ..
+            } => {
+                let len = text.chars().count();
+                if text.trim().is_empty() || len > self.config.max_chat_length {
+                    return Err(ActionError::InvalidMessage {
+                        max: self.config.max_chat_length as u32,
+                    });
+                }
+            }
+            GameAction::SpawnAs(_)
+            | GameAction::PickUp(_)
+            | GameAction::Drop(_)
+            | GameAction::Equip(_)
+            | GameAction::Unequip(_)
+            | GameAction::Store { .. }
+            | GameAction::Organize
+            | GameAction::SpawnTemplate { .. }
+            | GameAction::HandoffReady(_)
+            | GameAction::HandoffDeclined => {}
+        }
+
+        budget.actions += 1;
+        Ok(())
+    }
+}
+
+/// The permission level needed to perform `action`.
+pub fn required_permission(action: &GameAction) -> PermissionLevel {
+    match action {
+        GameAction::SaveWorld | GameAction::SpawnTemplate { .. } => PermissionLevel::Operator,
+        GameAction::Move(_)
+        | GameAction::MoveDiagonal(_)
+        | GameAction::Turn(_)
+        | GameAction::Walk { .. }
+        | GameAction::Attack(_)
+        | GameAction::AttackPart { .. }
+        | GameAction::Fire { .. }
+        | GameAction::Modify { .. }
+        | GameAction::SpawnPlayer(_)
+        | GameAction::SpawnAs(_)
+        | GameAction::HandoffReady(_)
+        | GameAction::HandoffDeclined
+        | GameAction::PickUp(_)
+        | GameAction::Drop(_)
+        | GameAction::Equip(_)
+        | GameAction::Unequip(_)
+        | GameAction::Store { .. }
+        | GameAction::Organize
+        | GameAction::Say(_)
+        | GameAction::Whisper { .. }
+        | GameAction::Emote(_)
+        | GameAction::SayGlobal(_)
+        // Each command checks its own permission when it runs
+        | GameAction::Command(_) => PermissionLevel::Player,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Direction, Turn};
+    use crate::testing::endpoint;
+
+    #[test]
+    fn second_move_in_a_tick_is_rate_limited() {
+        let mut validator = Validator::default();
+        let alice = endpoint(1);
+        let step = GameAction::Move(Direction::Right);
+
+        assert_eq!(
+            validator.check(alice, &step, PermissionLevel::Player, 1),
+            Ok(())
+        );
+        assert_eq!(
+            validator.check(alice, &step, PermissionLevel::Player, 1),
+            Err(ActionError::RateLimited)
+        );
+    }
+
+    #[test]
+    fn budgets_refresh_each_tick() {
+        let mut validator = Validator::default();
+        let alice = endpoint(1);
+        let step = GameAction::Move(Direction::Right);
+
+        validator
+            .check(alice, &step, PermissionLevel::Player, 1)
+            .expect("first move is allowed");
+        validator.begin_tick(1);
+
+        assert_eq!(
+            validator.check(alice, &step, PermissionLevel::Player, 1),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn attacks_share_the_move_budget() {
+        let mut validator = Validator::default();
+        let alice = endpoint(1);
+
+        assert_eq!(
+            validator.check(
+                alice,
+                &GameAction::Attack(crate::game::EntityID(7)),
+                PermissionLevel::Player,
+                1
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            validator.check(
+                alice,
+                &GameAction::Move(Direction::Right),
+                PermissionLevel::Player,
+                1
+            ),
+            Err(ActionError::RateLimited)
+        );
+    }
+
+    #[test]
+    fn turning_takes_as_long_as_a_step() {
+        let mut validator = Validator::default();
+        let alice = endpoint(1);
+        let turn = GameAction::Turn(Turn::Left);
+
+        assert_eq!(
+            validator.check(alice, &turn, PermissionLevel::Player, 1),
+            Ok(())
+        );
+        assert_eq!(
+            validator.check(alice, &turn, PermissionLevel::Player, 1),
+            Err(ActionError::RateLimited)
+        );
+    }
+
+    #[test]
+    fn budgets_are_per_endpoint() {
+        let mut validator = Validator::default();
+        let step = GameAction::Move(Direction::Right);
+
+        validator
+            .check(endpoint(1), &step, PermissionLevel::Player, 1)
+            .expect("first move is allowed");
+
+        assert_eq!(
+            validator.check(endpoint(2), &step, PermissionLevel::Player, 1),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn total_actions_per_tick_are_capped() {
+        let mut validator = Validator::default();
+        let alice = endpoint(1);
+        let spawn = GameAction::SpawnAs(crate::game::EntityID(1));
+
+        for _ in 0..validator.config.actions_per_tick {
+            validator
+                .check(alice, &spawn, PermissionLevel::Player, 1)
+                .expect("within budget");
+        }
+
+        assert_eq!(
+            validator.check(alice, &spawn, PermissionLevel::Player, 1),
+            Err(ActionError::RateLimited)
+        );
+    }
+
+    #[test]
+    fn players_cannot_save_the_world() {
+        let mut validator = Validator::default();
+
+        assert_eq!(
+            validator.check(
+                endpoint(1),
+                &GameAction::SaveWorld,
+                PermissionLevel::Player,
+                0
+            ),
+            Err(ActionError::NotPermitted)
+        );
+    }
+
+    #[test]
+    fn saves_have_a_cooldown() {
+        let mut validator = Validator::default();
+        let op = endpoint(1);
+
+        validator
+            .check(op, &GameAction::SaveWorld, PermissionLevel::Operator, 0)
+            .expect("first save is allowed");
+        validator.begin_tick(1);
+        assert_eq!(
+            validator.check(op, &GameAction::SaveWorld, PermissionLevel::Operator, 0),
+            Err(ActionError::RateLimited)
+        );
+
+        validator.begin_tick(validator.config.save_cooldown_ticks);
+        assert_eq!(
+            validator.check(op, &GameAction::SaveWorld, PermissionLevel::Operator, 0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn empty_and_oversized_chat_is_rejected() {
+        let mut validator = Validator::default();
+        let max = validator.config.max_chat_length;
+        let too_long = "a".repeat(max + 1);
+        let max = max as u32;
+
+        assert_eq!(
+            validator.check(
+                endpoint(1),
+                &GameAction::Say("   ".into()),
+                PermissionLevel::Player,
+                1
+            ),
+            Err(ActionError::InvalidMessage { max })
+        );
+        assert_eq!(
+            validator.check(
+                endpoint(1),
+                &GameAction::SayGlobal(too_long),
+                PermissionLevel::Player,
+                1
+            ),
+            Err(ActionError::InvalidMessage { max })
+        );
+    }
+
+    #[test]
+    fn tattoos_need_a_sensible_description() {
+        let mut validator = Validator::default();
+        let tattoo = |description: String| GameAction::Modify {
+            target: crate::game::EntityID(7),
+            part: 0,
+            modification: Modification {
+                kind: crate::game::anatomy::ModificationKind::Tattoo,
+                description,
+            },
+        };
+        let max = validator.config.max_chat_length;
+        let too_long = "a".repeat(max + 1);
+        let max = max as u32;
+
+        for description in [String::new(), too_long] {
+            assert_eq!(
+                validator.check(
+                    endpoint(1),
+                    &tattoo(description),
+                    PermissionLevel::Player,
+                    1
+                ),
+                Err(ActionError::InvalidMessage { max })
+            );
+        }
+        assert_eq!(
+            validator.check(
+                endpoint(1),
+                &tattoo("a rose".into()),
+                PermissionLevel::Player,
+                1
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn character_names_are_checked() {
+        let mut validator = Validator::default();
+        let max = validator.config.max_name_length;
+        let spawn = |name: String| GameAction::SpawnPlayer(name);
+
+        for name in [
+            String::new(),
+            "  ".into(),
+            "a".repeat(max + 1),
+            "Eve\n".into(),
+        ] {
+            assert_eq!(
+                validator.check(endpoint(1), &spawn(name), PermissionLevel::Player, 0),
+                Err(ActionError::InvalidName { max: max as u32 })
+            );
+        }
+        for name in ["Eve".into(), "Ève the Tall".into(), "a".repeat(max)] {
+            assert_eq!(
+                validator.check(endpoint(2), &spawn(name), PermissionLevel::Player, 0),
+                Ok(())
+            );
+        }
+    }
+
+    #[test]
+    fn character_limit_is_enforced() {
+        let mut validator = Validator::default();
+        let max = validator.config.max_characters_per_account;
+        let spawn = GameAction::SpawnPlayer("Alice".into());
+
+        assert_eq!(
+            validator.check(endpoint(1), &spawn, PermissionLevel::Player, max),
+            Err(ActionError::TooManyCharacters { max: max as u32 })
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! How a [`Message`] is laid out on its stream.
+//!
+//! Each message is a format byte followed by its bitcode encoding. Large
+//! messages, in practice world snapshots, are deflated when that makes them
+//! smaller. Decoding enforces a size limit per direction before anything is
+//! decoded, so a small compressed frame cannot inflate into megabytes, and
+//! then one per kind of message, so a peer cannot make us hold a snapshot's
+//! worth of memory for a chat line.
+
+use super::{Message, ServerMessage};
+
+use miniz_oxide::inflate::TINFLStatus;
+use std::fmt;
+
+/// Largest message, after decompression, we accept from anybody.
+pub const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024; // 10 MB
+/// Largest message, as sent or decompressed, we read from a client. Clients
+/// only ever send their [`Hello`](super::Hello) and single actions.
+pub const MAX_CLIENT_MESSAGE_SIZE: usize = 16 * 1024;
+/// Messages whose encoding is at least this long are worth compressing.
+pub const COMPRESS_ABOVE: usize = 256;
+/// Balances speed against size; snapshots are compressed every tick.
+const COMPRESSION_LEVEL: u8 = 4;
+
+const FORMAT_RAW: u8 = 0;
+const FORMAT_DEFLATE: u8 = 1;
+
+/// Why a message could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    Empty,
+    UnknownFormat(u8),
+    /// The message is larger than its kind allows.
+    TooLarge {
+        kind: &'static str,
+        size: usize,
+        limit: usize,
+    },
+    Corrupt(String),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty message"),
+            Self::UnknownFormat(format) => write!(f, "unknown message format {format}"),
+            Self::TooLarge { kind, size, limit } => {
+                write!(
+                    f,
+                    "{kind} message of {size} bytes exceeds its {limit} byte limit"
+                )
+            }
+            Self::Corrupt(e) => write!(f, "corrupt message: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+/// Encode `msg` for sending, compressed if that pays off.
+pub fn encode(msg: &Message) -> Vec<u8> {
+    let encoded = bitcode::encode(msg);
+    if encoded.len() >= COMPRESS_ABOVE {
+        let compressed = miniz_oxide::deflate::compress_to_vec(&encoded, COMPRESSION_LEVEL);
+        if compressed.len() < encoded.len() {
+            return framed(FORMAT_DEFLATE, &compressed);
+        }
+    }
+    framed(FORMAT_RAW, &encoded)
+}
+
+fn framed(format: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(body.len() + 1);
+    bytes.push(format);
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Decode a message produced by [`encode`] whose encoding, decompressed, is
+/// at most `limit` bytes.
+///
+/// # Errors
+///
+/// Returns a [`WireError`] if the bytes are not a valid message, or the
+/// message is larger than `limit` or than [`size_limit`] allows for its kind.
+/// Messages over `limit` are refused before they are decoded.
+pub fn decode(bytes: &[u8], limit: usize) -> Result<Message, WireError> {
+    let (&format, body) = bytes.split_first().ok_or(WireError::Empty)?;
+    let inflated;
+    let encoded = match format {
+        FORMAT_RAW => body,
+        FORMAT_DEFLATE => {
+            inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(body, limit).map_err(
+                |e| match e.status {
+                    TINFLStatus::HasMoreOutput => WireError::TooLarge {
+                        kind: "compressed",
+                        size: e.output.len(),
+                        limit,
+                    },
+                    _ => WireError::Corrupt(e.to_string()),
+                },
+            )?;
+            &inflated
+        }
+        other => return Err(WireError::UnknownFormat(other)),
+    };
+    if encoded.len() > limit {
+        return Err(WireError::TooLarge {
+            kind: "raw",
+            size: encoded.len(),
+            limit,
+        });
+    }
+
+    let msg: Message = bitcode::decode(encoded).map_err(|e| WireError::Corrupt(e.to_string()))?;
+    let (kind, limit) = size_limit(&msg);
+    if encoded.len() > limit {
+        return Err(WireError::TooLarge {
+            kind,
+            size: encoded.len(),
+            limit,
+        });
+    }
+    Ok(msg)
+}
+
+/// The kind of `msg` and the largest uncompressed encoding allowed for it.
+pub fn size_limit(msg: &Message) -> (&'static str, usize) {
+    const KIB: usize = 1024;
+    match msg {
+        Message::Hello(_) => ("hello", KIB),
+        Message::Client(_) => ("action", 4 * KIB),
+        Message::Server(ServerMessage::EntityMap(_)) => ("snapshot", MAX_MESSAGE_SIZE),
+        Message::Server(ServerMessage::Handoff(_)) => ("world", MAX_MESSAGE_SIZE),
+        Message::Server(ServerMessage::OwnedCharacters(_)) => ("character list", 64 * KIB),
+        Message::Server(_) => ("server", 16 * KIB),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{self, GameAction, GameState};
+
+    fn round_trips(msg: &Message, bytes: &[u8]) -> bool {
+        match (msg, decode(bytes, MAX_MESSAGE_SIZE)) {
+            // Equal hash maps need not iterate, and so encode, in the same order
+            (
+                Message::Server(ServerMessage::EntityMap(sent)),
+                Ok(Message::Server(ServerMessage::EntityMap(received))),
+            ) => *sent == received,
+            (_, Ok(decoded)) => bitcode::encode(&decoded) == bitcode::encode(msg),
+            (_, Err(_)) => false,
+        }
+    }
+
+    /// A busy but ordinary world, as one of a full server of players in a
+    /// forest sees it.
+    fn typical_snapshot() -> Message {
+        let mut game = GameState::create_test_world("test".into());
+        let players: Vec<game::EntityID> = (0..32)
+            .map(|n| game::spawn_player(&mut game, format!("Player {n}")))
+            .collect();
+        for n in 0..500 {
+            let position = game::Point {
+                x: n % 40,
+                y: n / 40,
+            };
+            game::spawn_template(&mut game, "tree", position);
+        }
+        let view = game::item::view_for(&game.entities, players.first().copied());
+        Message::Server(ServerMessage::EntityMap(Box::new(view)))
+    }
+
+    #[test]
+    fn small_messages_are_sent_raw() {
+        let msg = Message::Client(GameAction::SaveWorld);
+        let bytes = encode(&msg);
+        assert_eq!(bytes.first(), Some(&FORMAT_RAW));
+        assert!(round_trips(&msg, &bytes));
+    }
+
+    #[test]
+    fn snapshots_are_compressed() {
+        let msg = typical_snapshot();
+        let bytes = encode(&msg);
+        assert_eq!(bytes.first(), Some(&FORMAT_DEFLATE));
+        assert!(bytes.len() < bitcode::encode(&msg).len());
+        assert!(round_trips(&msg, &bytes));
+    }
+
+    /// Fails when snapshots grow; raise the budget deliberately, not by accident.
+    #[test]
+    fn typical_snapshot_fits_its_budget() {
+        // Every character's body is sent, so that it can be inspected
+        const SNAPSHOT_BUDGET: usize = 5 * 1024 / 2;
+        let size = encode(&typical_snapshot()).len();
+        assert!(
+            size <= SNAPSHOT_BUDGET,
+            "a typical snapshot takes {size} bytes, over its {SNAPSHOT_BUDGET} byte budget"
+        );
+    }
+
+    #[test]
+    fn oversized_messages_are_refused_by_kind() {
+        let msg = Message::Client(GameAction::Say("a".repeat(8 * 1024)));
+        assert!(matches!(
+            decode(&encode(&msg), MAX_MESSAGE_SIZE),
+            Err(WireError::TooLarge { kind: "action", .. })
+        ));
+    }
+
+    #[test]
+    fn garbage_is_refused() {
+        assert!(matches!(
+            decode(&[], MAX_MESSAGE_SIZE),
+            Err(WireError::Empty)
+        ));
+        assert!(matches!(
+            decode(&[9, 1, 2], MAX_MESSAGE_SIZE),
+            Err(WireError::UnknownFormat(9))
+        ));
+        assert!(matches!(
+            decode(&[FORMAT_DEFLATE, 1, 2, 3], MAX_MESSAGE_SIZE),
+            Err(WireError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn compressed_bombs_are_refused_before_decoding() {
+        let msg = Message::Client(GameAction::Say("a".repeat(4 * 1024 * 1024)));
+        let bytes = encode(&msg);
+        assert!(bytes.len() < MAX_CLIENT_MESSAGE_SIZE);
+
+        assert_eq!(
+            decode(&bytes, MAX_CLIENT_MESSAGE_SIZE).map(|_| ()),
+            Err(WireError::TooLarge {
+                kind: "compressed",
+                size: MAX_CLIENT_MESSAGE_SIZE,
+                limit: MAX_CLIENT_MESSAGE_SIZE,
+            })
+        );
+    }
+
+    #[test]
+    fn moves_keep_their_protocol_12_encoding() {
+        use game::{Diagonal, Direction};
+        // `encode(&Message::Client(GameAction::Move(..)))` as sent by clients
+        // built at protocol version 12, before diagonal steps existed. Those
+        // clients are turned away at the handshake, as they cannot decode
+        // what came since; this only pins down how moves are encoded
+        let captured = [
+            ([0, 1, 0, 0], Direction::Up),
+            ([0, 1, 0, 1], Direction::Down),
+            ([0, 1, 0, 2], Direction::Left),
+            ([0, 1, 0, 3], Direction::Right),
+        ];
+        for (bytes, direction) in captured {
+            assert!(matches!(
+                decode(&bytes, MAX_CLIENT_MESSAGE_SIZE),
+                Ok(Message::Client(GameAction::Move(d))) if d == direction
+            ));
+            assert_eq!(encode(&Message::Client(GameAction::Move(direction))), bytes);
+        }
+        let diagonal = Message::Client(GameAction::MoveDiagonal(Diagonal::UpLeft));
+        assert!(round_trips(&diagonal, &encode(&diagonal)));
+    }
+}
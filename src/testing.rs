@@ -0,0 +1,32 @@
+//! Fixtures shared by the unit tests.
+
+use crate::game::{
+    AccountMap, EntityGenerator, EntityID, EntityMap, GameRng, GameState, Templates, spawn_player,
+};
+
+use iroh::{EndpointId, SecretKey};
+
+/// The endpoint of a client whose key is `seed` repeated, so that the same
+/// seed always stands for the same client.
+pub fn endpoint(seed: u8) -> EndpointId {
+    SecretKey::from_bytes(&[seed; 32]).public()
+}
+
+/// A world with the built-in templates and nothing in it.
+pub fn empty_state() -> GameState {
+    GameState {
+        entity_gen: EntityGenerator::default(),
+        entities: EntityMap::default(),
+        accounts: AccountMap::default(),
+        templates: Templates::builtin(),
+        world_name: "test".into(),
+        rng: GameRng::new(0),
+    }
+}
+
+/// The test world with Alice, a freshly spawned character, in it.
+pub fn state_with_player() -> (GameState, EntityID) {
+    let mut state = GameState::create_test_world("test".into());
+    let player = spawn_player(&mut state, "Alice".into());
+    (state, player)
+}
@@ -4,51 +4,206 @@
 //! It reads [`GameState`](crate::game::GameState) and produces visual output —
 //! no game logic lives here.
 
-use crate::game::{Entity, EntityType, Point};
+use crate::game::component::{Renderable, Rgb};
+use crate::game::{EntityID, EntityMap, Point};
 use egui::Color32;
 use rustc_hash::FxHashMap;
 
 /// Visual representation of a single grid cell.
 pub struct Glyph {
-    pub character: &'static str,
+    pub character: char,
     pub fg_color: Color32,
     pub bg_color: Color32,
     pub size_mod: f32,
 }
 
-/// Pre-computed spatial index mapping positions to entities.
-pub type SpatialIndex<'a> = FxHashMap<Point, &'a Entity>;
+/// How a tile the player cannot see is drawn.
+pub const UNSEEN: Glyph = Glyph {
+    character: ' ',
+    fg_color: Color32::BLACK,
+    bg_color: Color32::BLACK,
+    size_mod: 2.0,
+};
+
+/// Pre-computed spatial index mapping positions to the entity drawn there.
+pub type SpatialIndex = FxHashMap<Point, EntityID>;
 
 /// Build a spatial index from the entity map for O(1) lookups per cell.
-pub fn build_spatial_index(entities: &crate::game::EntityMap) -> SpatialIndex<'_> {
-    entities
-        .values()
-        .map(|e| (e.position, e))
-        .collect()
+///
+/// Where several entities share a tile, the one on the highest
+/// [`layer`](crate::game::component::Renderable::layer) wins; anything in
+/// flight is drawn over everything else.
+pub fn build_spatial_index(entities: &EntityMap) -> SpatialIndex {
+    let layer = |eid: &EntityID, renderable: &Renderable| {
+        if entities.projectile.contains_key(eid) {
+            Renderable::PROJECTILE
+        } else {
+            renderable.layer
+        }
+    };
+    let mut index = SpatialIndex::default();
+    #[expect(
+        clippy::iter_over_hash_type,
+        reason = "ties are broken by ID, so the order does not matter"
+    )]
+    for (eid, renderable) in &entities.renderable {
+        let Some(position) = entities.position().get(eid) else {
+            continue;
+        };
+        let replaces = index.get(position).is_none_or(|shown| {
+            entities
+                .renderable
+                .get(shown)
+                .is_none_or(|r| (layer(shown, r), shown.0) < (layer(eid, renderable), eid.0))
+        });
+        if replaces {
+            index.insert(*position, *eid);
+        }
+    }
+    index
+}
+
+const fn color(rgb: Rgb) -> Color32 {
+    Color32::from_rgb(rgb.0, rgb.1, rgb.2)
 }
 
 /// Return the visual representation of whatever occupies `point` in the world.
-pub fn glyph_at(index: &SpatialIndex<'_>, point: &Point) -> Glyph {
-    if let Some(entity) = index.get(point) {
-        return match entity.entity_type {
-            EntityType::Player => Glyph {
-                character: "@",
-                fg_color: Color32::WHITE,
-                bg_color: Color32::BLACK,
-                size_mod: 1.0,
-            },
-            EntityType::Tree => Glyph {
-                character: "木",
-                fg_color: Color32::DARK_GREEN,
-                bg_color: Color32::BLACK,
-                size_mod: 1.0,
-            },
+pub fn glyph_at(entities: &EntityMap, index: &SpatialIndex, point: &Point) -> Glyph {
+    let shown = index
+        .get(point)
+        .and_then(|eid| Some((*eid, entities.renderable.get(eid)?)));
+    if let Some((eid, renderable)) = shown {
+        let fg = if entities.is_dormant(eid) {
+            Rgb::DARK_GRAY
+        } else {
+            renderable.fg
+        };
+        // Items lying about are drawn at half size, those in flight in full
+        let lying = entities.is_item(eid) && !entities.projectile.contains_key(&eid);
+        let item_scale = if lying { 2.0 } else { 1.0 };
+        return Glyph {
+            character: renderable.glyph,
+            fg_color: color(fg),
+            bg_color: color(renderable.bg),
+            size_mod: item_scale * 100.0 / f32::from(renderable.size_percent.max(1)),
         };
     }
     Glyph {
-        character: ".",
+        character: '.',
         fg_color: Color32::WHITE,
         bg_color: Color32::BLACK,
         size_mod: 2.0,
     }
 }
+
+/// What the map view is centred on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Camera {
+    /// Keep an entity in the middle of the screen.
+    Follow(EntityID),
+    /// Stay on a fixed point until panned.
+    Free(Point),
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::Free(Point { x: 0, y: 0 })
+    }
+}
+
+impl Camera {
+    /// The world point at the centre of the view. A followed entity that has
+    /// disappeared leaves the camera at the origin.
+    pub fn center(&self, entities: &EntityMap) -> Point {
+        match self {
+            Self::Follow(eid) => entities
+                .position()
+                .get(eid)
+                .copied()
+                .unwrap_or(Point { x: 0, y: 0 }),
+            Self::Free(point) => *point,
+        }
+    }
+
+    /// Move the view by `(dx, dy)` tiles, letting go of any followed entity.
+    pub fn pan(&mut self, entities: &EntityMap, dx: i32, dy: i32) {
+        let center = self.center(entities);
+        *self = Self::Free(Point {
+            x: center.x.saturating_add(dx),
+            y: center.y.saturating_add(dy),
+        });
+    }
+
+    /// Follow the next player character after the current one, in ID order,
+    /// wrapping around. Does nothing if there are no players.
+    pub fn follow_next(&mut self, entities: &EntityMap) {
+        let mut players: Vec<EntityID> = entities.player.keys().copied().collect();
+        players.sort_by_key(|eid| eid.0);
+
+        let next = match self {
+            Self::Follow(current) => players
+                .iter()
+                .find(|eid| eid.0 > current.0)
+                .or_else(|| players.first()),
+            Self::Free(_) => players.first(),
+        };
+        if let Some(eid) = next {
+            *self = Self::Follow(*eid);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{self, GameState};
+
+    #[test]
+    fn follow_tracks_the_entity() {
+        let mut state = GameState::create_test_world("test".into());
+        let pid = game::spawn_player(&mut state, "Alice".into());
+        game::teleport(&mut state, pid, Point { x: 30, y: -4 });
+
+        assert_eq!(
+            Camera::Follow(pid).center(&state.entities),
+            Point { x: 30, y: -4 }
+        );
+    }
+
+    #[test]
+    fn panning_lets_go_of_the_followed_entity() {
+        let mut state = GameState::create_test_world("test".into());
+        let pid = game::spawn_player(&mut state, "Alice".into());
+        let start = state.entities.position()[&pid];
+        let mut camera = Camera::Follow(pid);
+
+        camera.pan(&state.entities, 2, -1);
+
+        assert_eq!(
+            camera,
+            Camera::Free(Point {
+                x: start.x + 2,
+                y: start.y - 1
+            })
+        );
+    }
+
+    #[test]
+    fn follow_next_cycles_through_players() {
+        let mut state = GameState::create_test_world("test".into());
+        let alice = game::spawn_player(&mut state, "Alice".into());
+        let bob = game::spawn_player(&mut state, "Bob".into());
+        let mut camera = Camera::default();
+
+        camera.follow_next(&state.entities);
+        assert_eq!(camera, Camera::Follow(alice));
+        camera.follow_next(&state.entities);
+        assert_eq!(camera, Camera::Follow(bob));
+        camera.follow_next(&state.entities);
+        assert_eq!(camera, Camera::Follow(alice));
+    }
+}